@@ -6,6 +6,12 @@ fn main() -> shadow_rs::SdResult<()> {
     let hash = get_git_hash().expect("failed to get latest git commit hash");
     println!("cargo:rustc-env=NU_COMMIT_HASH={}", hash);
 
+    // ioxversion reports the version of the Iox client it was built against
+    println!(
+        "cargo:rustc-env=IOX_CLIENT_VERSION={}",
+        iox_client_version().unwrap_or_else(|| "unknown".to_string())
+    );
+
     shadow_rs::new()
 }
 
@@ -16,3 +22,15 @@ fn get_git_hash() -> Result<String, std::io::Error> {
         .trim()
         .to_string())
 }
+
+fn iox_client_version() -> Option<String> {
+    let manifest = std::fs::read_to_string("../influxdb_iox_client/Cargo.toml").ok()?;
+
+    manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[package]")
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix("version"))
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+        .map(|version| version.trim().trim_matches('"').to_string())
+}
@@ -410,14 +410,35 @@ pub fn create_default_context() -> EngineState {
             IsAdmin,
         };
 
-        // Iox
+        // Iox, ioxdbnames first, it completes the --dbname flags of the others
+        let dbname_completer = working_set.add_decl(Box::new(Ioxdbnames));
+        set_dbname_completer(dbname_completer);
+
         bind_command! {
+            Ioxexport,
+            Ioxfieldkeys,
+            Ioxmeasurements,
             Ioxnamespace,
+            Ioxnamespacecreate,
+            Ioxnamespacedelete,
+            Ioxping,
+            Ioxschema,
             Ioxsql,
+            Ioxtables,
+            Ioxtagkeys,
+            Ioxuse,
+            Ioxversion,
             Ioxwrite,
             Ioxwritefile,
+            Ioxwritetable,
         }
 
+        #[cfg(feature = "iox-local")]
+        bind_command!(Ioxsqllocal);
+
+        #[cfg(feature = "iox-influxql")]
+        bind_command!(Ioxinfluxql);
+
         // Deprecated
         bind_command! {
             PivotDeprecated,
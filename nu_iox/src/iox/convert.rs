@@ -0,0 +1,453 @@
+use arrow::array::{
+    as_boolean_array, as_primitive_array, as_string_array, Array, ArrayRef, LargeStringArray,
+};
+use arrow::datatypes::{
+    DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, Schema,
+    TimeUnit, TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType,
+    TimestampSecondType, UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow::record_batch::RecordBatch;
+use arrow::util::display::array_value_to_string;
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use influxdb_iox_client::flight::PerformQuery;
+use nu_protocol::{ShellError, Span, Value};
+
+use super::util::block_on_iox;
+
+/// Converts the record batches of a query result into a nushell table
+///
+/// Every row becomes a Value::Record whose cells are typed from the Arrow
+/// schema, so integers, floats, booleans and timestamps survive intact.
+pub fn batches_to_value(batches: &[RecordBatch], span: Span) -> Result<Value, ShellError> {
+    let mut rows = vec![];
+
+    for batch in batches {
+        rows.extend(batch_to_values(batch, span)?);
+    }
+
+    Ok(Value::List { vals: rows, span })
+}
+
+/// Converts the rows of a single record batch into nushell records
+pub fn batch_to_values(batch: &RecordBatch, span: Span) -> Result<Vec<Value>, ShellError> {
+    let cols: Vec<String> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().to_string())
+        .collect();
+
+    (0..batch.num_rows())
+        .map(|row| {
+            let vals = batch
+                .columns()
+                .iter()
+                .map(|column| array_value_to_nu_value(column, row, span))
+                .collect::<Result<Vec<Value>, ShellError>>()?;
+
+            Ok(Value::Record {
+                cols: cols.clone(),
+                vals,
+                span,
+            })
+        })
+        .collect()
+}
+
+/// Describes the columns of a query result as a table
+///
+/// One row per column with its name, the Arrow data type and whether it
+/// may hold nulls.
+pub fn schema_to_value(schema: &Schema, span: Span) -> Value {
+    let vals = schema
+        .fields()
+        .iter()
+        .map(|field| Value::Record {
+            cols: vec![
+                "column".to_string(),
+                "arrow_type".to_string(),
+                "nullable".to_string(),
+            ],
+            vals: vec![
+                Value::String {
+                    val: field.name().to_string(),
+                    span,
+                },
+                Value::String {
+                    val: field.data_type().to_string(),
+                    span,
+                },
+                Value::Bool {
+                    val: field.is_nullable(),
+                    span,
+                },
+            ],
+            span,
+        })
+        .collect();
+
+    Value::List { vals, span }
+}
+
+/// Yields the rows of a running flight query as nushell records
+///
+/// Batches are only fetched from the server when the rows of the previous
+/// batch have been consumed, so `ioxsql --stream ... | first 10` stops
+/// reading once it has its rows. A failure part way through is yielded as a
+/// Value::Error and ends the stream.
+pub struct BatchValueStream {
+    query: PerformQuery,
+    rows: std::vec::IntoIter<Value>,
+    span: Span,
+    done: bool,
+}
+
+impl BatchValueStream {
+    pub fn new(query: PerformQuery, span: Span) -> Self {
+        Self {
+            query,
+            rows: Vec::new().into_iter(),
+            span,
+            done: false,
+        }
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>, ShellError> {
+        next_query_batch(&mut self.query, self.span)
+    }
+}
+
+/// Blocks until the next batch of a streaming query arrives, None once it ends
+pub fn next_query_batch(
+    query: &mut PerformQuery,
+    span: Span,
+) -> Result<Option<RecordBatch>, ShellError> {
+    let stream_error = |msg: String| {
+        ShellError::GenericError(
+            "Error reading Iox results".into(),
+            msg,
+            Some(span),
+            None,
+            Vec::new(),
+        )
+    };
+
+    block_on_iox(query.next())
+        .map_err(|e| stream_error(e.to_string()))?
+        .map_err(|e| stream_error(e.to_string()))
+}
+
+impl Iterator for BatchValueStream {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        loop {
+            if let Some(value) = self.rows.next() {
+                return Some(value);
+            }
+
+            if self.done {
+                return None;
+            }
+
+            let rows = match self.next_batch() {
+                Ok(Some(batch)) => batch_to_values(&batch, self.span),
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(error) => Err(error),
+            };
+
+            match rows {
+                Ok(rows) => self.rows = rows.into_iter(),
+                Err(error) => {
+                    self.done = true;
+                    return Some(Value::Error { error });
+                }
+            }
+        }
+    }
+}
+
+/// Converts a single cell of an Arrow column into a nushell value
+///
+/// Types without a natural nushell counterpart, e.g. the dictionary encoded
+/// tag columns, are rendered with Arrow's own display formatting.
+pub fn array_value_to_nu_value(
+    column: &ArrayRef,
+    row: usize,
+    span: Span,
+) -> Result<Value, ShellError> {
+    if column.is_null(row) {
+        return Ok(Value::Nothing { span });
+    }
+
+    let value = match column.data_type() {
+        DataType::Boolean => Value::Bool {
+            val: as_boolean_array(column).value(row),
+            span,
+        },
+        DataType::Int8 => int_value(as_primitive_array::<Int8Type>(column).value(row), span),
+        DataType::Int16 => int_value(as_primitive_array::<Int16Type>(column).value(row), span),
+        DataType::Int32 => int_value(as_primitive_array::<Int32Type>(column).value(row), span),
+        DataType::Int64 => int_value(as_primitive_array::<Int64Type>(column).value(row), span),
+        DataType::UInt8 => int_value(as_primitive_array::<UInt8Type>(column).value(row), span),
+        DataType::UInt16 => int_value(as_primitive_array::<UInt16Type>(column).value(row), span),
+        DataType::UInt32 => int_value(as_primitive_array::<UInt32Type>(column).value(row), span),
+        DataType::UInt64 => {
+            let val = as_primitive_array::<UInt64Type>(column).value(row);
+            match i64::try_from(val) {
+                Ok(val) => Value::Int { val, span },
+                // too large for a nushell int, keep every digit as a string
+                Err(_) => Value::String {
+                    val: val.to_string(),
+                    span,
+                },
+            }
+        }
+        DataType::Float32 => Value::Float {
+            val: as_primitive_array::<Float32Type>(column).value(row) as f64,
+            span,
+        },
+        DataType::Float64 => Value::Float {
+            val: as_primitive_array::<Float64Type>(column).value(row),
+            span,
+        },
+        DataType::Utf8 => Value::String {
+            val: as_string_array(column).value(row).to_string(),
+            span,
+        },
+        DataType::LargeUtf8 => {
+            let array = column
+                .as_any()
+                .downcast_ref::<LargeStringArray>()
+                .ok_or_else(|| conversion_error(column, span))?;
+            Value::String {
+                val: array.value(row).to_string(),
+                span,
+            }
+        }
+        DataType::Timestamp(unit, _) => {
+            // a time past what a date can hold is an error, not a panic
+            let val = match unit {
+                TimeUnit::Second => Utc
+                    .timestamp_opt(
+                        as_primitive_array::<TimestampSecondType>(column).value(row),
+                        0,
+                    )
+                    .single(),
+                TimeUnit::Millisecond => Utc
+                    .timestamp_millis_opt(
+                        as_primitive_array::<TimestampMillisecondType>(column).value(row),
+                    )
+                    .single(),
+                TimeUnit::Microsecond => as_primitive_array::<TimestampMicrosecondType>(column)
+                    .value(row)
+                    .checked_mul(1_000)
+                    .map(|nanos| Utc.timestamp_nanos(nanos)),
+                TimeUnit::Nanosecond => Some(Utc.timestamp_nanos(
+                    as_primitive_array::<TimestampNanosecondType>(column).value(row),
+                )),
+            }
+            .ok_or_else(|| conversion_error(column, span))?;
+            Value::Date {
+                val: DateTime::<FixedOffset>::from(val),
+                span,
+            }
+        }
+        _ => Value::String {
+            val: array_value_to_string(column, row).map_err(|_| conversion_error(column, span))?,
+            span,
+        },
+    };
+
+    Ok(value)
+}
+
+fn int_value(val: impl Into<i64>, span: Span) -> Value {
+    Value::Int {
+        val: val.into(),
+        span,
+    }
+}
+
+fn conversion_error(column: &ArrayRef, span: Span) -> ShellError {
+    ShellError::GenericError(
+        "Failed to convert Iox result".into(),
+        format!("unsupported Arrow type {}", column.data_type()),
+        Some(span),
+        None,
+        Vec::new(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::{BooleanArray, Float64Array, Int64Array, StringArray};
+    use arrow::array::{
+        DictionaryArray, TimestampMicrosecondArray, TimestampNanosecondArray, TimestampSecondArray,
+    };
+    use std::sync::Arc;
+
+    fn test_batch() -> RecordBatch {
+        let tag: DictionaryArray<Int32Type> = vec!["la", "pa"].into_iter().collect();
+
+        RecordBatch::try_from_iter(vec![
+            ("region", Arc::new(tag) as ArrayRef),
+            (
+                "user",
+                Arc::new(Int64Array::from(vec![Some(955), None])) as ArrayRef,
+            ),
+            (
+                "usage",
+                Arc::new(Float64Array::from(vec![0.5, 1.25])) as ArrayRef,
+            ),
+            (
+                "active",
+                Arc::new(BooleanArray::from(vec![true, false])) as ArrayRef,
+            ),
+            (
+                "host",
+                Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+            ),
+            (
+                "time",
+                Arc::new(TimestampNanosecondArray::from(vec![222522, 0])) as ArrayRef,
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn out_of_range_times_are_an_error() {
+        let span = Span::test_data();
+        let columns = [
+            Arc::new(TimestampSecondArray::from(vec![i64::MAX])) as ArrayRef,
+            Arc::new(TimestampMicrosecondArray::from(vec![i64::MAX])) as ArrayRef,
+        ];
+
+        for column in &columns {
+            assert!(matches!(
+                array_value_to_nu_value(column, 0, span),
+                Err(ShellError::GenericError(..))
+            ));
+        }
+
+        let column = Arc::new(TimestampMicrosecondArray::from(vec![222])) as ArrayRef;
+        let expected: DateTime<FixedOffset> = Utc.timestamp_nanos(222000).into();
+        assert_eq!(
+            array_value_to_nu_value(&column, 0, span).unwrap(),
+            Value::Date {
+                val: expected,
+                span
+            }
+        );
+    }
+
+    #[test]
+    fn converts_batches_to_typed_records() {
+        let span = Span::test_data();
+        let converted = batches_to_value(&[test_batch()], span).unwrap();
+
+        let expected_time: DateTime<FixedOffset> = Utc.timestamp_nanos(222522).into();
+        let expected = Value::Record {
+            cols: vec![
+                "region".to_string(),
+                "user".to_string(),
+                "usage".to_string(),
+                "active".to_string(),
+                "host".to_string(),
+                "time".to_string(),
+            ],
+            vals: vec![
+                Value::test_string("la"),
+                Value::Int { val: 955, span },
+                Value::Float { val: 0.5, span },
+                Value::Bool { val: true, span },
+                Value::test_string("a"),
+                Value::Date {
+                    val: expected_time,
+                    span,
+                },
+            ],
+            span,
+        };
+
+        match converted {
+            Value::List { vals, .. } => {
+                assert_eq!(vals.len(), 2);
+                assert_eq!(vals[0], expected);
+            }
+            _ => panic!("expected a list"),
+        }
+    }
+
+    #[test]
+    fn describes_mixed_type_schema() {
+        let span = Span::test_data();
+        let schema = test_batch().schema();
+
+        let columns = match schema_to_value(&schema, span) {
+            Value::List { vals, .. } => vals,
+            _ => panic!("expected a list"),
+        };
+
+        assert_eq!(columns.len(), 6);
+        assert_eq!(
+            columns[1],
+            Value::Record {
+                cols: vec![
+                    "column".to_string(),
+                    "arrow_type".to_string(),
+                    "nullable".to_string(),
+                ],
+                vals: vec![
+                    Value::test_string("user"),
+                    Value::test_string("Int64"),
+                    Value::Bool { val: true, span },
+                ],
+                span,
+            }
+        );
+        assert_eq!(
+            columns[5].get_data_by_key("arrow_type"),
+            Some(Value::test_string("Timestamp(Nanosecond, None)"))
+        );
+    }
+
+    #[test]
+    fn null_is_nothing_and_differs_from_an_empty_string() {
+        let span = Span::test_data();
+        let batch = RecordBatch::try_from_iter(vec![(
+            "host",
+            Arc::new(StringArray::from(vec![Some(""), None, Some("a")])) as ArrayRef,
+        )])
+        .unwrap();
+
+        let hosts: Vec<Value> = batch_to_values(&batch, span)
+            .unwrap()
+            .iter()
+            .map(|row| row.get_data_by_key("host").unwrap())
+            .collect();
+
+        assert_eq!(
+            hosts,
+            vec![
+                Value::test_string(""),
+                Value::Nothing { span },
+                Value::test_string("a"),
+            ]
+        );
+        assert_ne!(hosts[0], hosts[1]);
+    }
+
+    #[test]
+    fn converts_empty_result_to_empty_list() {
+        let span = Span::test_data();
+        let converted = batches_to_value(&[], span).unwrap();
+
+        assert_eq!(converted, Value::List { vals: vec![], span });
+    }
+}
@@ -0,0 +1,151 @@
+use super::nuclient::list_database_names;
+use super::util::{resolve_iox_profile, resolve_iox_tls_ca, validate_iox_addr, DEFAULT_IOX_ADDR};
+use lazy_static::lazy_static;
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, DeclId, Example, IntoPipelineData, PipelineData, ShellError, Signature, Spanned,
+    SyntaxShape, Value,
+};
+use std::sync::Mutex;
+
+lazy_static! {
+    // The declaration of ioxdbnames once it is added to the engine state
+    static ref DBNAME_COMPLETER: Mutex<Option<DeclId>> = Mutex::new(None);
+}
+
+/// Makes ioxdbnames the completer of the --dbname flags, see dbname_shape
+pub fn set_dbname_completer(decl_id: DeclId) {
+    *DBNAME_COMPLETER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(decl_id);
+}
+
+/// The shape of a --dbname flag, a string completed by ioxdbnames
+///
+/// Until ioxdbnames is added to the engine state it is a plain string.
+pub fn dbname_shape() -> SyntaxShape {
+    let completer = *DBNAME_COMPLETER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    match completer {
+        Some(decl_id) => SyntaxShape::Custom(Box::new(SyntaxShape::String), decl_id),
+        None => SyntaxShape::String,
+    }
+}
+
+#[derive(Clone)]
+pub struct Ioxdbnames;
+
+impl Command for Ioxdbnames {
+    fn name(&self) -> &str {
+        "ioxdbnames"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxdbnames")
+            .named(
+                "addr",
+                SyntaxShape::String,
+                format!(
+                    "address of the Iox server, defaults to IOX_ADDR or {}",
+                    DEFAULT_IOX_ADDR
+                ),
+                Some('a'),
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "tls-ca",
+                SyntaxShape::Filepath,
+                "PEM certificate authority for an https Iox server, defaults to IOX_TLS_CA",
+                None,
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "List the names of the Iox databases."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "This is the completer of the --dbname flags of the Iox commands, which runs on every tab press, so the names are kept for 30 seconds before the server is asked again."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let addr_flag: Option<Spanned<String>> = call.get_flag(engine_state, stack, "addr")?;
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr_span = addr_flag.as_ref().map_or(call.head, |a| a.span);
+        let addr = profile.resolve_addr(stack, engine_state, addr_flag.map(|a| a.item));
+        let addr = validate_iox_addr(&addr, addr_span)?;
+
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "tls-ca")?,
+            call.head,
+        );
+
+        let names = list_database_names(&addr, token.as_deref(), tls_ca.as_ref()).map_err(|e| {
+            ShellError::GenericError(
+                "Failed to list the Iox databases".into(),
+                e.to_string(),
+                Some(call.head),
+                None,
+                Vec::new(),
+            )
+        })?;
+
+        Ok(Value::List {
+            vals: names
+                .into_iter()
+                .map(|val| Value::String {
+                    val,
+                    span: call.head,
+                })
+                .collect(),
+            span: call.head,
+        }
+        .into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "List the databases of the default Iox server",
+                example: r#"ioxdbnames"#,
+                result: None,
+            },
+            Example {
+                description: "List the databases a token may query on a remote Iox server",
+                example: r#"ioxdbnames -a https://iox.example.com:443 -t $env.IOX_TOKEN"#,
+                result: None,
+            },
+        ]
+    }
+}
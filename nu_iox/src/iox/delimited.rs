@@ -1,6 +1,48 @@
 use csv::{ReaderBuilder, Trim};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{EngineState, Stack};
 use nu_protocol::{Config, IntoPipelineData, PipelineData, ShellError, Span, Value};
 
+/// Options controlling how delimited Iox results are parsed into a table
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DelimitedOptions {
+    pub noheaders: bool,
+    pub no_infer: bool,
+    pub separator: char,
+    pub trim: Trim,
+}
+
+impl Default for DelimitedOptions {
+    fn default() -> Self {
+        Self {
+            noheaders: false,
+            no_infer: false,
+            separator: ',',
+            trim: Trim::None,
+        }
+    }
+}
+
+impl DelimitedOptions {
+    /// Reads the --no-headers, --no-infer, --separator and --trim flags
+    pub fn from_call(
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+    ) -> Result<Self, ShellError> {
+        let separator: Option<Value> = call.get_flag(engine_state, stack, "separator")?;
+        let trim: Option<Value> = call.get_flag(engine_state, stack, "trim")?;
+
+        Ok(Self {
+            noheaders: call.has_flag("no-headers"),
+            no_infer: call.has_flag("no-infer"),
+            separator: separator_from_str(separator)?,
+            trim: trim_from_str(trim)?,
+        })
+    }
+}
+
 fn from_delimited_string_to_value(
     s: String,
     noheaders: bool,
@@ -90,3 +132,69 @@ pub fn trim_from_str(trim: Option<Value>) -> Result<Trim, ShellError> {
         _ => Ok(Trim::None),
     }
 }
+
+pub fn separator_from_str(separator: Option<Value>) -> Result<char, ShellError> {
+    match separator {
+        Some(Value::String { val: s, span }) => {
+            if s == r"\t" {
+                Ok('\t')
+            } else {
+                let vec_s: Vec<char> = s.chars().collect();
+                if vec_s.len() != 1 {
+                    return Err(ShellError::MissingParameter(
+                        "single character separator".into(),
+                        span,
+                    ));
+                };
+                Ok(vec_s[0])
+            }
+        }
+        _ => Ok(','),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn separator_defaults_to_comma() {
+        assert_eq!(separator_from_str(None).unwrap(), ',');
+    }
+
+    #[test]
+    fn separator_accepts_single_character_and_tab() {
+        assert_eq!(
+            separator_from_str(Some(Value::test_string(";"))).unwrap(),
+            ';'
+        );
+        assert_eq!(
+            separator_from_str(Some(Value::test_string(r"\t"))).unwrap(),
+            '\t'
+        );
+    }
+
+    #[test]
+    fn separator_rejects_multiple_characters() {
+        assert!(separator_from_str(Some(Value::test_string(";;"))).is_err());
+    }
+
+    // ioxnamespace builds typed records without going through csv, so the
+    // flags of ioxsql are the only way these options are set
+    #[test]
+    fn call_without_flags_uses_the_default_options() {
+        let options = DelimitedOptions::from_call(
+            &EngineState::new(),
+            &mut Stack::new(),
+            &Call::new(Span::test_data()),
+        )
+        .unwrap();
+
+        assert_eq!(options, DelimitedOptions::default());
+    }
+
+    #[test]
+    fn trim_rejects_unknown_values() {
+        assert!(trim_from_str(Some(Value::test_string("start"))).is_err());
+    }
+}
@@ -0,0 +1,605 @@
+use super::convert::next_query_batch;
+use super::schema::{catalog_connection, with_catalog_flags, CatalogConnection};
+use super::sql::{query_error, tokio_block_sql_stream, SqlQueryOptions};
+use arrow::record_batch::RecordBatch;
+use influxdb_iox_client::flight::PerformQuery;
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape,
+    Value,
+};
+use parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Clone)]
+pub struct Ioxexport;
+
+impl Command for Ioxexport {
+    fn name(&self) -> &str {
+        "ioxexport"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        with_catalog_flags(
+            Signature::build("ioxexport")
+                .required_named(
+                    "table",
+                    SyntaxShape::String,
+                    "table to export",
+                    None,
+                )
+                .required_named(
+                    "out",
+                    SyntaxShape::Filepath,
+                    "directory the parquet files are written to, created when missing",
+                    Some('o'),
+                )
+                .named(
+                    "max-rows-per-file",
+                    SyntaxShape::Int,
+                    "start a new parquet file after this many rows, by default every row goes to one file",
+                    None,
+                ),
+        )
+    }
+
+    fn usage(&self) -> &str {
+        "Export a whole table of an Iox Database to parquet files."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Runs SELECT * on the table and writes the batches as they arrive, so the table never has to fit in memory. The files are named after the table, e.g. cpu-00000.parquet, and the command returns the directory, the number of rows and the files written. A table without rows writes no files."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let table: Spanned<String> = call
+            .get_flag(engine_state, stack, "table")?
+            .ok_or_else(|| ShellError::MissingParameter("table".into(), call.head))?;
+        let out: Spanned<String> = call
+            .get_flag(engine_state, stack, "out")?
+            .ok_or_else(|| ShellError::MissingParameter("out".into(), call.head))?;
+        let max_rows_per_file = max_rows_per_file_from_flag(call.get_flag(
+            engine_state,
+            stack,
+            "max-rows-per-file",
+        )?)?;
+
+        let CatalogConnection {
+            connector,
+            addr,
+            database,
+            timeout,
+            retries,
+        } = catalog_connection(engine_state, stack, call)?;
+
+        let sql = Spanned {
+            item: export_sql(&table.item),
+            span: table.span,
+        };
+        let options = SqlQueryOptions {
+            timeout,
+            retries,
+            ..Default::default()
+        };
+        let query = tokio_block_sql_stream(connector, &addr, &database, &sql, &options)?
+            .map_err(|error| query_error(error, call))?;
+
+        let mut parts = ParquetParts::new(out, &table.item, max_rows_per_file)?;
+        let exported = export_batches(
+            query_batches(query, call.head),
+            &mut parts,
+            engine_state.ctrlc.as_deref(),
+        );
+        // closed either way, so the files of a failed export can still be read
+        let (dir, rows, files) = parts.finish()?;
+        exported?;
+
+        Ok(export_parts_to_value(&dir, rows, &files, call.head).into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Archive the cpu table of the bananas database",
+                example: r#"ioxexport -d bananas --table cpu --out archive/cpu"#,
+                result: None,
+            },
+            Example {
+                description: "Archive the cpu table in files of at most a million rows",
+                example: r#"ioxexport --table cpu --out archive/cpu --max-rows-per-file 1000000"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+/// The query behind `ioxexport`, the table name is quoted as an identifier
+pub fn export_sql(table: &str) -> String {
+    format!("SELECT * FROM \"{}\"", table.replace('"', "\"\""))
+}
+
+/// Reads the --max-rows-per-file value, which has to be positive
+pub fn max_rows_per_file_from_flag(
+    max_rows: Option<Spanned<i64>>,
+) -> Result<Option<usize>, ShellError> {
+    match max_rows {
+        None => Ok(None),
+        Some(Spanned { item, span }) if item <= 0 => Err(ShellError::UnsupportedInput(
+            format!("--max-rows-per-file must be positive, got {}", item),
+            span,
+        )),
+        Some(Spanned { item, .. }) => Ok(Some(item as usize)),
+    }
+}
+
+/// Pulls the batches of a streaming query one at a time, like --stream does
+pub fn query_batches(
+    mut query: PerformQuery,
+    span: Span,
+) -> impl Iterator<Item = Result<RecordBatch, ShellError>> {
+    std::iter::from_fn(move || next_query_batch(&mut query, span).transpose())
+}
+
+/// Writes the batches to the parquet files as they arrive
+///
+/// Stops at the first failure. ctrl-c stops the export between batches,
+/// the files written so far are kept, finish still closes the open one.
+pub fn export_batches<I>(
+    batches: I,
+    parts: &mut ParquetParts,
+    ctrlc: Option<&AtomicBool>,
+) -> Result<(), ShellError>
+where
+    I: IntoIterator<Item = Result<RecordBatch, ShellError>>,
+{
+    for batch in batches {
+        if ctrlc.map_or(false, |c| c.load(Ordering::SeqCst)) {
+            return Err(ShellError::GenericError(
+                "Iox export cancelled".into(),
+                format!(
+                    "interrupted with ctrl-c after {} rows, the files written so far are kept",
+                    parts.rows
+                ),
+                Some(parts.dir.span),
+                None,
+                Vec::new(),
+            ));
+        }
+
+        parts.write(&batch?)?;
+    }
+
+    Ok(())
+}
+
+/// Writes record batches to numbered parquet files in a directory
+///
+/// A new file is started once the open one holds max_rows_per_file rows, a
+/// batch crossing the limit is split between the two files.
+pub struct ParquetParts {
+    dir: Spanned<String>,
+    prefix: String,
+    max_rows_per_file: Option<usize>,
+    writer: Option<(Spanned<String>, ArrowWriter<File>)>,
+    rows_in_file: usize,
+    files: Vec<String>,
+    rows: usize,
+}
+
+impl ParquetParts {
+    /// Creates the directory, the files are named after prefix
+    pub fn new(
+        dir: Spanned<String>,
+        prefix: &str,
+        max_rows_per_file: Option<usize>,
+    ) -> Result<Self, ShellError> {
+        std::fs::create_dir_all(&dir.item).map_err(|e| {
+            ShellError::GenericError(
+                format!("Failed to create directory {}", dir.item),
+                e.to_string(),
+                Some(dir.span),
+                None,
+                Vec::new(),
+            )
+        })?;
+
+        Ok(Self {
+            dir,
+            prefix: prefix.replace(|c: char| c == '/' || c == '\\', "_"),
+            max_rows_per_file,
+            writer: None,
+            rows_in_file: 0,
+            files: Vec::new(),
+            rows: 0,
+        })
+    }
+
+    /// Writes the rows of the batch, starting new files as they fill up
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<(), ShellError> {
+        let mut offset = 0;
+
+        while offset < batch.num_rows() {
+            let left = batch.num_rows() - offset;
+            let len = self
+                .max_rows_per_file
+                .map_or(left, |max| left.min(max - self.rows_in_file));
+            let part = batch.slice(offset, len);
+
+            self.open_file(&part)?;
+            if let Some((path, writer)) = &mut self.writer {
+                writer
+                    .write(&part)
+                    .map_err(|e| write_error(path, e.to_string()))?;
+            }
+            self.rows_in_file += len;
+            self.rows += len;
+            offset += len;
+
+            if Some(self.rows_in_file) == self.max_rows_per_file {
+                self.close_file()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Closes the open file, returning the directory, the rows and the files written
+    pub fn finish(mut self) -> Result<(String, usize, Vec<String>), ShellError> {
+        self.close_file()?;
+
+        Ok((self.dir.item, self.rows, self.files))
+    }
+
+    fn open_file(&mut self, batch: &RecordBatch) -> Result<(), ShellError> {
+        if self.writer.is_some() {
+            return Ok(());
+        }
+
+        let name = format!("{}-{:05}.parquet", self.prefix, self.files.len());
+        let path = Spanned {
+            item: Path::new(&self.dir.item)
+                .join(name)
+                .to_string_lossy()
+                .to_string(),
+            span: self.dir.span,
+        };
+        let file = File::create(&path.item).map_err(|e| write_error(&path, e.to_string()))?;
+        let writer = ArrowWriter::try_new(file, batch.schema(), None)
+            .map_err(|e| write_error(&path, e.to_string()))?;
+
+        self.files.push(path.item.clone());
+        self.writer = Some((path, writer));
+        self.rows_in_file = 0;
+        Ok(())
+    }
+
+    fn close_file(&mut self) -> Result<(), ShellError> {
+        if let Some((path, writer)) = self.writer.take() {
+            writer
+                .close()
+                .map_err(|e| write_error(&path, e.to_string()))?;
+        }
+        self.rows_in_file = 0;
+        Ok(())
+    }
+}
+
+/// The record `ioxexport` reports once the table has been written
+pub fn export_parts_to_value(dir: &str, rows: usize, files: &[String], span: Span) -> Value {
+    Value::Record {
+        cols: vec!["path".to_string(), "rows".to_string(), "files".to_string()],
+        vals: vec![
+            Value::String {
+                val: dir.to_string(),
+                span,
+            },
+            Value::Int {
+                val: rows as i64,
+                span,
+            },
+            Value::List {
+                vals: files
+                    .iter()
+                    .map(|file| Value::String {
+                        val: file.clone(),
+                        span,
+                    })
+                    .collect(),
+                span,
+            },
+        ],
+        span,
+    }
+}
+
+/// Writes the record batches of a query result to a parquet file
+///
+/// The Arrow schema of the first batch is stored with the file, so the
+/// column types come back unchanged when it is read again. Returns the
+/// number of rows written.
+pub fn write_parquet(path: &Spanned<String>, batches: &[RecordBatch]) -> Result<usize, ShellError> {
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => {
+            return Err(ShellError::GenericError(
+                "Nothing to write".into(),
+                "the query returned no batches, so there is no schema for the parquet file".into(),
+                Some(path.span),
+                None,
+                Vec::new(),
+            ))
+        }
+    };
+
+    let file = File::create(&path.item).map_err(|e| write_error(path, e.to_string()))?;
+    let mut writer =
+        ArrowWriter::try_new(file, schema, None).map_err(|e| write_error(path, e.to_string()))?;
+
+    for batch in batches {
+        writer
+            .write(batch)
+            .map_err(|e| write_error(path, e.to_string()))?;
+    }
+    writer
+        .close()
+        .map_err(|e| write_error(path, e.to_string()))?;
+
+    Ok(batches.iter().map(|b| b.num_rows()).sum())
+}
+
+/// The record reported once the results have been written
+pub fn export_to_value(path: &str, rows: usize, span: Span) -> Value {
+    Value::Record {
+        cols: vec!["path".to_string(), "rows".to_string()],
+        vals: vec![
+            Value::String {
+                val: path.to_string(),
+                span,
+            },
+            Value::Int {
+                val: rows as i64,
+                span,
+            },
+        ],
+        span,
+    }
+}
+
+/// Writes results already formatted as text, e.g. as csv or ndjson, to a file
+pub fn write_text(path: &Spanned<String>, contents: &str) -> Result<(), ShellError> {
+    std::fs::write(&path.item, contents).map_err(|e| {
+        ShellError::GenericError(
+            format!("Failed to write file {}", path.item),
+            e.to_string(),
+            Some(path.span),
+            None,
+            Vec::new(),
+        )
+    })
+}
+
+fn write_error(path: &Spanned<String>, msg: String) -> ShellError {
+    ShellError::GenericError(
+        format!("Failed to write parquet file {}", path.item),
+        msg,
+        Some(path.span),
+        None,
+        Vec::new(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::TimestampNanosecondArray;
+    use arrow::array::{ArrayRef, Float64Array, Int64Array, StringArray};
+    use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+    use std::sync::Arc;
+
+    #[test]
+    fn parquet_round_trip_keeps_schema_and_values() {
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "user",
+                Arc::new(Int64Array::from(vec![Some(955), None])) as ArrayRef,
+            ),
+            (
+                "usage",
+                Arc::new(Float64Array::from(vec![0.5, 1.25])) as ArrayRef,
+            ),
+            (
+                "host",
+                Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+            ),
+            (
+                "time",
+                Arc::new(TimestampNanosecondArray::from(vec![222522, 0])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+
+        let path = std::env::temp_dir().join("nu_iox_round_trip.parquet");
+        let out = Spanned {
+            item: path.to_string_lossy().to_string(),
+            span: Span::test_data(),
+        };
+
+        let rows = write_parquet(&out, &[batch.clone(), batch.clone()]).unwrap();
+        assert_eq!(rows, 4);
+
+        let mut reader = ParquetFileArrowReader::try_new(File::open(&path).unwrap()).unwrap();
+        let read: Vec<RecordBatch> = reader
+            .get_record_reader(1024)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].schema().fields(), batch.schema().fields());
+        assert_eq!(read[0].slice(0, 2).columns(), batch.columns());
+        assert_eq!(read[0].slice(2, 2).columns(), batch.columns());
+    }
+
+    #[test]
+    fn unwritable_text_file_is_an_error() {
+        let out = Spanned {
+            item: "/no/such/dir/cpu.csv".to_string(),
+            span: Span::test_data(),
+        };
+
+        assert!(matches!(
+            write_text(&out, "user\n955\n"),
+            Err(ShellError::GenericError(..))
+        ));
+    }
+
+    #[test]
+    fn users_batch(users: Vec<i64>) -> RecordBatch {
+        RecordBatch::try_from_iter(vec![(
+            "user",
+            Arc::new(Int64Array::from(users)) as ArrayRef,
+        )])
+        .unwrap()
+    }
+
+    fn read_rows(path: &str) -> Vec<i64> {
+        let mut reader = ParquetFileArrowReader::try_new(File::open(path).unwrap()).unwrap();
+        let batches: Vec<RecordBatch> = reader
+            .get_record_reader(1024)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let users = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap();
+                users.values().to_vec()
+            })
+            .collect()
+    }
+
+    fn export_dir(name: &str) -> Spanned<String> {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        Spanned {
+            item: dir.to_string_lossy().to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn export_splits_the_table_into_files_of_max_rows() {
+        let dir = export_dir("nu_iox_export_split");
+        let batches = vec![
+            Ok(users_batch(vec![1, 2])),
+            Ok(users_batch(vec![3, 4])),
+            Ok(users_batch(vec![5, 6, 7])),
+        ];
+
+        let mut parts = ParquetParts::new(dir.clone(), "cpu", Some(3)).unwrap();
+        export_batches(batches, &mut parts, None).unwrap();
+        let (path, rows, files) = parts.finish().unwrap();
+
+        assert_eq!(path, dir.item);
+        assert_eq!(rows, 7);
+        assert_eq!(
+            files,
+            [
+                "cpu-00000.parquet",
+                "cpu-00001.parquet",
+                "cpu-00002.parquet"
+            ]
+            .iter()
+            .map(|name| Path::new(&dir.item)
+                .join(name)
+                .to_string_lossy()
+                .to_string())
+            .collect::<Vec<_>>()
+        );
+        assert_eq!(read_rows(&files[0]), vec![1, 2, 3]);
+        assert_eq!(read_rows(&files[1]), vec![4, 5, 6]);
+        assert_eq!(read_rows(&files[2]), vec![7]);
+
+        let _ = std::fs::remove_dir_all(&dir.item);
+    }
+
+    #[test]
+    fn export_without_max_rows_writes_one_file() {
+        let dir = export_dir("nu_iox_export_single");
+        let batches = vec![Ok(users_batch(vec![1, 2])), Ok(users_batch(vec![3]))];
+
+        let mut parts = ParquetParts::new(dir.clone(), "cpu", None).unwrap();
+        export_batches(batches, &mut parts, None).unwrap();
+        let (_, rows, files) = parts.finish().unwrap();
+
+        assert_eq!(rows, 3);
+        assert_eq!(files.len(), 1);
+        assert_eq!(read_rows(&files[0]), vec![1, 2, 3]);
+
+        let _ = std::fs::remove_dir_all(&dir.item);
+    }
+
+    #[test]
+    fn failed_export_keeps_the_rows_written_so_far() {
+        let dir = export_dir("nu_iox_export_failed");
+        let batches = vec![
+            Ok(users_batch(vec![1, 2])),
+            Err(ShellError::IOError("connection reset".into())),
+            Ok(users_batch(vec![3])),
+        ];
+
+        let mut parts = ParquetParts::new(dir.clone(), "cpu", None).unwrap();
+        assert!(export_batches(batches, &mut parts, None).is_err());
+        let (_, rows, files) = parts.finish().unwrap();
+
+        assert_eq!(rows, 2);
+        assert_eq!(read_rows(&files[0]), vec![1, 2]);
+
+        let _ = std::fs::remove_dir_all(&dir.item);
+    }
+
+    #[test]
+    fn export_quotes_the_table_and_rejects_non_positive_max_rows() {
+        assert_eq!(export_sql("cpu"), r#"SELECT * FROM "cpu""#);
+        assert_eq!(export_sql(r#"my"cpu"#), r#"SELECT * FROM "my""cpu""#);
+
+        let max_rows = |item| {
+            max_rows_per_file_from_flag(Some(Spanned {
+                item,
+                span: Span::test_data(),
+            }))
+        };
+        assert_eq!(max_rows(10).unwrap(), Some(10));
+        assert!(max_rows(0).is_err());
+        assert_eq!(max_rows_per_file_from_flag(None).unwrap(), None);
+    }
+
+    #[test]
+    fn nothing_to_write_is_an_error() {
+        let out = Spanned {
+            item: "empty.parquet".to_string(),
+            span: Span::test_data(),
+        };
+
+        assert!(matches!(
+            write_parquet(&out, &[]),
+            Err(ShellError::GenericError(..))
+        ));
+    }
+}
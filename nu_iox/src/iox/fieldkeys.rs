@@ -0,0 +1,184 @@
+use super::schema::{
+    catalog_columns, run_catalog_query, schema_sql, with_catalog_flags, CatalogColumn,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct Ioxfieldkeys;
+
+impl Command for Ioxfieldkeys {
+    fn name(&self) -> &str {
+        "ioxfieldkeys"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        with_catalog_flags(Signature::build("ioxfieldkeys").optional(
+            "measurement",
+            SyntaxShape::String,
+            "only list the field keys of this measurement",
+        ))
+    }
+
+    fn usage(&self) -> &str {
+        "List the field keys of an Iox Database, like SHOW FIELD KEYS."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Reads information_schema.columns of the database, the fields are the columns that are neither tags nor the time. One row per measurement and field key, with the InfluxQL name of its type."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let measurement: Option<String> = call.opt(engine_state, stack, 0)?;
+        let batches = run_catalog_query(
+            engine_state,
+            stack,
+            call,
+            schema_sql(measurement.as_deref()),
+        )?;
+
+        Ok(
+            field_keys_to_value(&catalog_columns(&batches, call.head)?, call.head)
+                .into_pipeline_data(),
+        )
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "List the field keys of every measurement",
+                example: r#"ioxfieldkeys"#,
+                result: None,
+            },
+            Example {
+                description: "List the float fields of the cpu measurement",
+                example: r#"ioxfieldkeys cpu | where field_type == float"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+/// The InfluxQL name of a field's Arrow type, other types keep the Arrow name
+pub fn field_type(data_type: &str) -> &str {
+    match data_type {
+        "Float64" => "float",
+        "Int64" => "integer",
+        "UInt64" => "unsigned",
+        "Utf8" => "string",
+        "Boolean" => "boolean",
+        other => other,
+    }
+}
+
+/// The field columns as a table of measurement, field_key and field_type
+pub fn field_keys_to_value(columns: &[CatalogColumn], span: Span) -> Value {
+    let vals = columns
+        .iter()
+        .filter(|column| !column.is_tag() && !column.is_time())
+        .map(|column| Value::Record {
+            cols: vec![
+                "measurement".to_string(),
+                "field_key".to_string(),
+                "field_type".to_string(),
+            ],
+            vals: vec![
+                Value::String {
+                    val: column.table_name.clone(),
+                    span,
+                },
+                Value::String {
+                    val: column.column_name.clone(),
+                    span,
+                },
+                Value::String {
+                    val: field_type(&column.data_type).to_string(),
+                    span,
+                },
+            ],
+            span,
+        })
+        .collect();
+
+    Value::List { vals, span }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::iox::schema::TAG_DATA_TYPE;
+
+    fn column(table_name: &str, column_name: &str, data_type: &str) -> CatalogColumn {
+        CatalogColumn {
+            table_name: table_name.to_string(),
+            column_name: column_name.to_string(),
+            data_type: data_type.to_string(),
+        }
+    }
+
+    #[test]
+    fn tags_and_time_are_not_field_keys() {
+        let span = Span::test_data();
+        let columns = vec![
+            column("cpu", "host", TAG_DATA_TYPE),
+            column("cpu", "time", "Timestamp(Nanosecond, None)"),
+            column("cpu", "usage_user", "Float64"),
+            column("cpu", "active", "Boolean"),
+        ];
+
+        let field_keys = match field_keys_to_value(&columns, span) {
+            Value::List { vals, .. } => vals,
+            _ => panic!("expected a list"),
+        };
+
+        assert_eq!(
+            field_keys,
+            vec![
+                Value::Record {
+                    cols: vec![
+                        "measurement".to_string(),
+                        "field_key".to_string(),
+                        "field_type".to_string(),
+                    ],
+                    vals: vec![
+                        Value::test_string("cpu"),
+                        Value::test_string("usage_user"),
+                        Value::test_string("float"),
+                    ],
+                    span,
+                },
+                Value::Record {
+                    cols: vec![
+                        "measurement".to_string(),
+                        "field_key".to_string(),
+                        "field_type".to_string(),
+                    ],
+                    vals: vec![
+                        Value::test_string("cpu"),
+                        Value::test_string("active"),
+                        Value::test_string("boolean"),
+                    ],
+                    span,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn field_types_use_the_influxql_names() {
+        assert_eq!(field_type("Int64"), "integer");
+        assert_eq!(field_type("UInt64"), "unsigned");
+        assert_eq!(field_type("Utf8"), "string");
+        assert_eq!(field_type("Decimal128(10, 2)"), "Decimal128(10, 2)");
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::ArrayRef;
+use arrow::buffer::Buffer;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::ipc::{self, reader::read_dictionary, MessageHeader};
+use arrow::record_batch::RecordBatch;
+use arrow_flight::flight_service_client::FlightServiceClient;
+use arrow_flight::sql::{CommandStatementQuery, ProstMessageExt};
+use arrow_flight::utils::flight_data_to_arrow_batch;
+use arrow_flight::{FlightData, FlightDescriptor, Ticket};
+use influxdb_iox_client::connection::{Connection, GrpcConnection};
+use prost::Message;
+use snafu::ResultExt;
+use tonic::Streaming;
+
+use super::nuclient::{
+    with_retries, Compression, DecodingFlightSqlSnafu, Result, RunningFlightSqlQuerySnafu,
+};
+
+/// Request header Iox takes the namespace of a Flight SQL query from
+const NAMESPACE_HEADER: &str = "iox-namespace-name";
+
+/// A query running over Arrow Flight SQL
+///
+/// The statement is planned with GetFlightInfo, then the results of every
+/// endpoint are read with DoGet. Like PerformQuery, batches are only read
+/// from the server when `next` is called.
+pub struct FlightSqlQuery {
+    client: FlightServiceClient<GrpcConnection>,
+    namespace: String,
+    tickets: std::vec::IntoIter<Ticket>,
+    stream: Option<Streaming<FlightData>>,
+    schema: Option<SchemaRef>,
+    dictionaries_by_id: HashMap<i64, ArrayRef>,
+}
+
+impl FlightSqlQuery {
+    /// Plans the sql on the server, retrying transient failures
+    ///
+    /// With Compression::Gzip the client accepts gzipped replies and
+    /// decompresses them as they are read. Requests are never compressed, a
+    /// server without gzip support would reject them.
+    pub async fn start(
+        connection: Connection,
+        namespace: &str,
+        sql: &str,
+        retries: usize,
+        compression: Compression,
+    ) -> Result<Self> {
+        let client = FlightServiceClient::new(connection.into_grpc_connection());
+        let client = match compression {
+            Compression::Gzip => client.accept_gzip(),
+            Compression::None => client,
+        };
+        let command = CommandStatementQuery {
+            query: sql.to_string(),
+        };
+        let descriptor = FlightDescriptor::new_cmd(command.as_any().encode_to_vec());
+
+        let flight_info = with_retries(retries, || {
+            let mut client = client.clone();
+            let request = namespace_request(descriptor.clone(), namespace);
+
+            async move { client.get_flight_info(request?).await }
+        })
+        .await
+        .context(RunningFlightSqlQuerySnafu)?
+        .into_inner();
+
+        Ok(Self {
+            client,
+            namespace: namespace.to_string(),
+            tickets: flight_info
+                .endpoint
+                .into_iter()
+                .filter_map(|endpoint| endpoint.ticket)
+                .collect::<Vec<_>>()
+                .into_iter(),
+            stream: None,
+            schema: None,
+            dictionaries_by_id: HashMap::new(),
+        })
+    }
+
+    /// Returns the next record batch of the result, None once every endpoint
+    /// has been read
+    pub async fn next(&mut self) -> Result<Option<RecordBatch>> {
+        loop {
+            let data = match &mut self.stream {
+                Some(stream) => stream.message().await.context(RunningFlightSqlQuerySnafu)?,
+                None => match self.tickets.next() {
+                    Some(ticket) => {
+                        let request = namespace_request(ticket, &self.namespace)
+                            .context(RunningFlightSqlQuerySnafu)?;
+                        let stream = self
+                            .client
+                            .do_get(request)
+                            .await
+                            .context(RunningFlightSqlQuerySnafu)?
+                            .into_inner();
+
+                        // every endpoint sends its own schema and dictionaries
+                        self.stream = Some(stream);
+                        self.schema = None;
+                        self.dictionaries_by_id.clear();
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+
+            let data = match data {
+                Some(data) => data,
+                None => {
+                    self.stream = None;
+                    continue;
+                }
+            };
+
+            if let Some(batch) = self.decode(data).context(DecodingFlightSqlSnafu)? {
+                return Ok(Some(batch));
+            }
+        }
+    }
+
+    /// Decodes one flight message, only a record batch message yields a batch
+    fn decode(&mut self, data: FlightData) -> Result<Option<RecordBatch>, ArrowError> {
+        let message = ipc::root_as_message(&data.data_header[..])
+            .map_err(|e| ArrowError::ParseError(format!("invalid flight message: {}", e)))?;
+
+        match message.header_type() {
+            MessageHeader::Schema => {
+                self.schema = Some(Arc::new(Schema::try_from(&data)?));
+                Ok(None)
+            }
+            MessageHeader::DictionaryBatch => {
+                let dictionary_batch = message.header_as_dictionary_batch().ok_or_else(|| {
+                    ArrowError::ParseError("invalid dictionary batch message".into())
+                })?;
+
+                let schema = Arc::clone(self.schema()?);
+                read_dictionary(
+                    &Buffer::from(&data.data_body),
+                    dictionary_batch,
+                    &schema,
+                    &mut self.dictionaries_by_id,
+                    &message.version(),
+                )?;
+                Ok(None)
+            }
+            MessageHeader::RecordBatch => Ok(Some(flight_data_to_arrow_batch(
+                &data,
+                Arc::clone(self.schema()?),
+                &self.dictionaries_by_id,
+            )?)),
+            other => Err(ArrowError::ParseError(format!(
+                "unexpected flight message {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn schema(&self) -> Result<&SchemaRef, ArrowError> {
+        self.schema
+            .as_ref()
+            .ok_or_else(|| ArrowError::ParseError("flight data arrived before its schema".into()))
+    }
+}
+
+/// Wraps a Flight SQL message in a request naming the namespace it is for
+fn namespace_request<T>(message: T, namespace: &str) -> Result<tonic::Request<T>, tonic::Status> {
+    let namespace = namespace.parse().map_err(|_| {
+        tonic::Status::invalid_argument(format!("invalid namespace '{}'", namespace))
+    })?;
+
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(NAMESPACE_HEADER, namespace);
+
+    Ok(request)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_name_their_namespace() {
+        let request = namespace_request(Ticket { ticket: vec![] }, "bananas").unwrap();
+
+        assert_eq!(request.metadata().get(NAMESPACE_HEADER).unwrap(), "bananas");
+    }
+
+    #[test]
+    fn namespace_must_fit_in_a_header() {
+        let status = namespace_request(Ticket { ticket: vec![] }, "ban\nanas").unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn statement_query_is_sent_as_a_flight_sql_command() {
+        let command = CommandStatementQuery {
+            query: "select * from cpu".to_string(),
+        };
+        let descriptor = FlightDescriptor::new_cmd(command.as_any().encode_to_vec());
+
+        let any = prost_types::Any::decode(&descriptor.cmd[..]).unwrap();
+        assert_eq!(any.type_url, CommandStatementQuery::type_url());
+        assert_eq!(
+            CommandStatementQuery::decode(&any.value[..]).unwrap().query,
+            "select * from cpu"
+        );
+    }
+}
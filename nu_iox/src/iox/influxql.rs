@@ -0,0 +1,303 @@
+use super::convert::batches_to_value;
+use super::dbnames::dbname_shape;
+use super::json::from_json_data;
+use super::nuclient::{format_batches, Error, RemoteDatabase};
+use super::nuerror::NuIoxErrorHandler;
+use super::sql::{format_error, pretty_text, sql_format_from_str, SqlFormat, SqlQueryOptions};
+
+use super::util::{
+    block_on_iox, headers_from_value, iox_connector, resolve_iox_database, resolve_iox_format,
+    resolve_iox_keepalive, resolve_iox_profile, resolve_iox_quiet, resolve_iox_tls_ca,
+    resolve_pretty_color, retries_from_flag, timeout_from_value, validate_iox_addr, IoxConnector,
+    DEFAULT_IOX_ADDR, DEFAULT_QUERY_TIMEOUT,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Spanned, SyntaxShape,
+    Value,
+};
+
+use arrow::record_batch::RecordBatch;
+use influxdb_iox_client::format::QueryOutputFormat;
+
+#[derive(Clone)]
+pub struct Ioxinfluxql;
+
+impl Command for Ioxinfluxql {
+    fn name(&self) -> &str {
+        "ioxinfluxql"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxinfluxql")
+            .required(
+                "query",
+                SyntaxShape::String,
+                "InfluxQL to execute against the database",
+            )
+            .named(
+                "dbname",
+                dbname_shape(),
+                "name of the database to search over",
+                Some('d'),
+            )
+            .named(
+                "namespace",
+                SyntaxShape::String,
+                "namespace sent to the server as is, e.g. myorg_bananas, when it differs from the database name",
+                None,
+            )
+            .named(
+                "addr",
+                SyntaxShape::String,
+                format!(
+                    "address of the Iox server, defaults to IOX_ADDR or {}",
+                    DEFAULT_IOX_ADDR
+                ),
+                Some('a'),
+            )
+            .named(
+                "format",
+                SyntaxShape::String,
+                "format of the results: arrow (default), json or pretty, IOX_FORMAT sets the default",
+                Some('f'),
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "tls-ca",
+                SyntaxShape::Filepath,
+                "PEM certificate authority for an https Iox server, defaults to IOX_TLS_CA",
+                None,
+            )
+            .named(
+                "header",
+                SyntaxShape::Any,
+                "metadata header sent with every request as key=value, or a list of them, e.g. x-tenant-id=bananas",
+                None,
+            )
+            .named(
+                "retries",
+                SyntaxShape::Int,
+                "retries for an unavailable server, with exponential backoff, defaults to 3",
+                None,
+            )
+            .named(
+                "timeout",
+                SyntaxShape::Duration,
+                "how long the query may run, defaults to 30sec, 0sec waits forever",
+                None,
+            )
+            .switch(
+                "color",
+                "keep the ANSI colors of the pretty format, it is plain by default",
+                None,
+            )
+            .switch(
+                "no-color",
+                "never color the pretty format, like setting NO_COLOR",
+                None,
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "InfluxQL query against the Iox Database."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Needs nu-command built with the iox-influxql feature and an Iox server that accepts InfluxQL over flight."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let influxql: Spanned<String> = call.req(engine_state, stack, 0)?;
+        let db: Option<String> = call.get_flag(engine_state, stack, "dbname")?;
+        let namespace: Option<String> = call.get_flag(engine_state, stack, "namespace")?;
+        let addr_flag: Option<Spanned<String>> = call.get_flag(engine_state, stack, "addr")?;
+        let format_flag: Option<Value> = resolve_iox_format(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "format")?,
+            call.head,
+        );
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+        let retries = retries_from_flag(call.get_flag(engine_state, stack, "retries")?)?;
+        let timeout = timeout_from_value(
+            call.get_flag(engine_state, stack, "timeout")?,
+            DEFAULT_QUERY_TIMEOUT,
+        )?;
+
+        let format_span = format_flag
+            .as_ref()
+            .and_then(|f| f.span().ok())
+            .unwrap_or(call.head);
+        let format = sql_format_from_str(format_flag)?;
+        if format == SqlFormat::Csv {
+            return Err(ShellError::UnsupportedInput(
+                "ioxinfluxql supports the 'arrow', 'json' and 'pretty' formats".into(),
+                format_span,
+            ));
+        }
+
+        let database = resolve_iox_database(stack, engine_state, db, namespace, call.head)?;
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr_span = addr_flag.as_ref().map_or(call.head, |a| a.span);
+        let addr = profile.resolve_addr(stack, engine_state, addr_flag.map(|a| a.item));
+        let addr = validate_iox_addr(&addr, addr_span)?;
+
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "tls-ca")?,
+            call.head,
+        );
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+        let headers = headers_from_value(call.get_flag(engine_state, stack, "header")?)?;
+        let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+            .with_keepalive(keepalive)
+            .with_headers(headers);
+
+        let options = SqlQueryOptions {
+            timeout,
+            retries,
+            ctrlc: engine_state.ctrlc.clone(),
+            quiet: resolve_iox_quiet(stack, engine_state),
+            ..Default::default()
+        };
+        let batches = tokio_block_influxql(connector, &addr, &database, &influxql, &options)?;
+
+        let batches = match batches {
+            Ok(batches) => batches,
+            Err(Error::Timeout { timeout }) => {
+                return Err(ShellError::GenericError(
+                    "Iox query timed out".into(),
+                    format!("no result within {:?}", timeout),
+                    Some(call.head),
+                    Some("Raise the limit with --timeout, or use 0sec to wait forever".into()),
+                    Vec::new(),
+                ));
+            }
+            Err(Error::InfluxQlUnavailable) => {
+                return Err(ShellError::GenericError(
+                    "InfluxQL is not available".into(),
+                    Error::InfluxQlUnavailable.to_string(),
+                    Some(call.head),
+                    Some("Use ioxsql or rebuild with --features iox-influxql".into()),
+                    Vec::new(),
+                ));
+            }
+            Err(Error::Cancelled) => {
+                return Err(ShellError::GenericError(
+                    "Iox query cancelled".into(),
+                    "interrupted with ctrl-c, partial results were dropped".into(),
+                    Some(call.head),
+                    None,
+                    Vec::new(),
+                ));
+            }
+            Err(error) => {
+                let nierrorhandler =
+                    NuIoxErrorHandler::from_error(super::nuerror::CommandType::Sql, &error);
+
+                nierrorhandler.nu_iox_error_check()?;
+                nierrorhandler.nu_iox_error_generic(call)?;
+                return Ok(PipelineData::new(call.head));
+            }
+        };
+
+        let value = match format {
+            SqlFormat::Json => {
+                let json = format_batches(&QueryOutputFormat::Json, &batches)
+                    .map_err(|e| format_error(e, call.head))?;
+                from_json_data(&json, call.head)?
+            }
+            SqlFormat::Pretty => Value::String {
+                val: pretty_text(
+                    &batches,
+                    resolve_pretty_color(
+                        stack,
+                        engine_state,
+                        call.has_flag("color"),
+                        call.has_flag("no-color"),
+                    ),
+                    call.head,
+                )?,
+                span: call.head,
+            },
+            _ => batches_to_value(&batches, call.head)?,
+        };
+
+        Ok(value.into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Run an InfluxQL query against the bananas database",
+                example: r#"ioxinfluxql -d bananas "SELECT * FROM cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Run an InfluxQL query against the default database",
+                example: r#"ioxinfluxql "SELECT usage_user FROM cpu WHERE region = 'us-west'""#,
+                result: None,
+            },
+            Example {
+                description: "Run an InfluxQL query and return the pretty printed table",
+                example: r#"ioxinfluxql -f pretty "SELECT MEAN(usage_user) FROM cpu GROUP BY region""#,
+                result: None,
+            },
+            Example {
+                description: "Run an InfluxQL query against a remote Iox server",
+                example: r#"ioxinfluxql -a http://iox.example.com:8082 -d bananas "SELECT * FROM cpu""#,
+                result: None,
+            },
+        ]
+    }
+}
+
+pub fn tokio_block_influxql(
+    connector: IoxConnector,
+    addr: &str,
+    database: &RemoteDatabase,
+    influxql: &Spanned<String>,
+    options: &SqlQueryOptions,
+) -> Result<Result<Vec<RecordBatch>, Error>, ShellError> {
+    use crate::iox::Nuclient;
+    let batches = block_on_iox(async move {
+        let connection = connector.connect(addr).await?;
+
+        let mut repl = Nuclient::new(connection);
+        repl.use_remote_database(database.clone());
+        options.configure(&mut repl);
+
+        Ok::<_, ShellError>(repl.run_influxql_batches(influxql.item.to_string()).await)
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?;
+
+    batches
+}
@@ -0,0 +1,101 @@
+use nu_protocol::{ShellError, Span, Value};
+
+fn convert_nujson_to_value(value: &nu_json::Value, span: Span) -> Value {
+    match value {
+        nu_json::Value::Array(array) => {
+            let v: Vec<Value> = array
+                .iter()
+                .map(|x| convert_nujson_to_value(x, span))
+                .collect();
+
+            Value::List { vals: v, span }
+        }
+        nu_json::Value::Bool(b) => Value::Bool { val: *b, span },
+        nu_json::Value::F64(f) => Value::Float { val: *f, span },
+        nu_json::Value::I64(i) => Value::Int { val: *i, span },
+        nu_json::Value::Null => Value::Nothing { span },
+        nu_json::Value::Object(k) => {
+            let mut cols = vec![];
+            let mut vals = vec![];
+
+            for item in k {
+                cols.push(item.0.clone());
+                vals.push(convert_nujson_to_value(item.1, span));
+            }
+
+            Value::Record { cols, vals, span }
+        }
+        nu_json::Value::U64(u) => {
+            if *u > i64::MAX as u64 {
+                Value::Error {
+                    error: ShellError::CantConvert(
+                        "i64 sized integer".into(),
+                        "value larger than i64".into(),
+                        span,
+                        None,
+                    ),
+                }
+            } else {
+                Value::Int {
+                    val: *u as i64,
+                    span,
+                }
+            }
+        }
+        nu_json::Value::String(s) => Value::String {
+            val: s.clone(),
+            span,
+        },
+    }
+}
+
+// Parses the json formatted results returned by the Iox client
+// An empty result set formats to an empty string rather than `[]`
+pub fn from_json_data(data: &str, span: Span) -> Result<Value, ShellError> {
+    if data.trim().is_empty() {
+        return Ok(Value::List { vals: vec![], span });
+    }
+
+    let result: Result<nu_json::Value, nu_json::Error> = nu_json::from_str(data);
+    match result {
+        Ok(value) => Ok(convert_nujson_to_value(&value, span)),
+        Err(x) => Err(ShellError::CantConvert(
+            format!("structured json data ({})", x),
+            "string".into(),
+            span,
+            None,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_json_rows() {
+        let span = Span::test_data();
+        let data = r#"[{"region":"la","user":955}]"#;
+
+        let expected = Value::List {
+            vals: vec![Value::Record {
+                cols: vec!["region".to_string(), "user".to_string()],
+                vals: vec![Value::test_string("la"), Value::Int { val: 955, span }],
+                span,
+            }],
+            span,
+        };
+
+        assert_eq!(from_json_data(data, span).unwrap(), expected);
+    }
+
+    #[test]
+    fn parses_empty_result() {
+        let span = Span::test_data();
+
+        assert_eq!(
+            from_json_data("", span).unwrap(),
+            Value::List { vals: vec![], span }
+        );
+    }
+}
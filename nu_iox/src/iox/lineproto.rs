@@ -0,0 +1,411 @@
+use nu_protocol::{ShellError, Span, Spanned, Value};
+
+/// Checks line protocol before it is sent to Iox
+///
+/// Every line needs a measurement and at least one field, and an optional
+/// timestamp has to be an integer. All offending lines are collected into a
+/// single error so they can be fixed in one go, blank lines and comments are
+/// skipped.
+pub fn validate_line_protocol(lp_data: &Spanned<String>) -> Result<(), ShellError> {
+    let problems = invalid_lines(&lp_data.item);
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let line_numbers: Vec<String> = problems.iter().map(|(n, _)| n.to_string()).collect();
+    let details = problems
+        .iter()
+        .map(|(n, problem)| {
+            ShellError::GenericError(
+                format!("line {}: {}", n, problem),
+                problem.to_string(),
+                None,
+                None,
+                Vec::new(),
+            )
+        })
+        .collect();
+
+    Err(ShellError::GenericError(
+        "Invalid line protocol".into(),
+        format!("malformed line(s): {}", line_numbers.join(", ")),
+        Some(lp_data.span),
+        Some("Fix the lines or pass --skip-validation to send them anyway".into()),
+        details,
+    ))
+}
+
+/// The 1-based numbers of the malformed lines and why each is malformed
+pub fn invalid_lines(lp_data: &str) -> Vec<(usize, String)> {
+    lp_data
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| validate_line(line).err().map(|e| (index + 1, e)))
+        .collect()
+}
+
+/// Counts the lines that carry points, blank lines and comments don't
+pub fn count_points_lines(lp_data: &str) -> usize {
+    lp_data
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .count()
+}
+
+/// Checks a single line, returning why it is malformed
+pub fn validate_line(line: &str) -> Result<(), String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+
+    let sections = split_unescaped(line, ' ');
+    let (series, fields, timestamp) = match sections.as_slice() {
+        [series] => (*series, "", None),
+        [series, fields] => (*series, *fields, None),
+        [series, fields, timestamp] => (*series, *fields, Some(*timestamp)),
+        _ => return Err("unexpected text after the timestamp".into()),
+    };
+
+    let measurement = split_unescaped(series, ',')[0];
+    if measurement.is_empty() {
+        return Err("missing measurement".into());
+    }
+
+    if fields.is_empty() {
+        return Err("missing fields, at least one field=value is required".into());
+    }
+
+    for field in split_unescaped(fields, ',') {
+        match field.split_once('=') {
+            Some((key, value)) if !key.is_empty() && !value.is_empty() => {}
+            _ => return Err(format!("invalid field '{}', expected field=value", field)),
+        }
+    }
+
+    if let Some(timestamp) = timestamp {
+        if timestamp.parse::<i64>().is_err() {
+            return Err(format!(
+                "invalid timestamp '{}', expected an integer",
+                timestamp
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates the line protocol and, on a dry run, summarizes it instead of writing
+///
+/// The write closure only runs when this is not a dry run, and its value is
+/// returned. A dry run returns a record of the line count and the
+/// measurements the data would touch instead.
+pub fn write_unless_dry_run<F>(
+    lp_data: &Spanned<String>,
+    dbname: &str,
+    dry_run: bool,
+    span: Span,
+    write: F,
+) -> Result<Value, ShellError>
+where
+    F: FnOnce() -> Result<Value, ShellError>,
+{
+    if !dry_run {
+        return write();
+    }
+
+    validate_line_protocol(lp_data)?;
+
+    let lines = count_points_lines(&lp_data.item);
+    let mut measurements: Vec<String> = vec![];
+    for line in lp_data.item.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let series = split_unescaped(line, ' ')[0];
+        let measurement = split_unescaped(series, ',')[0].to_string();
+        if !measurements.contains(&measurement) {
+            measurements.push(measurement);
+        }
+    }
+    measurements.sort();
+
+    Ok(Value::Record {
+        cols: vec![
+            "dbname".to_string(),
+            "lines".to_string(),
+            "measurements".to_string(),
+        ],
+        vals: vec![
+            Value::String {
+                val: dbname.to_string(),
+                span,
+            },
+            Value::Int {
+                val: lines as i64,
+                span,
+            },
+            Value::List {
+                vals: measurements
+                    .into_iter()
+                    .map(|val| Value::String { val, span })
+                    .collect(),
+                span,
+            },
+        ],
+        span,
+    })
+}
+
+/// The unit of the timestamps in line protocol, nanoseconds unless told otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision::Nanoseconds
+    }
+}
+
+impl Precision {
+    /// Nanoseconds in one unit of this precision
+    pub fn nanos_per_unit(self) -> i64 {
+        match self {
+            Precision::Nanoseconds => 1,
+            Precision::Microseconds => 1_000,
+            Precision::Milliseconds => 1_000_000,
+            Precision::Seconds => 1_000_000_000,
+        }
+    }
+}
+
+/// Parses the --precision flag, one of ns, us, ms or s
+pub fn precision_from_flag(precision: Option<Spanned<String>>) -> Result<Precision, ShellError> {
+    match precision {
+        None => Ok(Precision::default()),
+        Some(Spanned { item, span }) => match item.as_str() {
+            "ns" => Ok(Precision::Nanoseconds),
+            "us" => Ok(Precision::Microseconds),
+            "ms" => Ok(Precision::Milliseconds),
+            "s" => Ok(Precision::Seconds),
+            _ => Err(ShellError::UnsupportedInput(
+                format!(
+                    "unknown precision '{}', expected one of ns, us, ms or s",
+                    item
+                ),
+                span,
+            )),
+        },
+    }
+}
+
+/// Rewrites the timestamps of the line protocol from the precision to nanoseconds
+///
+/// The Iox write API only takes nanoseconds, so this is how the precision
+/// reaches the server. Lines without a timestamp, blank lines, comments and
+/// lines whose timestamp isn't an integer are passed through untouched.
+pub fn timestamps_to_nanos(lp_data: &str, precision: Precision) -> Result<String, String> {
+    if precision == Precision::Nanoseconds {
+        return Ok(lp_data.to_string());
+    }
+
+    let mut converted = String::with_capacity(lp_data.len());
+    for (index, line) in lp_data.split_inclusive('\n').enumerate() {
+        let content = line.trim_end();
+        let trimmed = content.trim_start();
+
+        let sections = split_unescaped(trimmed, ' ');
+        let timestamp = match sections.as_slice() {
+            [_, _, timestamp] if !trimmed.starts_with('#') => timestamp.parse::<i64>().ok(),
+            _ => None,
+        };
+
+        match timestamp {
+            Some(timestamp) => {
+                let nanos = timestamp
+                    .checked_mul(precision.nanos_per_unit())
+                    .ok_or_else(|| {
+                        format!(
+                            "line {}: timestamp {} is out of range in nanoseconds",
+                            index + 1,
+                            timestamp
+                        )
+                    })?;
+                let prefix = &content[..content.rfind(' ').unwrap_or_default()];
+                converted.push_str(prefix);
+                converted.push(' ');
+                converted.push_str(&nanos.to_string());
+                converted.push_str(&line[content.len()..]);
+            }
+            None => converted.push_str(line),
+        }
+    }
+
+    Ok(converted)
+}
+
+// Splits on the separator unless it is escaped with a backslash or inside
+// a double quoted string field value
+fn split_unescaped(data: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+
+    for (index, c) in data.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&data[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&data[start..]);
+
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spanned(item: &str) -> Spanned<String> {
+        Spanned {
+            item: item.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_lines() {
+        assert!(validate_line("cpu,region=la user=955 222522").is_ok());
+        assert!(validate_line("cpu user=955,system=0.5").is_ok());
+        assert!(validate_line(r#"logs,host=a msg="disk full, again" 1"#).is_ok());
+        assert!(validate_line(r"my\ cpu,region=north\ la user=1i -5").is_ok());
+        assert!(validate_line("# a comment").is_ok());
+        assert!(validate_line("").is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_fields() {
+        assert!(validate_line("cpu,region=la").is_err());
+        assert!(validate_line("cpu user= 222522").is_err());
+        assert!(validate_line("cpu =1 222522").is_err());
+    }
+
+    #[test]
+    fn rejects_missing_measurement() {
+        assert!(validate_line(",region=la user=1").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_timestamps() {
+        assert!(validate_line("cpu user=1 yesterday").is_err());
+        assert!(validate_line("cpu user=1 2022-07-01T00:00:00Z").is_err());
+        assert!(validate_line("cpu user=1 1 2").is_err());
+    }
+
+    #[test]
+    fn dry_run_summarizes_without_writing() {
+        let lp = spanned("# cpu and mem\ncpu,region=la user=1 1\n\nmem free=2 1\ncpu user=3 2\n");
+        let span = Span::test_data();
+
+        let summary = write_unless_dry_run(&lp, "bananas", true, span, || {
+            panic!("a dry run must not write")
+        })
+        .unwrap();
+
+        assert_eq!(
+            summary.get_data_by_key("lines"),
+            Some(Value::Int { val: 3, span })
+        );
+        assert_eq!(
+            summary.get_data_by_key("measurements"),
+            Some(Value::List {
+                vals: vec![Value::test_string("cpu"), Value::test_string("mem")],
+                span
+            })
+        );
+    }
+
+    #[test]
+    fn dry_run_still_rejects_bad_lines() {
+        let lp = spanned("cpu user=1 1\ncpu\n");
+
+        let result = write_unless_dry_run(&lp, "bananas", true, Span::test_data(), || {
+            panic!("a dry run must not write")
+        });
+
+        assert!(matches!(result, Err(ShellError::GenericError(..))));
+    }
+
+    #[test]
+    fn writes_when_not_a_dry_run() {
+        let lp = spanned("cpu user=1 1\n");
+        let mut written = false;
+
+        let summary = write_unless_dry_run(&lp, "bananas", false, Span::test_data(), || {
+            written = true;
+            Ok(Value::test_string("written"))
+        })
+        .unwrap();
+
+        assert!(written);
+        assert_eq!(summary, Value::test_string("written"));
+    }
+
+    #[test]
+    fn precision_must_be_a_known_unit() {
+        assert_eq!(precision_from_flag(None).unwrap(), Precision::Nanoseconds);
+        assert_eq!(
+            precision_from_flag(Some(spanned("ms"))).unwrap(),
+            Precision::Milliseconds
+        );
+        assert!(precision_from_flag(Some(spanned("h"))).is_err());
+    }
+
+    #[test]
+    fn timestamps_are_scaled_to_nanoseconds() {
+        let lp = "# comment\ncpu user=1 1660000000\n\ncpu,host=a\\ b user=2\r\ncpu user=3 7\n";
+
+        let converted = timestamps_to_nanos(lp, Precision::Seconds).unwrap();
+
+        assert_eq!(
+            converted,
+            "# comment\ncpu user=1 1660000000000000000\n\ncpu,host=a\\ b user=2\r\ncpu user=3 7000000000\n"
+        );
+        assert_eq!(timestamps_to_nanos(lp, Precision::Nanoseconds).unwrap(), lp);
+    }
+
+    #[test]
+    fn out_of_range_timestamps_are_an_error() {
+        let lp = format!("cpu user=1 {}\n", i64::MAX);
+
+        assert!(timestamps_to_nanos(&lp, Precision::Microseconds).is_err());
+    }
+
+    #[test]
+    fn reports_every_offending_line() {
+        let lp = spanned("cpu user=1 1\ncpu\ncpu user=2 2\ncpu user=3 later\n");
+
+        match validate_line_protocol(&lp) {
+            Err(ShellError::GenericError(_, msg, _, _, details)) => {
+                assert_eq!(msg, "malformed line(s): 2, 4");
+                assert_eq!(details.len(), 2);
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use super::schema::{run_catalog_query, with_catalog_flags};
+use super::tables::{name_filter_from_flags, table_names, tables_sql};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Example, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct Ioxmeasurements;
+
+impl Command for Ioxmeasurements {
+    fn name(&self) -> &str {
+        "ioxmeasurements"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        with_catalog_flags(
+            Signature::build("ioxmeasurements")
+                .named(
+                    "pattern",
+                    SyntaxShape::String,
+                    "only list the measurements whose name matches this glob, e.g. cpu*",
+                    Some('p'),
+                )
+                .named(
+                    "filter",
+                    SyntaxShape::String,
+                    "only list the measurements whose name matches this glob, or this regex between slashes, e.g. /^cpu_\\d+$/",
+                    None,
+                ),
+        )
+    }
+
+    fn usage(&self) -> &str {
+        "List the measurements of an Iox Database, like SHOW MEASUREMENTS."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Every Iox table is a measurement, the names are read from information_schema.tables and sorted by name."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let filter = name_filter_from_flags(
+            call.get_flag(engine_state, stack, "pattern")?,
+            call.get_flag(engine_state, stack, "filter")?,
+        )?;
+
+        let batches = run_catalog_query(engine_state, stack, call, tables_sql())?;
+        let names = table_names(&batches, filter.as_ref(), call.head)?;
+
+        Ok(Value::List {
+            vals: names,
+            span: call.head,
+        }
+        .into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "List the measurements of the default database",
+                example: r#"ioxmeasurements"#,
+                result: None,
+            },
+            Example {
+                description: "List the cpu measurements of the bananas database",
+                example: r#"ioxmeasurements -d bananas --pattern 'cpu*'"#,
+                result: None,
+            },
+            Example {
+                description: "List the measurements of the disk and mem metrics, with a regex",
+                example: r#"ioxmeasurements --filter '/^(disk|mem)/'"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::{ArrayRef, StringArray};
+    use arrow::record_batch::RecordBatch;
+    use nu_protocol::Span;
+    use std::sync::Arc;
+
+    #[test]
+    fn measurements_are_the_table_names() {
+        let batch = RecordBatch::try_from_iter(vec![(
+            "table_name",
+            Arc::new(StringArray::from(vec!["cpu", "disk"])) as ArrayRef,
+        )])
+        .unwrap();
+
+        let measurements = table_names(&[batch], None, Span::test_data()).unwrap();
+
+        assert_eq!(
+            measurements,
+            vec![Value::test_string("cpu"), Value::test_string("disk")]
+        );
+    }
+}
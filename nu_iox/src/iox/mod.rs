@@ -1,17 +1,61 @@
+mod convert;
+mod dbnames;
 mod delimited;
+mod export;
+mod fieldkeys;
+#[cfg(feature = "iox-flightsql")]
+mod flightsql;
+#[cfg(feature = "iox-influxql")]
+mod influxql;
+mod json;
+mod lineproto;
+mod measurements;
 mod namespace;
+mod namespacecreate;
+mod namespacedelete;
+mod ndjson;
 mod nuclient;
 mod nuerror;
+mod ping;
+mod schema;
 mod sql;
+mod sqllocal;
+mod tables;
+mod tagkeys;
+mod tls;
+mod use_;
 mod util;
+mod version;
 mod write;
 mod writefile;
+mod writetable;
 
+pub use convert::*;
+pub use dbnames::{dbname_shape, set_dbname_completer, Ioxdbnames};
 pub use delimited::*;
+pub use export::*;
+pub use fieldkeys::Ioxfieldkeys;
+#[cfg(feature = "iox-influxql")]
+pub use influxql::Ioxinfluxql;
+pub use json::*;
+pub use lineproto::*;
+pub use measurements::Ioxmeasurements;
 pub use namespace::Ioxnamespace;
+pub use namespacecreate::Ioxnamespacecreate;
+pub use namespacedelete::Ioxnamespacedelete;
+pub use ndjson::*;
 pub use nuclient::*;
 pub use nuerror::*;
+pub use ping::Ioxping;
+pub use schema::Ioxschema;
 pub use sql::Ioxsql;
+pub use sqllocal::Ioxsqllocal;
+pub use tables::Ioxtables;
+pub use tagkeys::Ioxtagkeys;
+pub use tls::*;
+pub use use_::Ioxuse;
 pub use util::*;
+pub use version::Ioxversion;
 pub use write::Ioxwrite;
 pub use writefile::Ioxwritefile;
+pub use writetable::Ioxwritetable;
@@ -1,10 +1,18 @@
-use super::delimited::from_delimited_data;
-use super::util::get_runtime;
+use super::nuclient::NamespaceInfo;
+use super::sql::limit_from_flag;
+use super::util::{
+    block_on_iox, block_on_iox_with_threads, connect_error, forget_connection_on_error,
+    headers_from_value, iox_connector, num_threads_from_flag, resolve_iox_keepalive,
+    resolve_iox_profile, resolve_iox_tls_ca, retries_from_flag, validate_iox_addr, IoxConnector,
+    DEFAULT_IOX_ADDR,
+};
+use nu_engine::CallExt;
 use nu_protocol::ast::Call;
 use nu_protocol::engine::{Command, EngineState, Stack};
-use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, Span, Value};
-
-use csv::Trim;
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, Spanned,
+    SyntaxShape, Value,
+};
 
 #[derive(Clone)]
 pub struct Ioxnamespace;
@@ -15,7 +23,65 @@ impl Command for Ioxnamespace {
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build("ioxnamespace").category(Category::Filters)
+        Signature::build("ioxnamespace")
+            .named(
+                "addr",
+                SyntaxShape::String,
+                format!(
+                    "address of the Iox server, defaults to IOX_ADDR or {}",
+                    DEFAULT_IOX_ADDR
+                ),
+                Some('a'),
+            )
+            .named(
+                "retries",
+                SyntaxShape::Int,
+                "retries for an unavailable server, with exponential backoff, defaults to 3",
+                None,
+            )
+            .named(
+                "num-threads",
+                SyntaxShape::Int,
+                "worker threads for this command's own runtime instead of the shared one",
+                None,
+            )
+            .named(
+                "limit",
+                SyntaxShape::Int,
+                "show at most this many namespaces",
+                Some('l'),
+            )
+            .named(
+                "offset",
+                SyntaxShape::Int,
+                "skip this many namespaces first",
+                None,
+            )
+            .named(
+                "sort-by",
+                SyntaxShape::String,
+                "order the namespaces by 'id' (default) or 'name'",
+                None,
+            )
+            .named(
+                "header",
+                SyntaxShape::Any,
+                "metadata header sent with every request as key=value, or a list of them, e.g. x-tenant-id=bananas",
+                None,
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
     }
 
     fn usage(&self) -> &str {
@@ -25,58 +91,323 @@ impl Command for Ioxnamespace {
     fn run(
         &self,
         engine_state: &EngineState,
-        _stack: &mut Stack,
+        stack: &mut Stack,
         call: &Call,
         _input: PipelineData,
     ) -> Result<PipelineData, ShellError> {
-        let namespace_result = tokio_block_namespace();
+        let addr_flag: Option<Spanned<String>> = call.get_flag(engine_state, stack, "addr")?;
 
-        let no_infer = false;
-        let noheaders = false;
-        let separator: char = ',';
-        let trim = Trim::None;
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr_span = addr_flag.as_ref().map_or(call.head, |a| a.span);
+        let addr = profile.resolve_addr(stack, engine_state, addr_flag.map(|a| a.item));
+        let addr = validate_iox_addr(&addr, addr_span)?;
 
-        let input = PipelineData::Value(
-            Value::String {
-                val: namespace_result.unwrap(),
-                span: call.head,
-            },
-            None,
-        );
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+        let headers = headers_from_value(call.get_flag(engine_state, stack, "header")?)?;
+        let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+            .with_keepalive(keepalive)
+            .with_headers(headers);
+
+        let num_threads =
+            num_threads_from_flag(call.get_flag(engine_state, stack, "num-threads")?)?;
 
-        let name = Span::new(0, 0);
-        let config = engine_state.get_config();
+        let retries = retries_from_flag(call.get_flag(engine_state, stack, "retries")?)?;
 
-        from_delimited_data(noheaders, no_infer, separator, trim, input, name, config)
+        let limit = limit_from_flag(call.get_flag(engine_state, stack, "limit")?)?;
+        let offset = offset_from_flag(call.get_flag(engine_state, stack, "offset")?)?;
+        let sort = namespace_sort_from_str(call.get_flag(engine_state, stack, "sort-by")?)?;
+
+        let namespaces = tokio_block_namespace(connector, &addr, retries, num_threads)?;
+        let namespaces = page_namespaces(namespaces, sort, offset, limit);
+
+        Ok(namespaces_to_value(&namespaces, call.head).into_pipeline_data())
     }
 
     fn examples(&self) -> Vec<Example> {
-        vec![Example {
-            description: "Show the databases or namespaces",
-            example: r#"ioxnamespace"#,
-            result: None,
-        }]
+        vec![
+            Example {
+                description: "Show the databases or namespaces",
+                example: r#"ioxnamespace"#,
+                result: None,
+            },
+            Example {
+                description: "Show the second page of ten databases in name order",
+                example: r#"ioxnamespace --sort-by name --offset 10 --limit 10"#,
+                result: None,
+            },
+            Example {
+                description: "Show the databases of a remote Iox server",
+                example: r#"ioxnamespace -a http://iox.example.com:8082"#,
+                result: None,
+            },
+            Example {
+                description: "Show the databases using a single worker thread",
+                example: r#"ioxnamespace --num-threads 1"#,
+                result: None,
+            },
+        ]
     }
 }
 
-pub fn tokio_block_namespace() -> Result<String, std::io::Error> {
-    use crate::iox::Nuclient;
-    use influxdb_iox_client::connection::Builder;
-    let num_threads: Option<usize> = None;
-    let tokio_runtime = get_runtime(num_threads)?;
+/// How `ioxnamespace` orders the namespaces
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NamespaceSort {
+    Id,
+    Name,
+}
+
+pub fn namespace_sort_from_str(sort: Option<Spanned<String>>) -> Result<NamespaceSort, ShellError> {
+    match sort {
+        None => Ok(NamespaceSort::Id),
+        Some(Spanned { item, span }) => match item.as_str() {
+            "id" => Ok(NamespaceSort::Id),
+            "name" => Ok(NamespaceSort::Name),
+            _ => Err(ShellError::UnsupportedInput(
+                "the only possible values for sort-by are 'id' and 'name'".into(),
+                span,
+            )),
+        },
+    }
+}
+
+pub fn offset_from_flag(offset: Option<Spanned<i64>>) -> Result<usize, ShellError> {
+    match offset {
+        None => Ok(0),
+        Some(Spanned { item, span }) if item < 0 => Err(ShellError::UnsupportedInput(
+            format!("--offset can't be negative, got {}", item),
+            span,
+        )),
+        Some(Spanned { item, .. }) => Ok(item as usize),
+    }
+}
+
+/// Sorts the namespaces and keeps the requested page of them
+///
+/// The slicing happens after the whole list was retrieved, the namespace
+/// API has no paging of its own.
+pub fn page_namespaces(
+    mut namespaces: Vec<NamespaceInfo>,
+    sort: NamespaceSort,
+    offset: usize,
+    limit: Option<usize>,
+) -> Vec<NamespaceInfo> {
+    match sort {
+        NamespaceSort::Id => namespaces.sort_by_key(|ns| ns.id),
+        NamespaceSort::Name => namespaces.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+
+    namespaces
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// Converts the namespaces into a table of id, name and retention
+///
+/// The retention is a duration, or nothing for namespaces kept forever.
+pub fn namespaces_to_value(namespaces: &[NamespaceInfo], span: Span) -> Value {
+    let vals = namespaces
+        .iter()
+        .map(|ns| Value::Record {
+            cols: vec![
+                "namespace_id".to_string(),
+                "name".to_string(),
+                "retention".to_string(),
+            ],
+            vals: vec![
+                Value::Int { val: ns.id, span },
+                Value::String {
+                    val: ns.name.clone(),
+                    span,
+                },
+                match ns.retention_period_ns {
+                    Some(val) => Value::Duration { val, span },
+                    None => Value::Nothing { span },
+                },
+            ],
+            span,
+        })
+        .collect();
+
+    Value::List { vals, span }
+}
 
-    let namespace = tokio_runtime.block_on(async move {
-        let connection = Builder::default()
-            .build("http://127.0.0.1:8082")
+pub fn tokio_block_namespace(
+    connector: IoxConnector,
+    addr: &str,
+    retries: usize,
+    num_threads: Option<usize>,
+) -> Result<Vec<NamespaceInfo>, ShellError> {
+    use crate::iox::Nuclient;
+    let connector = connector.for_num_threads(num_threads);
+    let namespace = block_on_iox_with_threads(num_threads, async move {
+        let span = connector.span();
+        let mut repl = Nuclient::connect(addr, connector)
             .await
-            .expect("client should be valid");
+            .map_err(|e| connect_error(addr, e, span))?;
+        repl.set_retries(retries);
+
+        forget_connection_on_error(addr, repl.list_namespaces().await).map_err(|e| {
+            ShellError::GenericError(
+                "Failed to list the Iox databases".into(),
+                e.to_string(),
+                None,
+                None,
+                Vec::new(),
+            )
+        })
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?;
+
+    namespace
+}
+
+pub fn tokio_block_namespace_names(
+    connector: IoxConnector,
+    addr: &str,
+) -> Result<Result<Vec<String>, super::nuclient::Error>, ShellError> {
+    use crate::iox::Nuclient;
+
+    let names = block_on_iox(async move {
+        let connection = connector.connect(addr).await?;
 
         let mut repl = Nuclient::new(connection);
-        let _output_format = repl.set_output_format("csv");
+        Ok::<_, ShellError>(forget_connection_on_error(
+            addr,
+            repl.list_namespace_names().await,
+        ))
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?;
 
-        let namespace = repl.list_namespaces().await.expect("namespaces");
-        namespace
-    });
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stopped_server_is_an_error_not_a_panic() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+
+        match tokio_block_namespace(connector, &addr, 0, None) {
+            Err(ShellError::GenericError(msg, ..)) => assert!(msg.contains(&addr)),
+            other => panic!("expected a connection error, got {:?}", other),
+        }
+    }
+
+    fn namespace(id: i64, name: &str) -> NamespaceInfo {
+        NamespaceInfo {
+            id,
+            name: name.to_string(),
+            retention_period_ns: None,
+        }
+    }
+
+    fn ids(namespaces: &[NamespaceInfo]) -> Vec<i64> {
+        namespaces.iter().map(|ns| ns.id).collect()
+    }
+
+    fn test_namespaces() -> Vec<NamespaceInfo> {
+        vec![
+            namespace(3, "apples"),
+            namespace(1, "cherries"),
+            namespace(4, "bananas"),
+            namespace(2, "dates"),
+        ]
+    }
+
+    #[test]
+    fn namespaces_default_to_all_sorted_by_id() {
+        let paged = page_namespaces(test_namespaces(), NamespaceSort::Id, 0, None);
+
+        assert_eq!(ids(&paged), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn namespaces_sort_by_name() {
+        let paged = page_namespaces(test_namespaces(), NamespaceSort::Name, 0, None);
 
-    Ok(namespace)
+        assert_eq!(ids(&paged), vec![3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn namespaces_slice_with_offset_and_limit() {
+        let page = |offset, limit| {
+            ids(&page_namespaces(
+                test_namespaces(),
+                NamespaceSort::Id,
+                offset,
+                limit,
+            ))
+        };
+
+        assert_eq!(page(1, Some(2)), vec![2, 3]);
+        assert_eq!(page(3, Some(2)), vec![4]);
+        assert_eq!(page(0, Some(0)), Vec::<i64>::new());
+        assert_eq!(page(10, None), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn rejects_unknown_sort_and_negative_offset() {
+        let span = Span::test_data();
+
+        assert!(namespace_sort_from_str(Some(Spanned {
+            item: "retention".to_string(),
+            span
+        }))
+        .is_err());
+        assert!(offset_from_flag(Some(Spanned { item: -1, span })).is_err());
+        assert_eq!(offset_from_flag(None).unwrap(), 0);
+    }
+
+    #[test]
+    fn namespace_ids_are_integers() {
+        let span = Span::test_data();
+        let namespaces = vec![
+            NamespaceInfo {
+                id: 1,
+                name: "bananas".to_string(),
+                retention_period_ns: None,
+            },
+            NamespaceInfo {
+                id: 2,
+                name: "apples".to_string(),
+                retention_period_ns: Some(3_600_000_000_000),
+            },
+        ];
+
+        let rows = match namespaces_to_value(&namespaces, span) {
+            Value::List { vals, .. } => vals,
+            _ => panic!("expected a list"),
+        };
+
+        assert_eq!(
+            rows[0].get_data_by_key("namespace_id"),
+            Some(Value::Int { val: 1, span })
+        );
+        assert_eq!(
+            rows[0].get_data_by_key("retention"),
+            Some(Value::Nothing { span })
+        );
+        assert_eq!(
+            rows[1].get_data_by_key("retention"),
+            Some(Value::Duration {
+                val: 3_600_000_000_000,
+                span
+            })
+        );
+    }
 }
@@ -0,0 +1,387 @@
+use super::nuclient::{Error as NuclientError, NamespaceInfo, Nuclient};
+use super::util::{
+    block_on_iox, iox_connector, resolve_iox_keepalive, resolve_iox_profile, resolve_iox_tls_ca,
+    validate_iox_addr, IoxConnector,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape, Value,
+};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Longest namespace name accepted by the Iox server
+const MAX_NAMESPACE_NAME_LENGTH: usize = 64;
+
+#[derive(Clone)]
+pub struct Ioxnamespacecreate;
+
+impl Command for Ioxnamespacecreate {
+    fn name(&self) -> &str {
+        "ioxnamespacecreate"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxnamespacecreate")
+            .required(
+                "name",
+                SyntaxShape::String,
+                "name of the namespace to create",
+            )
+            .named(
+                "retention",
+                SyntaxShape::Duration,
+                "how long data is retained, defaults to forever",
+                Some('r'),
+            )
+            .switch(
+                "if-not-exists",
+                "return the namespace when it already exists instead of failing",
+                None,
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "Create an Iox Database."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let name: Spanned<String> = call.req(engine_state, stack, 0)?;
+        let retention: Option<Value> = call.get_flag(engine_state, stack, "retention")?;
+
+        validate_namespace_name(&name)?;
+        let retention_period_ns = match retention {
+            Some(Value::Duration { val, .. }) if val > 0 => Some(val),
+            Some(Value::Duration { span, .. }) => {
+                return Err(ShellError::UnsupportedInput(
+                    "the retention period must be greater than zero".into(),
+                    span,
+                ))
+            }
+            _ => None,
+        };
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr = profile.resolve_addr(stack, engine_state, None);
+        let addr = validate_iox_addr(&addr, call.head)?;
+
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+        let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+            .with_keepalive(keepalive);
+
+        let (namespace_id, namespace_name, created) = tokio_block_namespace_create(
+            connector,
+            &addr,
+            &name.item,
+            retention_period_ns,
+            call.has_flag("if-not-exists"),
+        )?
+        .map_err(|e| create_error(e, &name))?;
+
+        Ok(PipelineData::Value(
+            namespace_to_value(namespace_id, namespace_name, created, call.head),
+            None,
+        ))
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Create the bananas database",
+                example: r#"ioxnamespacecreate bananas"#,
+                result: None,
+            },
+            Example {
+                description: "Create the bananas database keeping data for 30 days",
+                example: r#"ioxnamespacecreate bananas --retention 30day"#,
+                result: None,
+            },
+            Example {
+                description:
+                    "Create the bananas database unless it is already there, e.g. in a setup script",
+                example: r#"ioxnamespacecreate bananas --if-not-exists"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+fn namespace_to_value(namespace_id: i64, name: String, created: bool, span: Span) -> Value {
+    Value::Record {
+        cols: vec![
+            "namespace_id".to_string(),
+            "name".to_string(),
+            "created".to_string(),
+        ],
+        vals: vec![
+            Value::Int {
+                val: namespace_id,
+                span,
+            },
+            Value::String { val: name, span },
+            Value::Bool { val: created, span },
+        ],
+        span,
+    }
+}
+
+// Namespace names are limited to ascii letters, digits, '_' and '-'
+pub fn validate_namespace_name(name: &Spanned<String>) -> Result<(), ShellError> {
+    let valid = !name.item.is_empty()
+        && name.item.len() <= MAX_NAMESPACE_NAME_LENGTH
+        && name
+            .item
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ShellError::UnsupportedInput(
+            format!(
+                "Invalid namespace name '{}': use 1 to {} ascii letters, digits, '_' or '-'",
+                name.item, MAX_NAMESPACE_NAME_LENGTH
+            ),
+            name.span,
+        ))
+    }
+}
+
+fn create_error(error: NuclientError, name: &Spanned<String>) -> ShellError {
+    let msg = error.to_string();
+
+    if msg.to_lowercase().contains("already exists") {
+        ShellError::GenericError(
+            format!("Namespace '{}' already exists", name.item),
+            "namespace already exists".into(),
+            Some(name.span),
+            None,
+            Vec::new(),
+        )
+    } else {
+        ShellError::GenericError(
+            "Failed to create the Iox namespace".into(),
+            msg,
+            Some(name.span),
+            None,
+            Vec::new(),
+        )
+    }
+}
+
+pub type NamespaceFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, NuclientError>> + 'a>>;
+
+/// The namespace calls creating a namespace makes
+pub trait NamespaceCreator {
+    fn list_namespaces(&mut self) -> NamespaceFuture<'_, Vec<NamespaceInfo>>;
+
+    fn create_namespace<'a>(
+        &'a mut self,
+        name: &'a str,
+        retention_period_ns: Option<i64>,
+    ) -> NamespaceFuture<'a, (i64, String)>;
+}
+
+impl NamespaceCreator for Nuclient {
+    fn list_namespaces(&mut self) -> NamespaceFuture<'_, Vec<NamespaceInfo>> {
+        Box::pin(Nuclient::list_namespaces(self))
+    }
+
+    fn create_namespace<'a>(
+        &'a mut self,
+        name: &'a str,
+        retention_period_ns: Option<i64>,
+    ) -> NamespaceFuture<'a, (i64, String)> {
+        Box::pin(Nuclient::create_namespace(self, name, retention_period_ns))
+    }
+}
+
+/// Creates the namespace, unless if_not_exists finds one by that name already
+///
+/// Returns the id and name of the namespace and whether it was created. An
+/// existing namespace is returned as it is, whatever its retention period.
+pub async fn create_namespace_unless_exists<C: NamespaceCreator>(
+    client: &mut C,
+    name: &str,
+    retention_period_ns: Option<i64>,
+    if_not_exists: bool,
+) -> Result<(i64, String, bool), NuclientError> {
+    if if_not_exists {
+        let namespaces = client.list_namespaces().await?;
+        if let Some(existing) = namespaces.into_iter().find(|ns| ns.name == name) {
+            return Ok((existing.id, existing.name, false));
+        }
+    }
+
+    let (namespace_id, name) = client.create_namespace(name, retention_period_ns).await?;
+
+    Ok((namespace_id, name, true))
+}
+
+pub fn tokio_block_namespace_create(
+    connector: IoxConnector,
+    addr: &str,
+    name: &str,
+    retention_period_ns: Option<i64>,
+    if_not_exists: bool,
+) -> Result<Result<(i64, String, bool), NuclientError>, ShellError> {
+    let namespace = block_on_iox(async move {
+        let connection = connector.connect(addr).await?;
+
+        let mut repl = Nuclient::new(connection);
+        Ok::<_, ShellError>(
+            create_namespace_unless_exists(&mut repl, name, retention_period_ns, if_not_exists)
+                .await,
+        )
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?;
+
+    namespace
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn name(name: &str) -> Spanned<String> {
+        Spanned {
+            item: name.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_namespace_names() {
+        assert!(validate_namespace_name(&name("bananas")).is_ok());
+        assert!(validate_namespace_name(&name("my_org-bucket2")).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_namespace_names() {
+        assert!(validate_namespace_name(&name("")).is_err());
+        assert!(validate_namespace_name(&name("has space")).is_err());
+        assert!(validate_namespace_name(&name("semi;colon")).is_err());
+        assert!(validate_namespace_name(&name(&"x".repeat(65))).is_err());
+    }
+
+    #[test]
+    fn created_namespace_is_a_typed_record() {
+        let span = Span::test_data();
+
+        let expected = Value::Record {
+            cols: vec![
+                "namespace_id".to_string(),
+                "name".to_string(),
+                "created".to_string(),
+            ],
+            vals: vec![
+                Value::Int { val: 7, span },
+                Value::test_string("bananas"),
+                Value::Bool { val: true, span },
+            ],
+            span,
+        };
+
+        assert_eq!(
+            namespace_to_value(7, "bananas".to_string(), true, span),
+            expected
+        );
+    }
+
+    // Lists the given namespaces, creating a new one with the next id
+    struct MockNamespaces {
+        namespaces: Vec<NamespaceInfo>,
+        created: Vec<String>,
+    }
+
+    impl NamespaceCreator for MockNamespaces {
+        fn list_namespaces(&mut self) -> NamespaceFuture<'_, Vec<NamespaceInfo>> {
+            let namespaces = self.namespaces.clone();
+            Box::pin(async move { Ok(namespaces) })
+        }
+
+        fn create_namespace<'a>(
+            &'a mut self,
+            name: &'a str,
+            _retention_period_ns: Option<i64>,
+        ) -> NamespaceFuture<'a, (i64, String)> {
+            self.created.push(name.to_string());
+            let id = (self.namespaces.len() + self.created.len()) as i64;
+            Box::pin(async move { Ok((id, name.to_string())) })
+        }
+    }
+
+    fn create_with(name: &str, if_not_exists: bool) -> ((i64, String, bool), Vec<String>) {
+        let mut client = MockNamespaces {
+            namespaces: vec![NamespaceInfo {
+                id: 1,
+                name: "bananas".to_string(),
+                retention_period_ns: None,
+            }],
+            created: vec![],
+        };
+
+        let namespace = block_on_iox(create_namespace_unless_exists(
+            &mut client,
+            name,
+            None,
+            if_not_exists,
+        ))
+        .unwrap()
+        .unwrap();
+
+        (namespace, client.created)
+    }
+
+    #[test]
+    fn if_not_exists_returns_the_existing_namespace() {
+        let (namespace, created) = create_with("bananas", true);
+
+        assert_eq!(namespace, (1, "bananas".to_string(), false));
+        assert!(created.is_empty());
+    }
+
+    #[test]
+    fn if_not_exists_creates_a_missing_namespace() {
+        let (namespace, created) = create_with("apples", true);
+
+        assert_eq!(namespace, (2, "apples".to_string(), true));
+        assert_eq!(created, vec!["apples"]);
+    }
+
+    #[test]
+    fn without_if_not_exists_the_server_decides() {
+        let (namespace, created) = create_with("bananas", false);
+
+        assert_eq!(namespace, (2, "bananas".to_string(), true));
+        assert_eq!(created, vec!["bananas"]);
+    }
+}
@@ -0,0 +1,169 @@
+use super::util::{
+    block_on_iox, iox_connector, resolve_iox_keepalive, resolve_iox_profile, resolve_iox_tls_ca,
+    validate_iox_addr, IoxConnector,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct Ioxnamespacedelete;
+
+impl Command for Ioxnamespacedelete {
+    fn name(&self) -> &str {
+        "ioxnamespacedelete"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxnamespacedelete")
+            .required(
+                "name",
+                SyntaxShape::String,
+                "name of the namespace to delete",
+            )
+            .switch(
+                "force",
+                "required, confirms the namespace and all of its data should be deleted",
+                Some('f'),
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "Delete an Iox Database."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let name: Spanned<String> = call.req(engine_state, stack, 0)?;
+
+        // nothing is sent to the server unless --force was given
+        check_force(call.has_flag("force"), &name, call.head)?;
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr = profile.resolve_addr(stack, engine_state, None);
+        let addr = validate_iox_addr(&addr, call.head)?;
+
+        let token = profile.resolve_token(stack, engine_state, None);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+        let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+            .with_keepalive(keepalive);
+
+        tokio_block_namespace_delete(connector, &addr, &name.item)?
+            .map_err(|e| delete_error(e, &name))?;
+
+        Ok(PipelineData::Value(
+            Value::String {
+                val: name.item,
+                span: call.head,
+            },
+            None,
+        ))
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            description: "Delete the bananas database and all of its data",
+            example: r#"ioxnamespacedelete bananas --force"#,
+            result: None,
+        }]
+    }
+}
+
+fn check_force(force: bool, name: &Spanned<String>, span: Span) -> Result<(), ShellError> {
+    if force {
+        Ok(())
+    } else {
+        Err(ShellError::GenericError(
+            format!("Refusing to delete namespace '{}'", name.item),
+            "deleting a namespace removes all of its data".into(),
+            Some(span),
+            Some(format!(
+                "Run `ioxnamespacedelete {} --force` to delete it",
+                name.item
+            )),
+            Vec::new(),
+        ))
+    }
+}
+
+fn delete_error(error: super::nuclient::Error, name: &Spanned<String>) -> ShellError {
+    let msg = error.to_string();
+
+    if msg.to_lowercase().contains("not found") {
+        ShellError::GenericError(
+            format!("Namespace '{}' not found", name.item),
+            "no such namespace".into(),
+            Some(name.span),
+            None,
+            Vec::new(),
+        )
+    } else {
+        ShellError::GenericError(
+            "Failed to delete the Iox namespace".into(),
+            msg,
+            Some(name.span),
+            None,
+            Vec::new(),
+        )
+    }
+}
+
+pub fn tokio_block_namespace_delete(
+    connector: IoxConnector,
+    addr: &str,
+    name: &str,
+) -> Result<Result<(), super::nuclient::Error>, ShellError> {
+    use crate::iox::Nuclient;
+
+    let deleted = block_on_iox(async move {
+        let connection = connector.connect(addr).await?;
+
+        let mut repl = Nuclient::new(connection);
+        Ok::<_, ShellError>(repl.delete_namespace(name).await)
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?;
+
+    deleted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn name(name: &str) -> Spanned<String> {
+        Spanned {
+            item: name.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn delete_without_force_is_blocked() {
+        assert!(check_force(false, &name("bananas"), Span::test_data()).is_err());
+    }
+
+    #[test]
+    fn delete_with_force_is_allowed() {
+        assert!(check_force(true, &name("bananas"), Span::test_data()).is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+use arrow::record_batch::RecordBatch;
+use nu_protocol::{ShellError, Span, Value};
+use std::fmt::Write;
+
+use super::convert::batch_to_values;
+
+/// Formats the record batches of a query result as newline delimited json
+///
+/// Every row becomes one json object on its own line, with the columns in
+/// schema order. Nulls are written as null rather than left out, so every
+/// line has the same keys.
+pub fn batches_to_ndjson(batches: &[RecordBatch], span: Span) -> Result<String, ShellError> {
+    let mut ndjson = String::new();
+
+    for batch in batches {
+        for row in batch_to_values(batch, span)? {
+            ndjson.push_str(&record_to_json_line(&row)?);
+            ndjson.push('\n');
+        }
+    }
+
+    Ok(ndjson)
+}
+
+/// Formats a single row record as a json object, without the trailing newline
+pub fn record_to_json_line(row: &Value) -> Result<String, ShellError> {
+    match row {
+        Value::Record { cols, vals, .. } => {
+            let mut line = String::from("{");
+
+            for (i, (col, val)) in cols.iter().zip(vals).enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                line.push_str(&json_string(col));
+                line.push(':');
+                line.push_str(&json_value(val)?);
+            }
+            line.push('}');
+
+            Ok(line)
+        }
+        Value::Error { error } => Err(error.clone()),
+        other => Err(ShellError::UnsupportedInput(
+            format!("expected a row record, got {}", other.get_type()),
+            other.span()?,
+        )),
+    }
+}
+
+// Only the cell types produced by array_value_to_nu_value are expected here
+fn json_value(val: &Value) -> Result<String, ShellError> {
+    Ok(match val {
+        Value::Nothing { .. } => "null".to_string(),
+        Value::Bool { val, .. } => val.to_string(),
+        Value::Int { val, .. } => val.to_string(),
+        // json has no NaN or infinity
+        Value::Float { val, .. } if !val.is_finite() => "null".to_string(),
+        Value::Float { val, .. } => format!("{:?}", val),
+        Value::String { val, .. } => json_string(val),
+        Value::Date { val, .. } => json_string(&val.to_rfc3339()),
+        other => json_string(&other.as_string()?),
+    })
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::TimestampNanosecondArray;
+    use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray};
+    use std::sync::Arc;
+
+    fn mixed_batch() -> RecordBatch {
+        RecordBatch::try_from_iter(vec![
+            (
+                "host",
+                Arc::new(StringArray::from(vec![
+                    Some("a"),
+                    Some("say \"hi\"\n"),
+                    None,
+                ])) as ArrayRef,
+            ),
+            (
+                "user",
+                Arc::new(Int64Array::from(vec![Some(955), None, Some(-1)])) as ArrayRef,
+            ),
+            (
+                "usage",
+                Arc::new(Float64Array::from(vec![Some(0.5), Some(2.0), None])) as ArrayRef,
+            ),
+            (
+                "active",
+                Arc::new(BooleanArray::from(vec![Some(true), None, Some(false)])) as ArrayRef,
+            ),
+            (
+                "time",
+                Arc::new(TimestampNanosecondArray::from(vec![Some(0), None, None])) as ArrayRef,
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn writes_one_typed_object_per_row() {
+        let ndjson = batches_to_ndjson(&[mixed_batch()], Span::test_data()).unwrap();
+
+        let expected = concat!(
+            r#"{"host":"a","user":955,"usage":0.5,"active":true,"time":"1970-01-01T00:00:00+00:00"}"#,
+            "\n",
+            r#"{"host":"say \"hi\"\n","user":null,"usage":2.0,"active":null,"time":null}"#,
+            "\n",
+            r#"{"host":null,"user":-1,"usage":null,"active":false,"time":null}"#,
+            "\n",
+        );
+
+        assert_eq!(ndjson, expected);
+    }
+
+    #[test]
+    fn empty_result_is_empty_output() {
+        assert_eq!(batches_to_ndjson(&[], Span::test_data()).unwrap(), "");
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        assert_eq!(json_string("a\tb\u{1}"), r#""a\tb\u0001""#);
+    }
+}
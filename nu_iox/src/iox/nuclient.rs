@@ -1,28 +1,70 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use arrow::{
-    array::{ArrayRef, Int64Array, StringArray},
+    datatypes::{Schema, SchemaRef},
     record_batch::RecordBatch,
 };
+use lazy_static::lazy_static;
+use nu_protocol::{Span, Spanned};
 use observability_deps::tracing::{debug, info};
 use snafu::{ResultExt, Snafu};
 
+#[cfg(feature = "iox-flightsql")]
+use super::flightsql::FlightSqlQuery;
+use super::util::{block_on_iox, iox_connector, token_hash, IoxConnector};
+
 use influxdb_iox_client::{
-    connection::Connection, flight::generated_types::ReadInfo, format::QueryOutputFormat,
+    connection::Connection,
+    flight::{generated_types::ReadInfo, PerformQuery},
+    format::QueryOutputFormat,
 };
 
 #[derive(Debug, Snafu)]
 pub enum Error {
+    #[snafu(display("Error connecting to Iox: {}", source))]
+    #[snafu(visibility(pub(crate)))]
+    Connecting {
+        addr: String,
+        source: influxdb_iox_client::connection::Error,
+    },
+
+    #[snafu(display("Timed out connecting to Iox at {} after {:?}", addr, timeout))]
+    #[snafu(visibility(pub(crate)))]
+    ConnectTimeout { addr: String, timeout: Duration },
+
     #[snafu(display("Error loading remote state: {}", source))]
     LoadingRemoteState {
         source: Box<dyn std::error::Error + Send + Sync + 'static>,
     },
 
+    #[snafu(display("Error creating namespace '{}': {}", name, source))]
+    CreatingNamespace {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    #[snafu(display("Error deleting namespace '{}': {}", name, source))]
+    DeletingNamespace {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
     #[snafu(display("Error formatting results: {}", source))]
     FormattingResults {
         source: influxdb_iox_client::format::Error,
     },
 
+    #[snafu(display("Error formatting results as ndjson: {}", source))]
+    FormattingNdjson { source: arrow::error::ArrowError },
+
     #[snafu(display("Error setting format to '{}': {}", requested_format, source))]
     SettingFormat {
         requested_format: String,
@@ -32,15 +74,275 @@ pub enum Error {
     #[snafu(display("Error running remote query: {}", source))]
     RunningRemoteQuery {
         source: influxdb_iox_client::flight::Error,
+        /// The gRPC status code, None when the server never answered with a status
+        code: Option<tonic::Code>,
+    },
+
+    #[snafu(display(
+        "Remote query failed after {} batches arrived: {}",
+        batches_received,
+        source
+    ))]
+    RemoteQueryFailedMidStream {
+        source: influxdb_iox_client::flight::Error,
+        code: Option<tonic::Code>,
+        /// The batches that arrived before the failure, they are dropped
+        batches_received: usize,
     },
+
+    #[snafu(display("Error running Flight SQL query: {}", source))]
+    #[snafu(visibility(pub(crate)))]
+    RunningFlightSqlQuery { source: tonic::Status },
+
+    #[snafu(display("Error decoding Flight SQL results: {}", source))]
+    #[snafu(visibility(pub(crate)))]
+    DecodingFlightSql { source: arrow::error::ArrowError },
+
+    #[cfg(feature = "iox-local")]
+    #[snafu(display("Error running local query: {}", source))]
+    RunningLocalQuery {
+        source: datafusion::error::DataFusionError,
+    },
+
+    #[snafu(display("Unsupported local file '{}', expected a .parquet or .csv file", path.display()))]
+    UnsupportedLocalFile { path: PathBuf },
+
+    #[snafu(display("This query needs a remote database to be selected"))]
+    NoRemoteDatabase,
+
+    #[snafu(display("No database selected, run USE DATABASE <dbname> to select one"))]
+    NoDatabaseSelected,
+
+    #[snafu(display("InfluxQL queries need nu-command built with the iox-influxql feature"))]
+    InfluxQlUnavailable,
+
+    #[snafu(display("Flight SQL queries need nu-command built with the iox-flightsql feature"))]
+    FlightSqlUnavailable,
+
+    #[snafu(display("Flight SQL only runs collected SQL queries, use the legacy protocol to stream or for InfluxQL"))]
+    FlightSqlUnsupported,
+
+    #[snafu(display("Query cancelled"))]
+    Cancelled,
+
+    #[snafu(display("Query timed out after {:?}", timeout))]
+    Timeout { timeout: Duration },
+
+    #[snafu(display("Query stalled, no results arrived for {:?}", timeout))]
+    Stalled { timeout: Duration },
+
+    #[snafu(display("Local queries need nu-command built with the iox-local feature"))]
+    LocalQueryUnavailable,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// How often a transient failure is retried when --retries is not given
+pub const DEFAULT_RETRIES: usize = 3;
+
+/// Delay before the first retry, doubled for every further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Language a remote query is written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryLanguage {
+    Sql,
+    InfluxQl,
+}
+
+/// Protocol remote queries are sent with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryProtocol {
+    /// Iox's own flight ticket, a ReadInfo naming the namespace and the query
+    Legacy,
+    /// Standard Arrow Flight SQL, the namespace is sent as a request header
+    FlightSql,
+}
+
+/// Compression asked for the results of remote queries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// Accept gzipped results, a server that can't compress sends them as is
+    Gzip,
+    /// Ask for the results as they are
+    None,
+}
+
 #[derive(Debug)]
 pub enum QueryEngine {
     /// Run queries against the named database on the remote server
-    Remote(String),
+    Remote(RemoteDatabase),
+
+    /// Run queries with DataFusion against the local files, no server needed
+    Local(Vec<LocalTable>),
+}
+
+/// A database on the remote server
+///
+/// Some setups name their namespaces after a convention like `org_bucket`,
+/// so the namespace sent in the flight request may differ from the name
+/// shown to the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteDatabase {
+    /// Name the database is shown by
+    pub name: String,
+
+    /// Namespace the flight request is sent to, passed through unchanged
+    pub namespace: String,
+}
+
+impl RemoteDatabase {
+    /// Queries the namespace when given, otherwise the namespace named like the database
+    pub fn new(name: String, namespace: Option<String>) -> Self {
+        let namespace = namespace.unwrap_or_else(|| name.clone());
+
+        Self { name, namespace }
+    }
+}
+
+/// A local parquet or csv file registered as a table for local queries
+#[derive(Debug, Clone)]
+pub struct LocalTable {
+    /// Name the table is queried by
+    pub name: String,
+
+    /// Path of the file holding the table data
+    pub path: PathBuf,
+}
+
+impl LocalTable {
+    /// Creates a table named after the file stem, e.g. `cpu.parquet` becomes `cpu`
+    pub fn from_path(path: PathBuf) -> Self {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Self { name, path }
+    }
+}
+
+/// A namespace as listed by the Iox namespace API
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamespaceInfo {
+    pub id: i64,
+    pub name: String,
+    /// None when the data is kept forever
+    pub retention_period_ns: Option<i64>,
+}
+
+/// Statistics about a query, reported by `ioxsql --stats`
+///
+/// Everything is measured on the client, the flight responses of the Iox
+/// client carry no server side metrics to report alongside.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryStats {
+    /// Total number of rows returned
+    pub rows: usize,
+
+    /// Number of record batches the rows arrived in
+    pub batches: usize,
+
+    /// Memory taken by the Arrow arrays of the result
+    pub bytes: usize,
+
+    /// Time spent running the query, excluding any formatting of the results
+    pub elapsed: Duration,
+
+    /// Whether the results came from the --cache of an earlier run
+    pub cached: bool,
+}
+
+impl QueryStats {
+    pub fn new<'a>(batches: impl IntoIterator<Item = &'a RecordBatch>, elapsed: Duration) -> Self {
+        let mut stats = Self {
+            elapsed,
+            ..Self::default()
+        };
+
+        for batch in batches {
+            stats.rows += batch.num_rows();
+            stats.batches += 1;
+            stats.bytes += batch
+                .columns()
+                .iter()
+                .map(|column| column.get_array_memory_size())
+                .sum::<usize>();
+        }
+
+        stats
+    }
+}
+
+/// The result of a query, for tools using nu_iox without nushell
+///
+/// The schema is that of the batches, or empty when the query returned
+/// none, as Iox sends no schema without data.
+///
+/// ```
+/// use arrow::array::{ArrayRef, Int64Array};
+/// use arrow::record_batch::RecordBatch;
+/// use nu_command::IoxResult;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let users = Arc::new(Int64Array::from(vec![955, 9599])) as ArrayRef;
+/// let batch = RecordBatch::try_from_iter(vec![("user", users)]).unwrap();
+///
+/// let result = IoxResult::new(vec![batch], Duration::from_millis(12));
+///
+/// assert_eq!(result.schema.field(0).name(), "user");
+/// assert_eq!(result.num_rows(), 2);
+/// assert_eq!(result.stats().batches, 1);
+/// ```
+///
+/// An empty result still has a schema, without any fields:
+///
+/// ```
+/// use nu_command::IoxResult;
+/// use std::time::Duration;
+///
+/// let result = IoxResult::new(vec![], Duration::ZERO);
+///
+/// assert!(result.schema.fields().is_empty());
+/// assert_eq!(result.num_rows(), 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct IoxResult {
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+    /// Time spent running the query
+    pub elapsed: Duration,
+}
+
+impl IoxResult {
+    pub fn new(batches: Vec<RecordBatch>, elapsed: Duration) -> Self {
+        let schema = batches
+            .first()
+            .map(|batch| batch.schema())
+            .unwrap_or_else(|| Arc::new(Schema::empty()));
+
+        Self {
+            schema,
+            batches,
+            elapsed,
+        }
+    }
+
+    /// Total number of rows in the batches
+    pub fn num_rows(&self) -> usize {
+        row_count(&self.batches)
+    }
+
+    /// The statistics ioxsql --stats reports for this result
+    pub fn stats(&self) -> QueryStats {
+        QueryStats::new(&self.batches, self.elapsed)
+    }
+}
+
+/// Total number of rows in the batches
+pub fn row_count<'a>(batches: impl IntoIterator<Item = &'a RecordBatch>) -> usize {
+    batches.into_iter().map(|b| b.num_rows()).sum()
 }
 
 #[derive(Debug)]
@@ -51,11 +353,36 @@ pub struct Nuclient {
     /// Client for running sql
     flight_client: influxdb_iox_client::flight::Client,
 
+    /// Kept to open Flight SQL clients, which influxdb_iox_client has none of
+    connection: Connection,
+
+    /// Protocol remote SQL queries are sent with
+    query_protocol: QueryProtocol,
+
+    /// Compression Flight SQL results are asked for. The legacy flight
+    /// client keeps its tonic client private, so it can't ask for any.
+    compression: Compression,
+
     /// database name against which SQL commands are run
     query_engine: Option<QueryEngine>,
 
     /// Formatter to use to format query results
-    output_format: QueryOutputFormat,
+    output_format: OutputFormat,
+
+    /// How long a remote query may run before it is abandoned, None waits forever
+    query_timeout: Option<Duration>,
+
+    /// How long a remote query may go without sending a batch, None waits forever
+    stall_timeout: Option<Duration>,
+
+    /// How often a request failing with a transient gRPC error is retried
+    retries: usize,
+
+    /// Set by nushell on ctrl-c, a running query stops at the next check
+    ctrlc: Option<Arc<AtomicBool>>,
+
+    /// Keeps the text of queries out of the logs, whatever RUST_LOG enables
+    quiet: bool,
 }
 
 impl Nuclient {
@@ -64,52 +391,97 @@ impl Nuclient {
         let namespace_client = influxdb_iox_client::namespace::Client::new(connection.clone());
         let flight_client = influxdb_iox_client::flight::Client::new(connection.clone());
 
-        let output_format = QueryOutputFormat::Pretty;
+        let output_format = OutputFormat::Iox(QueryOutputFormat::Pretty);
 
         Self {
             namespace_client,
             flight_client,
+            connection,
+            query_protocol: QueryProtocol::Legacy,
+            compression: Compression::None,
             query_engine: None,
             output_format,
+            query_timeout: None,
+            stall_timeout: None,
+            retries: DEFAULT_RETRIES,
+            ctrlc: None,
+            quiet: false,
         }
     }
 
-    // get all namespaces in csv output
-    pub async fn list_namespaces(&mut self) -> Result<String> {
-        let namespaces = self
+    /// Connect to the Iox server at addr and create a Nuclient for it
+    pub async fn connect(addr: &str, connector: IoxConnector) -> Result<Self> {
+        let compression = connector.compression();
+        let connection = connector.try_connect(addr).await?;
+
+        let mut client = Self::new(connection);
+        client.set_compression(compression);
+        Ok(client)
+    }
+
+    // get all namespaces with their id and retention
+    pub async fn list_namespaces(&mut self) -> Result<Vec<NamespaceInfo>> {
+        let client = &self.namespace_client;
+        let namespaces = with_retries(self.retries, || {
+            let mut client = client.clone();
+            async move { client.get_namespaces().await }
+        })
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(LoadingRemoteStateSnafu)?;
+
+        Ok(namespaces
+            .into_iter()
+            .map(|ns| NamespaceInfo {
+                id: ns.id,
+                name: ns.name,
+                retention_period_ns: ns.retention_period_ns,
+            })
+            .collect())
+    }
+
+    // create a namespace returning its id and name
+    pub async fn create_namespace(
+        &mut self,
+        name: &str,
+        retention_period_ns: Option<i64>,
+    ) -> Result<(i64, String)> {
+        let namespace = self
             .namespace_client
-            .get_namespaces()
+            .create_namespace(name, retention_period_ns)
             .await
             .map_err(|e| Box::new(e) as _)
-            .context(LoadingRemoteStateSnafu)?;
+            .context(CreatingNamespaceSnafu { name })?;
 
-        let namespace_id: Int64Array = namespaces.iter().map(|ns| Some(ns.id)).collect();
-        let name: StringArray = namespaces.iter().map(|ns| Some(&ns.name)).collect();
+        Ok((namespace.id, namespace.name))
+    }
 
-        let record_batch = RecordBatch::try_from_iter(vec![
-            ("namespace_id", Arc::new(namespace_id) as ArrayRef),
-            ("name", Arc::new(name) as ArrayRef),
-        ])
-        .expect("creating record batch successfully");
+    // delete the namespace with the given name
+    pub async fn delete_namespace(&mut self, name: &str) -> Result<()> {
+        self.namespace_client
+            .delete_namespace(name)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(DeletingNamespaceSnafu { name })
+    }
 
-        let result_str = self.get_results(&[record_batch])?;
-        Ok(result_str)
+    // get the names of all namespaces
+    pub async fn list_namespace_names(&mut self) -> Result<Vec<String>> {
+        let client = &self.namespace_client;
+        let namespaces = with_retries(self.retries, || {
+            let mut client = client.clone();
+            async move { client.get_namespaces().await }
+        })
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(LoadingRemoteStateSnafu)?;
+
+        Ok(namespaces.into_iter().map(|ns| ns.name).collect())
     }
 
     // Run a command against the currently selected remote database
     pub async fn run_sql(&mut self, sql: String) -> Result<String> {
-        let batches = match &mut self.query_engine {
-            None => {
-                println!("Error: no database selected.");
-                println!("Hint: Run USE DATABASE <dbname> to select database");
-                return Ok("Error: no database selected".to_string());
-            }
-            Some(QueryEngine::Remote(db_name)) => {
-                info!(%db_name, %sql, "Running sql on remote database");
-
-                scrape_query(&mut self.flight_client, db_name, &sql).await?
-            }
-        };
+        let batches = self.run_sql_batches(sql).await?;
 
         let result_str = self.get_results(&batches)?;
 
@@ -117,21 +489,151 @@ impl Nuclient {
     }
 
     // Run a command against the currently selected remote database
-    pub async fn print_sql(&mut self, sql: String) -> Result<()> {
-        let start = Instant::now();
+    // returning the record batches rather than a formatted string
+    pub async fn run_sql_batches(&mut self, sql: String) -> Result<Vec<RecordBatch>> {
+        self.run_query_batches(QueryLanguage::Sql, sql).await
+    }
+
+    // Run an InfluxQL query against the currently selected remote database
+    pub async fn run_influxql_batches(&mut self, influxql: String) -> Result<Vec<RecordBatch>> {
+        self.run_query_batches(QueryLanguage::InfluxQl, influxql)
+            .await
+    }
+
+    async fn run_query_batches(
+        &mut self,
+        language: QueryLanguage,
+        query: String,
+    ) -> Result<Vec<RecordBatch>> {
+        let query_timeout = self.query_timeout;
+        let stall_timeout = self.stall_timeout;
+        let retries = self.retries;
+        let ctrlc = self.ctrlc.clone();
+        let protocol = self.query_protocol;
+        let compression = self.compression;
+        let logged_query = logged_query(&query, self.quiet);
+
+        match &mut self.query_engine {
+            None => NoDatabaseSelectedSnafu.fail(),
+            Some(QueryEngine::Remote(database)) => {
+                let db_name = &database.name;
+                let namespace = &database.namespace;
+                info!(%db_name, %namespace, query = %logged_query, ?language, ?protocol, "Running query on remote database");
+
+                if protocol == QueryProtocol::FlightSql {
+                    if language != QueryLanguage::Sql {
+                        return FlightSqlUnsupportedSnafu.fail();
+                    }
+
+                    let query = run_flight_sql(
+                        self.connection.clone(),
+                        namespace,
+                        &query,
+                        retries,
+                        compression,
+                        stall_timeout,
+                        ctrlc,
+                    );
+                    return with_timeout(query_timeout, query).await;
+                }
 
-        let batches = match &mut self.query_engine {
-            None => {
-                println!("Error: no database selected.");
-                println!("Hint: Run USE DATABASE <dbname> to select database");
-                return Ok(());
+                let read_info = read_info(namespace, &query, language)?;
+                let query = scrape_query(
+                    &self.flight_client,
+                    read_info,
+                    retries,
+                    stall_timeout,
+                    ctrlc,
+                );
+                with_timeout(query_timeout, query).await
             }
-            Some(QueryEngine::Remote(db_name)) => {
-                info!(%db_name, %sql, "Running sql on remote database");
+            Some(QueryEngine::Local(tables)) => match language {
+                QueryLanguage::Sql => {
+                    info!(query = %logged_query, "Running sql on local files");
 
-                scrape_query(&mut self.flight_client, db_name, &sql).await?
+                    run_local_sql(tables, &query).await
+                }
+                QueryLanguage::InfluxQl => NoRemoteDatabaseSnafu.fail(),
+            },
+        }
+    }
+
+    // Start a query against the currently selected remote database
+    // returning the flight stream so batches can be read as they arrive
+    pub async fn start_query(&mut self, sql: String) -> Result<PerformQuery> {
+        let query_timeout = self.query_timeout;
+        let retries = self.retries;
+        let logged_sql = logged_query(&sql, self.quiet);
+
+        if self.query_protocol == QueryProtocol::FlightSql {
+            return FlightSqlUnsupportedSnafu.fail();
+        }
+
+        match &mut self.query_engine {
+            Some(QueryEngine::Remote(database)) => {
+                let db_name = &database.name;
+                let namespace = &database.namespace;
+                info!(%db_name, %namespace, sql = %logged_sql, "Streaming sql from remote database");
+
+                let read_info = read_info(namespace, &sql, QueryLanguage::Sql)?;
+                let query = perform_query(&self.flight_client, read_info, retries);
+                with_timeout(query_timeout, query).await
             }
-        };
+            _ => NoRemoteDatabaseSnafu.fail(),
+        }
+    }
+
+    // Run a query only until its first batch arrives, returning the schema
+    // of the result, None when the query returned no batches at all
+    pub async fn query_schema(&mut self, sql: String) -> Result<Option<SchemaRef>> {
+        let query_timeout = self.query_timeout;
+        let mut query = self.start_query(sql).await?;
+
+        let first_batch = async { query.next().await.map_err(remote_query_error) };
+        let first_batch = with_timeout(query_timeout, first_batch).await?;
+
+        Ok(first_batch.map(|batch| batch.schema()))
+    }
+
+    // Run a query against the currently selected remote database
+    // counting the rows of each batch as it arrives without keeping it
+    pub async fn count_sql_rows(&mut self, sql: String) -> Result<usize> {
+        let query_timeout = self.query_timeout;
+        let ctrlc = self.ctrlc.clone();
+        let query = self.start_query(sql).await?;
+        let mut query = StallTimeout::new(query, self.stall_timeout);
+
+        let rows = fold_until_interrupted(&mut query, ctrlc, 0, |rows, batch: RecordBatch| {
+            rows + batch.num_rows()
+        });
+        with_timeout(query_timeout, rows).await
+    }
+
+    // Run a command against the currently selected database
+    // returning the record batches along with the query statistics
+    pub async fn run_sql_batches_with_stats(
+        &mut self,
+        sql: String,
+    ) -> Result<(Vec<RecordBatch>, QueryStats)> {
+        let result = self.run_sql_result(sql).await?;
+        let stats = result.stats();
+
+        Ok((result.batches, stats))
+    }
+
+    /// Runs the sql against the currently selected database, timing it
+    pub async fn run_sql_result(&mut self, sql: String) -> Result<IoxResult> {
+        let start = Instant::now();
+        let batches = self.run_sql_batches(sql).await?;
+
+        Ok(IoxResult::new(batches, start.elapsed()))
+    }
+
+    // Run a command against the currently selected remote database
+    pub async fn print_sql(&mut self, sql: String) -> Result<()> {
+        let start = Instant::now();
+
+        let batches = self.run_sql_batches(sql).await?;
 
         let end = Instant::now();
         self.print_results(&batches)?;
@@ -145,7 +647,7 @@ impl Nuclient {
     }
 
     fn row_summary<'a>(batches: impl IntoIterator<Item = &'a RecordBatch>) -> String {
-        let total_rows: usize = batches.into_iter().map(|b| b.num_rows()).sum();
+        let total_rows = row_count(batches);
 
         if total_rows > 1 {
             format!("{} rows", total_rows)
@@ -157,71 +659,1450 @@ impl Nuclient {
     }
 
     pub fn use_database(&mut self, db_name: String) {
-        debug!(%db_name, "setting current database");
-        println!("You are now querying the database {}", db_name);
-        self.set_query_engine(QueryEngine::Remote(db_name));
+        self.use_remote_database(RemoteDatabase::new(db_name, None));
+    }
+
+    /// Selects the remote database, querying its namespace as given
+    pub fn use_remote_database(&mut self, database: RemoteDatabase) {
+        let db_name = &database.name;
+        let namespace = &database.namespace;
+        // logged rather than printed, every query selects its database and
+        // stdout carries the results
+        debug!(%db_name, %namespace, "setting current database");
+        self.set_query_engine(QueryEngine::Remote(database));
     }
 
     pub fn set_query_engine(&mut self, query_engine: QueryEngine) {
         self.query_engine = Some(query_engine)
     }
 
+    /// Sets the protocol remote SQL queries are sent with
+    pub fn set_query_protocol(&mut self, query_protocol: QueryProtocol) {
+        self.query_protocol = query_protocol
+    }
+
+    /// Sets the compression Flight SQL results are asked for
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression
+    }
+
+    /// The compression Flight SQL results are asked for
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Sets how long a remote query may run, None disables the timeout
+    pub fn set_query_timeout(&mut self, query_timeout: Option<Duration>) {
+        self.query_timeout = query_timeout
+    }
+
+    /// Sets how long a remote query may wait for its next batch, None disables it
+    ///
+    /// Unlike the query timeout this restarts with every batch, so a slow
+    /// query keeps running as long as its results keep coming.
+    pub fn set_stall_timeout(&mut self, stall_timeout: Option<Duration>) {
+        self.stall_timeout = stall_timeout
+    }
+
+    /// Sets whether the text of queries is redacted from the logs
+    ///
+    /// Which database is queried is still logged, only the query is not.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet
+    }
+
+    /// Sets the interrupt flag checked while a query is running
+    pub fn set_ctrlc(&mut self, ctrlc: Option<Arc<AtomicBool>>) {
+        self.ctrlc = ctrlc
+    }
+
+    /// Sets how often a transient failure is retried, 0 disables retrying
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries
+    }
+
     /// Sets the output format to the specified format
+    ///
+    /// Takes the formats of QueryOutputFormat, e.g. pretty, csv or json,
+    /// and ndjson.
     pub fn set_output_format<S: AsRef<str>>(&mut self, requested_format: S) -> Result<()> {
-        let requested_format = requested_format.as_ref();
-
-        self.output_format = requested_format
-            .parse()
-            .context(SettingFormatSnafu { requested_format })?;
-        // leave this here for future debugging...
-        // println!("Set output format to {}", self.output_format);
+        self.output_format = OutputFormat::from_name(requested_format.as_ref())?;
+        debug!("Set output format to {:?}", self.output_format);
         Ok(())
     }
 
+    /// The format results are returned and printed in
+    pub fn output_format(&self) -> &OutputFormat {
+        &self.output_format
+    }
+
     /// Prints to the specified output format
     fn get_results(&self, batches: &[RecordBatch]) -> Result<String> {
-        let formatted_results = self
-            .output_format
-            .format(batches)
-            .context(FormattingResultsSnafu)?;
+        let formatted_results = self.output_format.format(batches)?;
         //println!("{}", formatted_results);
         Ok(formatted_results)
     }
 
     /// Prints to the specified output format
     fn print_results(&self, batches: &[RecordBatch]) -> Result<()> {
-        let formatted_results = self
-            .output_format
-            .format(batches)
-            .context(FormattingResultsSnafu)?;
+        let formatted_results = self.output_format.format(batches)?;
         println!("{}", formatted_results);
         Ok(())
     }
 }
 
-/// Runs the specified `query` and returns the record batches of the result
-async fn scrape_query(
-    client: &mut influxdb_iox_client::flight::Client,
-    db_name: &str,
-    query: &str,
-) -> Result<Vec<RecordBatch>> {
-    let mut query_results = client
-        .perform_query(ReadInfo {
+/// How long the database names listed for completions are reused
+const DATABASE_NAMES_TTL: Duration = Duration::from_secs(30);
+
+/// The server address and token hash database names were listed with, see token_hash
+type DatabaseNamesKey = (String, Option<u64>);
+
+lazy_static! {
+    // The database names last listed per server and token, with when they were listed
+    static ref DATABASE_NAMES: Mutex<HashMap<DatabaseNamesKey, (Instant, Vec<String>)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Lists the names of the databases on the Iox server at addr
+///
+/// Meant for the custom completions of --dbname, see Ioxdbnames, which run
+/// on every tab press, so the names are reused for DATABASE_NAMES_TTL before
+/// the server is asked again. A server lists the databases the token may
+/// see, so they are cached per token too.
+pub fn list_database_names(
+    addr: &str,
+    token: Option<&str>,
+    tls_ca: Option<&Spanned<String>>,
+) -> Result<Vec<String>> {
+    let key = (addr.to_string(), token_hash(token));
+
+    cached_database_names(key, Instant::now(), || {
+        fetch_database_names(addr, token, tls_ca)
+    })
+}
+
+fn fetch_database_names(
+    addr: &str,
+    token: Option<&str>,
+    tls_ca: Option<&Spanned<String>>,
+) -> Result<Vec<String>> {
+    let connector = iox_connector(addr, token, tls_ca, Span::new(0, 0))
+        .map_err(|e| Box::new(e) as _)
+        .context(LoadingRemoteStateSnafu)?;
+
+    block_on_iox(async move {
+        let mut client = Nuclient::connect(addr, connector).await?;
+        client.list_namespace_names().await
+    })
+    .map_err(|e| Box::new(e) as _)
+    .context(LoadingRemoteStateSnafu)?
+}
+
+/// Runs the sql against the database on the Iox server at addr
+///
+/// A blocking entry point for tools embedding nu_iox without nushell, it
+/// connects without a token or custom certificate authority. It blocks on the shared Iox runtime, and tokio
+/// can't block_on from within a runtime, so called from async code it fails
+/// every time, use run_sql_async there instead.
+///
+/// ```no_run
+/// use nu_command::{query_iox, RemoteDatabase};
+///
+/// let database = RemoteDatabase::new("bananas".to_string(), None);
+/// let result = query_iox("http://127.0.0.1:8082", database, "select * from cpu").unwrap();
+///
+/// println!("{} rows in {:?}", result.num_rows(), result.elapsed);
+/// ```
+pub fn query_iox(addr: &str, database: RemoteDatabase, sql: &str) -> Result<IoxResult> {
+    block_on_iox(run_sql_async(addr, database, sql))
+        .map_err(|e| Box::new(e) as _)
+        .context(LoadingRemoteStateSnafu)?
+}
+
+/// Like query_iox, but awaited on the runtime of the caller
+///
+/// The connection is opened for this query alone, one cached for the
+/// session would die with the runtime of the caller.
+///
+/// ```no_run
+/// use nu_command::{run_sql_async, RemoteDatabase};
+///
+/// # async fn run() {
+/// let database = RemoteDatabase::new("bananas".to_string(), None);
+/// let result = run_sql_async("http://127.0.0.1:8082", database, "select * from cpu")
+///     .await
+///     .unwrap();
+///
+/// println!("{} rows in {:?}", result.num_rows(), result.elapsed);
+/// # }
+/// ```
+pub async fn run_sql_async(addr: &str, database: RemoteDatabase, sql: &str) -> Result<IoxResult> {
+    let connector = plain_connector(addr)?.without_cache();
+    let mut client = Nuclient::connect(addr, connector).await?;
+    client.set_query_engine(QueryEngine::Remote(database));
+    client.run_sql_result(sql.to_string()).await
+}
+
+/// Lists the namespaces on the Iox server at addr, awaited on the runtime
+/// of the caller on a connection of its own, like run_sql_async
+pub async fn list_namespaces_async(addr: &str) -> Result<Vec<NamespaceInfo>> {
+    let connector = plain_connector(addr)?.without_cache();
+    let mut client = Nuclient::connect(addr, connector).await?;
+    client.list_namespaces().await
+}
+
+/// A connector without a token or custom certificate authority
+fn plain_connector(addr: &str) -> Result<IoxConnector> {
+    iox_connector(addr, None, None, Span::new(0, 0))
+        .map_err(|e| Box::new(e) as _)
+        .context(LoadingRemoteStateSnafu)
+}
+
+/// Returns the cached names of the key while they are fresh, otherwise fetches them
+///
+/// The cache is not locked while fetching, so a slow server doesn't hold up
+/// the completions of another one. A failed fetch is not cached, the next
+/// completion tries again.
+fn cached_database_names<F>(key: DatabaseNamesKey, now: Instant, fetch: F) -> Result<Vec<String>>
+where
+    F: FnOnce() -> Result<Vec<String>>,
+{
+    let cache = || {
+        DATABASE_NAMES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    };
+
+    if let Some((listed, names)) = cache().get(&key) {
+        if now.saturating_duration_since(*listed) < DATABASE_NAMES_TTL {
+            return Ok(names.clone());
+        }
+    }
+
+    let names = fetch()?;
+    cache().insert(key, (now, names.clone()));
+
+    Ok(names)
+}
+
+/// The formats a Nuclient can format results in
+///
+/// Json is a single array holding every row, ndjson, which Iox has no
+/// QueryOutputFormat for, is one object per line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputFormat {
+    Iox(QueryOutputFormat),
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// The format named e.g. pretty, csv, json or ndjson
+    pub fn from_name(requested_format: &str) -> Result<Self> {
+        if requested_format.eq_ignore_ascii_case("ndjson") {
+            Ok(OutputFormat::Ndjson)
+        } else {
+            Ok(OutputFormat::Iox(
+                requested_format
+                    .parse()
+                    .context(SettingFormatSnafu { requested_format })?,
+            ))
+        }
+    }
+
+    pub fn format(&self, batches: &[RecordBatch]) -> Result<String> {
+        match self {
+            OutputFormat::Iox(output_format) => format_batches(output_format, batches),
+            OutputFormat::Ndjson => {
+                let mut writer = arrow::json::LineDelimitedWriter::new(Vec::new());
+                writer
+                    .write_batches(batches)
+                    .context(FormattingNdjsonSnafu)?;
+                writer.finish().context(FormattingNdjsonSnafu)?;
+
+                Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+            }
+        }
+    }
+}
+
+/// The query as it is logged, only its length when quiet
+fn logged_query(query: &str, quiet: bool) -> std::borrow::Cow<'_, str> {
+    if quiet {
+        format!("<{} bytes redacted>", query.len()).into()
+    } else {
+        query.into()
+    }
+}
+
+/// Formats the record batches with the given output format
+pub fn format_batches(
+    output_format: &QueryOutputFormat,
+    batches: &[RecordBatch],
+) -> Result<String> {
+    output_format
+        .format(batches)
+        .context(FormattingResultsSnafu)
+}
+
+/// Awaits the future, failing with Error::Timeout once the timeout expires
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        None => future.await,
+        Some(timeout) => match tokio::time::timeout(timeout, future).await {
+            Ok(result) => result,
+            Err(_) => TimeoutSnafu { timeout }.fail(),
+        },
+    }
+}
+
+/// The errors of the clients that carry the gRPC status code of the server
+pub trait GrpcCode {
+    /// The status code, None when the failure never reached the server
+    fn grpc_code(&self) -> Option<tonic::Code>;
+}
+
+impl GrpcCode for tonic::Status {
+    fn grpc_code(&self) -> Option<tonic::Code> {
+        Some(self.code())
+    }
+}
+
+impl GrpcCode for influxdb_iox_client::flight::Error {
+    fn grpc_code(&self) -> Option<tonic::Code> {
+        match self {
+            influxdb_iox_client::flight::Error::GrpcError(status) => Some(status.code()),
+            _ => None,
+        }
+    }
+}
+
+impl GrpcCode for influxdb_iox_client::error::Error {
+    fn grpc_code(&self) -> Option<tonic::Code> {
+        use influxdb_iox_client::error::Error as ClientError;
+
+        match self {
+            ClientError::Unknown(_) => Some(tonic::Code::Unknown),
+            ClientError::InvalidArgument(_) => Some(tonic::Code::InvalidArgument),
+            ClientError::DeadlineExceeded(_) => Some(tonic::Code::DeadlineExceeded),
+            ClientError::NotFound(_) => Some(tonic::Code::NotFound),
+            ClientError::AlreadyExists(_) => Some(tonic::Code::AlreadyExists),
+            ClientError::PermissionDenied(_) => Some(tonic::Code::PermissionDenied),
+            ClientError::ResourceExhausted(_) => Some(tonic::Code::ResourceExhausted),
+            ClientError::FailedPrecondition(_) => Some(tonic::Code::FailedPrecondition),
+            ClientError::Aborted(_) => Some(tonic::Code::Aborted),
+            ClientError::OutOfRange(_) => Some(tonic::Code::OutOfRange),
+            ClientError::Unimplemented(_) => Some(tonic::Code::Unimplemented),
+            ClientError::Internal(_) => Some(tonic::Code::Internal),
+            ClientError::Unavailable(_) => Some(tonic::Code::Unavailable),
+            ClientError::DataLoss(_) => Some(tonic::Code::DataLoss),
+            ClientError::Unauthenticated(_) => Some(tonic::Code::Unauthenticated),
+            ClientError::Client(_) => None,
+        }
+    }
+}
+
+impl GrpcCode for Error {
+    fn grpc_code(&self) -> Option<tonic::Code> {
+        Error::grpc_code(self)
+    }
+}
+
+/// Returns true for gRPC failures worth another attempt
+///
+/// Unavailable and DeadlineExceeded usually mean the server is restarting
+/// or overloaded, anything else, e.g. InvalidArgument, fails the same way
+/// again so it is returned right away.
+pub fn is_transient_error(error: &dyn GrpcCode) -> bool {
+    matches!(
+        error.grpc_code(),
+        Some(tonic::Code::Unavailable | tonic::Code::DeadlineExceeded)
+    )
+}
+
+/// Runs the operation, retrying transient failures with exponential backoff
+pub async fn with_retries<T, E, F, Fut>(retries: usize, mut operation: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+    E: GrpcCode + std::fmt::Display,
+{
+    let mut attempt = 0;
+
+    loop {
+        match operation().await {
+            Err(error) if attempt < retries && is_transient_error(&error) => {
+                let delay = RETRY_BASE_DELAY * 2u32.pow(attempt as u32);
+                attempt += 1;
+
+                debug!(%error, attempt, ?delay, "Retrying transient Iox failure");
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Builds the flight request for a query in the given language
+///
+/// The InfluxQL query type is only part of newer Iox flight protocols, so it
+/// is behind the iox-influxql feature.
+fn read_info(db_name: &str, query: &str, language: QueryLanguage) -> Result<ReadInfo> {
+    #[cfg(feature = "iox-influxql")]
+    use influxdb_iox_client::flight::generated_types::read_info::QueryType;
+
+    match language {
+        QueryLanguage::Sql => Ok(ReadInfo {
             namespace_name: db_name.to_string(),
             sql_query: query.to_string(),
-        })
-        .await
-        .context(RunningRemoteQuerySnafu)?;
+            #[cfg(feature = "iox-influxql")]
+            query_type: QueryType::Sql.into(),
+        }),
+        #[cfg(feature = "iox-influxql")]
+        QueryLanguage::InfluxQl => Ok(ReadInfo {
+            namespace_name: db_name.to_string(),
+            sql_query: query.to_string(),
+            query_type: QueryType::InfluxQl.into(),
+        }),
+        #[cfg(not(feature = "iox-influxql"))]
+        QueryLanguage::InfluxQl => InfluxQlUnavailableSnafu.fail(),
+    }
+}
 
-    let mut batches = vec![];
+/// Wraps a flight error, keeping the gRPC status code the server sent
+pub fn remote_query_error(source: influxdb_iox_client::flight::Error) -> Error {
+    let code = match &source {
+        influxdb_iox_client::flight::Error::GrpcError(status) => Some(status.code()),
+        _ => None,
+    };
 
-    while let Some(data) = query_results
-        .next()
-        .await
-        .context(RunningRemoteQuerySnafu)?
-    {
-        batches.push(data);
+    Error::RunningRemoteQuery { source, code }
+}
+
+impl Error {
+    /// The gRPC status code of a failed remote query
+    pub fn grpc_code(&self) -> Option<tonic::Code> {
+        match self {
+            Error::RunningRemoteQuery { code, .. } => *code,
+            Error::RemoteQueryFailedMidStream { code, .. } => *code,
+            Error::RunningFlightSqlQuery { source } => Some(source.code()),
+            _ => None,
+        }
     }
 
-    Ok(batches)
+    /// How many batches a query that failed partway had received
+    pub fn batches_received(&self) -> Option<usize> {
+        match self {
+            Error::RemoteQueryFailedMidStream {
+                batches_received, ..
+            } => Some(*batches_received),
+            _ => None,
+        }
+    }
+
+    /// Marks a failed remote query as failed while its results were streaming
+    ///
+    /// This tells it apart from a query the server refused to start, every
+    /// other error is returned as it is.
+    fn mid_stream(self, batches_received: usize) -> Self {
+        match self {
+            Error::RunningRemoteQuery { source, code } => Error::RemoteQueryFailedMidStream {
+                source,
+                code,
+                batches_received,
+            },
+            error => error,
+        }
+    }
+}
+
+/// Starts the query, retrying transient failures
+async fn perform_query(
+    client: &influxdb_iox_client::flight::Client,
+    read_info: ReadInfo,
+    retries: usize,
+) -> Result<PerformQuery> {
+    with_retries(retries, || {
+        let mut client = client.clone();
+        let read_info = read_info.clone();
+
+        async move { client.perform_query(read_info).await }
+    })
+    .await
+    .map_err(remote_query_error)
+}
+
+/// Runs the query and returns the record batches of the result
+async fn scrape_query(
+    client: &influxdb_iox_client::flight::Client,
+    read_info: ReadInfo,
+    retries: usize,
+    stall_timeout: Option<Duration>,
+    ctrlc: Option<Arc<AtomicBool>>,
+) -> Result<Vec<RecordBatch>> {
+    let query_results = perform_query(client, read_info, retries).await?;
+    let mut query_results = StallTimeout::new(query_results, stall_timeout);
+
+    collect_until_interrupted(&mut query_results, ctrlc).await
+}
+
+/// How often a pending batch checks the interrupt flag
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+type NextItem<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Option<T>>> + 'a>>;
+
+/// Something query results can be pulled from one at a time
+trait ResultSource {
+    type Item;
+
+    fn next_item(&mut self) -> NextItem<'_, Self::Item>;
+}
+
+impl ResultSource for PerformQuery {
+    type Item = RecordBatch;
+
+    fn next_item(&mut self) -> NextItem<'_, RecordBatch> {
+        Box::pin(async move { self.next().await.map_err(remote_query_error) })
+    }
+}
+
+/// Fails a result source with Error::Stalled when an item takes longer than timeout
+///
+/// The wait starts over for every item, a None timeout passes them through.
+struct StallTimeout<S> {
+    source: S,
+    timeout: Option<Duration>,
+}
+
+impl<S> StallTimeout<S> {
+    fn new(source: S, timeout: Option<Duration>) -> Self {
+        Self { source, timeout }
+    }
+}
+
+impl<S: ResultSource> ResultSource for StallTimeout<S> {
+    type Item = S::Item;
+
+    fn next_item(&mut self) -> NextItem<'_, S::Item> {
+        let timeout = self.timeout;
+        let item = self.source.next_item();
+
+        Box::pin(async move {
+            match timeout {
+                None => item.await,
+                Some(timeout) => match tokio::time::timeout(timeout, item).await {
+                    Ok(item) => item,
+                    Err(_) => StalledSnafu { timeout }.fail(),
+                },
+            }
+        })
+    }
+}
+
+/// Collects the items of a query until it ends or ctrl-c is pressed
+async fn collect_until_interrupted<S: ResultSource>(
+    source: &mut S,
+    ctrlc: Option<Arc<AtomicBool>>,
+) -> Result<Vec<S::Item>> {
+    fold_until_interrupted(source, ctrlc, vec![], |mut items, item| {
+        items.push(item);
+        items
+    })
+    .await
+}
+
+/// Folds the items of a query into acc until it ends or ctrl-c is pressed
+///
+/// The flag is checked between items and while waiting for the next one,
+/// an interrupt drops what was folded so far and fails with Cancelled. A
+/// failure reading an item drops it too, and is marked as mid-stream.
+async fn fold_until_interrupted<S, T, F>(
+    source: &mut S,
+    ctrlc: Option<Arc<AtomicBool>>,
+    mut acc: T,
+    mut fold: F,
+) -> Result<T>
+where
+    S: ResultSource,
+    F: FnMut(T, S::Item) -> T,
+{
+    let mut folded = 0;
+
+    loop {
+        let item = match &ctrlc {
+            None => source.next_item().await,
+            Some(ctrlc) => {
+                tokio::select! {
+                    item = source.next_item() => item,
+                    _ = wait_for_interrupt(ctrlc) => Ok(None),
+                }
+            }
+        }
+        .map_err(|error| error.mid_stream(folded))?;
+
+        if ctrlc.as_ref().map_or(false, |c| c.load(Ordering::SeqCst)) {
+            debug!("Iox query interrupted, dropping {} partial results", folded);
+            return CancelledSnafu.fail();
+        }
+
+        match item {
+            Some(item) => {
+                acc = fold(acc, item);
+                folded += 1;
+            }
+            None => return Ok(acc),
+        }
+    }
+}
+
+#[cfg(feature = "iox-flightsql")]
+impl ResultSource for FlightSqlQuery {
+    type Item = RecordBatch;
+
+    fn next_item(&mut self) -> NextItem<'_, RecordBatch> {
+        Box::pin(self.next())
+    }
+}
+
+async fn wait_for_interrupt(ctrlc: &AtomicBool) {
+    while !ctrlc.load(Ordering::SeqCst) {
+        tokio::time::sleep(INTERRUPT_POLL_INTERVAL).await;
+    }
+}
+
+/// Runs the sql over Flight SQL and returns the record batches of the result
+#[cfg(feature = "iox-flightsql")]
+async fn run_flight_sql(
+    connection: Connection,
+    namespace: &str,
+    sql: &str,
+    retries: usize,
+    compression: Compression,
+    stall_timeout: Option<Duration>,
+    ctrlc: Option<Arc<AtomicBool>>,
+) -> Result<Vec<RecordBatch>> {
+    let query = FlightSqlQuery::start(connection, namespace, sql, retries, compression).await?;
+    let mut query = StallTimeout::new(query, stall_timeout);
+
+    collect_until_interrupted(&mut query, ctrlc).await
+}
+
+/// Flight SQL needs arrow-flight, which is only built with the iox-flightsql feature
+#[cfg(not(feature = "iox-flightsql"))]
+async fn run_flight_sql(
+    _connection: Connection,
+    _namespace: &str,
+    _sql: &str,
+    _retries: usize,
+    _compression: Compression,
+    _stall_timeout: Option<Duration>,
+    _ctrlc: Option<Arc<AtomicBool>>,
+) -> Result<Vec<RecordBatch>> {
+    FlightSqlUnavailableSnafu.fail()
+}
+
+/// Runs the specified `query` with DataFusion against the local tables
+#[cfg(feature = "iox-local")]
+pub async fn run_local_sql(tables: &[LocalTable], query: &str) -> Result<Vec<RecordBatch>> {
+    use datafusion::prelude::{CsvReadOptions, ParquetReadOptions, SessionContext};
+
+    let ctx = SessionContext::new();
+
+    for table in tables {
+        let path = table.path.to_string_lossy();
+
+        match table.path.extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") => ctx
+                .register_parquet(&table.name, &path, ParquetReadOptions::default())
+                .await
+                .context(RunningLocalQuerySnafu)?,
+            Some("csv") => ctx
+                .register_csv(&table.name, &path, CsvReadOptions::new())
+                .await
+                .context(RunningLocalQuerySnafu)?,
+            _ => {
+                return UnsupportedLocalFileSnafu {
+                    path: table.path.clone(),
+                }
+                .fail()
+            }
+        }
+    }
+
+    let df = ctx.sql(query).await.context(RunningLocalQuerySnafu)?;
+
+    df.collect().await.context(RunningLocalQuerySnafu)
+}
+
+/// Local queries need DataFusion, which is only built with the iox-local feature
+#[cfg(not(feature = "iox-local"))]
+pub async fn run_local_sql(_tables: &[LocalTable], _query: &str) -> Result<Vec<RecordBatch>> {
+    LocalQueryUnavailableSnafu.fail()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::{ArrayRef, Int64Array};
+    use observability_deps::tracing::{field::Field, span, Event, Metadata, Subscriber};
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn remote_query_error_keeps_the_grpc_code() {
+        use influxdb_iox_client::flight::Error as FlightError;
+
+        let cases = [
+            (tonic::Status::unavailable("down"), tonic::Code::Unavailable),
+            (tonic::Status::not_found("no table"), tonic::Code::NotFound),
+            (
+                tonic::Status::permission_denied("nope"),
+                tonic::Code::PermissionDenied,
+            ),
+            (
+                tonic::Status::invalid_argument("bad sql"),
+                tonic::Code::InvalidArgument,
+            ),
+        ];
+
+        for (status, code) in cases {
+            let error = remote_query_error(FlightError::GrpcError(status));
+            assert_eq!(error.grpc_code(), Some(code));
+        }
+
+        assert_eq!(Error::NoRemoteDatabase.grpc_code(), None);
+    }
+
+    #[test]
+    fn flight_sql_errors_keep_the_grpc_code() {
+        let error = Error::RunningFlightSqlQuery {
+            source: tonic::Status::unauthenticated("no token"),
+        };
+
+        assert_eq!(error.grpc_code(), Some(tonic::Code::Unauthenticated));
+    }
+
+    #[test]
+    fn connect_to_a_stopped_server_is_an_error() {
+        use crate::iox::{block_on_iox, iox_connector};
+        use nu_protocol::Span;
+
+        // an address nothing listens on, the port was free a moment ago
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+        let result = block_on_iox(Nuclient::connect(&addr, connector)).unwrap();
+
+        match result {
+            Err(Error::Connecting { addr: failed, .. }) => assert_eq!(failed, addr),
+            other => panic!("expected a connection error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn async_api_runs_inside_a_runtime() {
+        // an address nothing listens on, the port was free a moment ago
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let database = RemoteDatabase::new("bananas".to_string(), None);
+
+        // both get as far as connecting, which a nested block_on never does
+        match run_sql_async(&addr, database.clone(), "select * from cpu").await {
+            Err(Error::Connecting { addr: failed, .. }) => assert_eq!(failed, addr),
+            other => panic!("expected a connection error, got {:?}", other),
+        }
+        match list_namespaces_async(&addr).await {
+            Err(Error::Connecting { addr: failed, .. }) => assert_eq!(failed, addr),
+            other => panic!("expected a connection error, got {:?}", other),
+        }
+
+        match query_iox(&addr, database, "select * from cpu") {
+            Err(Error::LoadingRemoteState { source }) => assert_eq!(
+                source.to_string(),
+                "Cannot block on an Iox request from within an async runtime"
+            ),
+            other => panic!("expected the blocking error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn json_and_ndjson_are_distinct_output_formats() {
+        use crate::iox::{block_on_iox, iox_connector};
+        use nu_protocol::Span;
+
+        // kept open so connecting succeeds, nothing is ever queried
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+        let batch = RecordBatch::try_from_iter(vec![(
+            "user",
+            Arc::new(Int64Array::from(vec![955, 9599])) as ArrayRef,
+        )])
+        .unwrap();
+
+        let mut client = block_on_iox(Nuclient::connect(&addr, connector))
+            .unwrap()
+            .unwrap();
+
+        client.set_output_format("json").unwrap();
+        assert_eq!(
+            client.output_format(),
+            &OutputFormat::Iox(QueryOutputFormat::Json)
+        );
+        assert_eq!(
+            client.get_results(&[batch.clone()]).unwrap(),
+            r#"[{"user":955},{"user":9599}]"#
+        );
+
+        client.set_output_format("ndjson").unwrap();
+        assert_eq!(client.output_format(), &OutputFormat::Ndjson);
+        assert_eq!(
+            client.get_results(&[batch]).unwrap(),
+            "{\"user\":955}\n{\"user\":9599}\n"
+        );
+
+        assert!(client.set_output_format("xml").is_err());
+    }
+
+    #[test]
+    fn query_without_a_database_is_an_error_not_data() {
+        use crate::iox::{block_on_iox, iox_connector};
+        use nu_protocol::Span;
+
+        // kept open so connecting succeeds, nothing is ever queried
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+
+        let result = block_on_iox(async {
+            let mut client = Nuclient::connect(&addr, connector).await.unwrap();
+            (
+                client.run_sql("select * from cpu".to_string()).await,
+                client
+                    .run_sql_batches("select * from cpu".to_string())
+                    .await,
+            )
+        })
+        .unwrap();
+
+        assert!(matches!(result.0, Err(Error::NoDatabaseSelected)));
+        assert!(matches!(result.1, Err(Error::NoDatabaseSelected)));
+    }
+
+    // Collects the fields of the events logged while it is the default subscriber
+    struct CapturedLogs(Arc<Mutex<Vec<String>>>);
+
+    impl Subscriber for CapturedLogs {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut line = String::new();
+            event.record(&mut |field: &Field, value: &dyn std::fmt::Debug| {
+                line.push_str(&format!("{}={:?} ", field.name(), value));
+            });
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn enter(&self, _span: &span::Id) {}
+
+        fn exit(&self, _span: &span::Id) {}
+    }
+
+    // The log lines of a query against a server that never answers
+    fn logs_of_query(quiet: bool) -> String {
+        use crate::iox::{block_on_iox, iox_connector};
+
+        // kept open so connecting succeeds, the query times out unanswered
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+        let logs = Arc::new(Mutex::new(vec![]));
+
+        observability_deps::tracing::subscriber::with_default(CapturedLogs(logs.clone()), || {
+            block_on_iox(async {
+                let mut client = Nuclient::connect(&addr, connector).await.unwrap();
+                client.set_query_engine(QueryEngine::Remote(RemoteDatabase::new(
+                    "bananas".to_string(),
+                    None,
+                )));
+                client.set_query_timeout(Some(Duration::from_millis(100)));
+                client.set_retries(0);
+                client.set_quiet(quiet);
+                let _ = client
+                    .run_sql_batches("select secret_column from cpu".to_string())
+                    .await;
+            })
+            .unwrap();
+        });
+
+        let lines = logs.lock().unwrap();
+        lines.join("\n")
+    }
+
+    #[test]
+    fn quiet_client_keeps_the_query_out_of_the_logs() {
+        let logs = logs_of_query(true);
+
+        assert!(logs.contains("bananas"), "{}", logs);
+        assert!(!logs.contains("secret_column"), "{}", logs);
+        assert!(logs.contains("bytes redacted"), "{}", logs);
+
+        assert!(logs_of_query(false).contains("secret_column"));
+    }
+
+    // Answers GetNamespaces with the names, counting the requests it gets
+    fn mock_namespace_server(names: &'static [&'static str]) -> (String, Arc<AtomicUsize>) {
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Request, Response, Server};
+        use influxdb_iox_client::namespace::generated_types::{GetNamespacesResponse, Namespace};
+        use prost::Message;
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+
+        let counter = requests.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            runtime.block_on(async move {
+                let make_service = make_service_fn(move |_| {
+                    let counter = counter.clone();
+                    async move {
+                        Ok::<_, hyper::Error>(service_fn(move |_: Request<Body>| {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            async move {
+                                let message = GetNamespacesResponse {
+                                    namespaces: names
+                                        .iter()
+                                        .enumerate()
+                                        .map(|(id, name)| Namespace {
+                                            id: id as i64,
+                                            name: name.to_string(),
+                                            ..Default::default()
+                                        })
+                                        .collect(),
+                                }
+                                .encode_to_vec();
+
+                                // a gRPC frame is uncompressed, its length, then the message
+                                let mut frame = vec![0];
+                                frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                                frame.extend_from_slice(&message);
+
+                                let (mut sender, body) = Body::channel();
+                                tokio::spawn(async move {
+                                    let _ = sender.send_data(frame.into()).await;
+                                    let mut trailers = http::HeaderMap::new();
+                                    trailers.insert("grpc-status", "0".parse().unwrap());
+                                    let _ = sender.send_trailers(trailers).await;
+                                });
+
+                                Response::builder()
+                                    .header("content-type", "application/grpc")
+                                    .body(body)
+                            }
+                        }))
+                    }
+                });
+
+                Server::from_tcp(listener)
+                    .unwrap()
+                    .http2_only(true)
+                    .serve(make_service)
+                    .await
+            })
+        });
+
+        (addr, requests)
+    }
+
+    #[test]
+    fn database_names_are_listed_once_per_address_and_token() {
+        let (addr, requests) = mock_namespace_server(&["apples", "bananas"]);
+
+        assert_eq!(
+            list_database_names(&addr, None, None).unwrap(),
+            vec!["apples", "bananas"]
+        );
+        assert_eq!(
+            list_database_names(&addr, None, None).unwrap(),
+            vec!["apples", "bananas"]
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // another token may see other databases
+        list_database_names(&addr, Some("s3cr3t"), None).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn database_names_are_listed_again_when_stale() {
+        let (addr, requests) = mock_namespace_server(&["bananas"]);
+        let key = || (addr.clone(), None);
+        let fetch = || fetch_database_names(&addr, None, None);
+        let now = Instant::now();
+
+        cached_database_names(key(), now, fetch).unwrap();
+        cached_database_names(key(), now + Duration::from_secs(1), fetch).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        cached_database_names(key(), now + DATABASE_NAMES_TTL, fetch).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn failed_database_listing_is_not_cached() {
+        let (addr, requests) = mock_namespace_server(&["bananas"]);
+        let now = Instant::now();
+
+        assert!(
+            cached_database_names((addr.clone(), None), now, || NoRemoteDatabaseSnafu.fail())
+                .is_err()
+        );
+        assert_eq!(
+            cached_database_names((addr.clone(), None), now, || {
+                fetch_database_names(&addr, None, None)
+            })
+            .unwrap(),
+            vec!["bananas"]
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn query_stats_counts_rows_across_batches() {
+        let batch = RecordBatch::try_from_iter(vec![(
+            "user",
+            Arc::new(Int64Array::from(vec![1, 2, 3])) as ArrayRef,
+        )])
+        .unwrap();
+
+        let stats = QueryStats::new(
+            &[batch.clone(), batch.slice(0, 1), batch],
+            Duration::from_millis(5),
+        );
+
+        assert_eq!(stats.rows, 7);
+        assert_eq!(stats.batches, 3);
+        assert!(stats.bytes > 0);
+        assert_eq!(stats.elapsed, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn row_count_sums_a_known_batch_set() {
+        let batch = RecordBatch::try_from_iter(vec![(
+            "user",
+            Arc::new(Int64Array::from(vec![1, 2, 3, 4])) as ArrayRef,
+        )])
+        .unwrap();
+
+        assert_eq!(row_count(&[batch.clone(), batch.slice(0, 2), batch]), 10);
+        assert_eq!(row_count(&[]), 0);
+    }
+
+    #[test]
+    fn with_timeout_fails_when_the_future_is_too_slow() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        };
+
+        let result =
+            crate::iox::block_on_iox(with_timeout(Some(Duration::from_millis(10)), slow)).unwrap();
+
+        assert!(matches!(result, Err(Error::Timeout { .. })));
+    }
+
+    #[test]
+    fn with_timeout_disabled_waits_for_the_result() {
+        let result = crate::iox::block_on_iox(with_timeout(None, async { Ok(42) })).unwrap();
+
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    fn transient_errors_are_told_by_their_grpc_code() {
+        use influxdb_iox_client::flight::Error as FlightError;
+
+        assert!(is_transient_error(&tonic::Status::unavailable("down")));
+        assert!(is_transient_error(&tonic::Status::deadline_exceeded(
+            "slow"
+        )));
+        assert!(is_transient_error(&remote_query_error(
+            FlightError::GrpcError(tonic::Status::unavailable("down"))
+        )));
+
+        // the message says nothing about whether another attempt helps
+        assert!(!is_transient_error(&tonic::Status::invalid_argument(
+            "column Unavailable not found"
+        )));
+        assert!(!is_transient_error(&Error::NoRemoteDatabase));
+    }
+
+    #[test]
+    fn with_retries_recovers_from_a_transient_failure() {
+        let attempts = std::cell::Cell::new(0);
+
+        let flaky = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+
+            async move {
+                match attempt {
+                    1 => Err(tonic::Status::unavailable("connection refused")),
+                    _ => Ok(attempt),
+                }
+            }
+        };
+
+        let result = crate::iox::block_on_iox(with_retries(3, flaky)).unwrap();
+
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn with_retries_gives_up_on_other_failures() {
+        let attempts = std::cell::Cell::new(0);
+
+        let invalid = || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>(tonic::Status::invalid_argument("bad sql")) }
+        };
+
+        let result = crate::iox::block_on_iox(with_retries(3, invalid)).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn with_retries_stops_after_the_last_attempt() {
+        let attempts = std::cell::Cell::new(0);
+
+        let down = || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>(tonic::Status::unavailable("down")) }
+        };
+
+        let result = crate::iox::block_on_iox(with_retries(2, down)).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    // Hands out 1..=len, setting the interrupt flag when `interrupt_at` is reached
+    struct MockResults {
+        next: usize,
+        len: usize,
+        interrupt_at: Option<(usize, Arc<AtomicBool>)>,
+    }
+
+    impl ResultSource for MockResults {
+        type Item = usize;
+
+        fn next_item(&mut self) -> NextItem<'_, usize> {
+            self.next += 1;
+            if let Some((at, ctrlc)) = &self.interrupt_at {
+                if self.next == *at {
+                    ctrlc.store(true, Ordering::SeqCst);
+                }
+            }
+            let item = (self.next <= self.len).then(|| self.next);
+
+            Box::pin(async move { Ok(item) })
+        }
+    }
+
+    // Hands out 1..=len, each item after a delay, the item at `stall_at` never
+    struct SlowResults {
+        next: usize,
+        len: usize,
+        delay: Duration,
+        stall_at: Option<usize>,
+    }
+
+    impl ResultSource for SlowResults {
+        type Item = usize;
+
+        fn next_item(&mut self) -> NextItem<'_, usize> {
+            self.next += 1;
+            let item = (self.next <= self.len).then(|| self.next);
+            let delay = match self.stall_at {
+                Some(at) if at == self.next => Duration::from_secs(3600),
+                _ => self.delay,
+            };
+
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                Ok(item)
+            })
+        }
+    }
+
+    #[test]
+    fn slow_but_steady_stream_outlasts_the_stall_timeout() {
+        let slow = SlowResults {
+            next: 0,
+            len: 5,
+            delay: Duration::from_millis(30),
+            stall_at: None,
+        };
+        // the whole stream takes longer than a single stall timeout
+        let mut results = StallTimeout::new(slow, Some(Duration::from_millis(100)));
+
+        let result =
+            crate::iox::block_on_iox(collect_until_interrupted(&mut results, None)).unwrap();
+
+        assert_eq!(result.unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn stalled_stream_fails_with_the_stall_timeout() {
+        let stalling = SlowResults {
+            next: 0,
+            len: 5,
+            delay: Duration::from_millis(1),
+            stall_at: Some(3),
+        };
+        let mut results = StallTimeout::new(stalling, Some(Duration::from_millis(50)));
+
+        let result =
+            crate::iox::block_on_iox(collect_until_interrupted(&mut results, None)).unwrap();
+
+        assert!(matches!(result, Err(Error::Stalled { .. })));
+        assert_eq!(results.source.next, 3);
+    }
+
+    // Hands out one item, then fails like a server dropping the query
+    struct FailingResults {
+        next: usize,
+    }
+
+    impl ResultSource for FailingResults {
+        type Item = usize;
+
+        fn next_item(&mut self) -> NextItem<'_, usize> {
+            use influxdb_iox_client::flight::Error as FlightError;
+
+            self.next += 1;
+            let item = match self.next {
+                1 => Ok(Some(1)),
+                _ => Err(remote_query_error(FlightError::GrpcError(
+                    tonic::Status::internal("ingester went away"),
+                ))),
+            };
+
+            Box::pin(async move { item })
+        }
+    }
+
+    #[test]
+    fn failure_mid_stream_keeps_its_reason() {
+        let mut results = FailingResults { next: 0 };
+
+        let result =
+            crate::iox::block_on_iox(collect_until_interrupted(&mut results, None)).unwrap();
+
+        match result {
+            Err(error) => {
+                assert!(matches!(error, Error::RemoteQueryFailedMidStream { .. }));
+                assert_eq!(error.batches_received(), Some(1));
+                assert_eq!(error.grpc_code(), Some(tonic::Code::Internal));
+                assert!(error.to_string().contains("ingester went away"));
+            }
+            Ok(items) => panic!("expected the query to fail, got {:?}", items),
+        }
+    }
+
+    #[test]
+    fn failure_to_start_is_not_mid_stream() {
+        use influxdb_iox_client::flight::Error as FlightError;
+
+        let error = remote_query_error(FlightError::GrpcError(tonic::Status::unavailable("down")));
+
+        assert!(matches!(error, Error::RunningRemoteQuery { .. }));
+        assert_eq!(error.batches_received(), None);
+    }
+
+    #[test]
+    fn interrupt_mid_stream_cancels_the_query() {
+        let ctrlc = Arc::new(AtomicBool::new(false));
+        let mut results = MockResults {
+            next: 0,
+            len: 5,
+            interrupt_at: Some((2, ctrlc.clone())),
+        };
+
+        let result =
+            crate::iox::block_on_iox(collect_until_interrupted(&mut results, Some(ctrlc))).unwrap();
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(results.next, 2);
+    }
+
+    #[test]
+    fn uninterrupted_stream_is_collected() {
+        let ctrlc = Arc::new(AtomicBool::new(false));
+        let mut results = MockResults {
+            next: 0,
+            len: 3,
+            interrupt_at: None,
+        };
+
+        let result =
+            crate::iox::block_on_iox(collect_until_interrupted(&mut results, Some(ctrlc))).unwrap();
+
+        assert_eq!(result.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn fold_counts_without_collecting() {
+        let mut results = MockResults {
+            next: 0,
+            len: 4,
+            interrupt_at: None,
+        };
+
+        let total = crate::iox::block_on_iox(fold_until_interrupted(
+            &mut results,
+            None,
+            0,
+            |total, item| total + item,
+        ))
+        .unwrap();
+
+        assert_eq!(total.unwrap(), 10);
+    }
+
+    #[test]
+    fn read_info_carries_sql_query() {
+        let read_info = read_info("bananas", "select * from cpu", QueryLanguage::Sql).unwrap();
+
+        assert_eq!(read_info.namespace_name, "bananas");
+        assert_eq!(read_info.sql_query, "select * from cpu");
+    }
+
+    #[test]
+    fn remote_database_defaults_namespace_to_its_name() {
+        let database = RemoteDatabase::new("bananas".to_string(), None);
+
+        assert_eq!(database.name, "bananas");
+        assert_eq!(database.namespace, "bananas");
+    }
+
+    #[test]
+    fn remote_database_passes_namespace_through_unchanged() {
+        let database =
+            RemoteDatabase::new("bananas".to_string(), Some("myorg_bananas".to_string()));
+
+        assert_eq!(database.name, "bananas");
+        assert_eq!(database.namespace, "myorg_bananas");
+    }
+
+    #[cfg(not(feature = "iox-influxql"))]
+    #[test]
+    fn influxql_needs_the_feature() {
+        let read_info = read_info("bananas", "SELECT * FROM cpu", QueryLanguage::InfluxQl);
+
+        assert!(matches!(read_info, Err(Error::InfluxQlUnavailable)));
+    }
+
+    #[test]
+    fn local_table_is_named_after_file_stem() {
+        let table = LocalTable::from_path(PathBuf::from("/data/cpu.parquet"));
+
+        assert_eq!(table.name, "cpu");
+        assert_eq!(table.path, PathBuf::from("/data/cpu.parquet"));
+    }
+
+    #[cfg(feature = "iox-local")]
+    #[test]
+    fn run_sql_batches_returns_the_arrow_batches() {
+        use crate::iox::{block_on_iox, iox_connector};
+        use arrow::datatypes::DataType;
+
+        let path = std::env::temp_dir().join("nu_iox_run_sql_batches_test.csv");
+        std::fs::write(&path, "region,user\nla,955\npa,9599\n").unwrap();
+
+        // kept open so connecting succeeds, the query runs on the local file
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+
+        let batches = block_on_iox(async {
+            let mut client = Nuclient::connect(&addr, connector).await.unwrap();
+            client.set_query_engine(QueryEngine::Local(vec![LocalTable {
+                name: "cpu".to_string(),
+                path: path.clone(),
+            }]));
+            client
+                .run_sql_batches("select region, user from cpu order by user".to_string())
+                .await
+        })
+        .unwrap()
+        .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let schema = batches[0].schema();
+        let fields: Vec<(&str, &DataType)> = schema
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![("region", &DataType::Utf8), ("user", &DataType::Int64)]
+        );
+
+        let users = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(row_count(&batches), 2);
+        assert_eq!(users.values(), &[955, 9599]);
+    }
+
+    #[cfg(feature = "iox-local")]
+    #[test]
+    fn local_sql_queries_csv_file() {
+        let path = std::env::temp_dir().join("nu_iox_local_sql_test.csv");
+        std::fs::write(&path, "region,user\nla,955\npa,9599\n").unwrap();
+
+        let tables = vec![LocalTable {
+            name: "cpu".to_string(),
+            path: path.clone(),
+        }];
+        let batches = crate::iox::block_on_iox(run_local_sql(
+            &tables,
+            "select user from cpu where region = 'pa'",
+        ))
+        .unwrap()
+        .unwrap();
+
+        let _ = std::fs::remove_file(path);
+
+        let total_rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(total_rows, 1);
+    }
 }
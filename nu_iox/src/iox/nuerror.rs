@@ -3,6 +3,8 @@ use nu_protocol::ast::Call;
 use nu_protocol::ShellError;
 use std::fmt;
 
+use super::nuclient::Error;
+
 #[derive(Copy, Clone, Debug)]
 pub enum CommandType {
     Sql,
@@ -15,12 +17,22 @@ pub struct NuIoxErrorHandler {
     ctype: CommandType,
     error: String,
     #[allow(dead_code)]
-    nu_iox_error: NuIoxError,
+    nu_iox_error: Option<NuIoxError>,
+    // the gRPC status code, when the error came from a failed remote query
+    code: Option<tonic::Code>,
+    // the messages of the error's sources, outermost first
+    causes: Vec<String>,
+    // the batches received before a remote query failed partway
+    batches_received: Option<usize>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum NuIoxErrorType {
     TableNotFound,
+    ColumnNotFound,
+    SyntaxError,
+    Unauthenticated,
+    Unknown,
 }
 
 #[derive(Clone, Debug)]
@@ -40,21 +52,69 @@ impl fmt::Display for NuIoxErrorType {
     }
 }
 
+impl NuIoxErrorType {
+    // Pick the error type from the gRPC status and the server message
+    //
+    // The wording of DataFusion's errors changes between versions, e.g.
+    // "table 'public.iox.cpu' not found" or "Table or CTE with name 'cpu'
+    // not found", so only the key words are looked for, in any case.
+    pub fn classify(status: &str, message: &str) -> Self {
+        let message = message.to_lowercase();
+
+        if status.contains("Unauthenticated") {
+            NuIoxErrorType::Unauthenticated
+        } else if message.contains("no field named")
+            || (message.contains("column") && is_missing(&message))
+        {
+            NuIoxErrorType::ColumnNotFound
+        } else if message.contains("table") && is_missing(&message) {
+            NuIoxErrorType::TableNotFound
+        } else if message.contains("parsererror") || message.contains("syntax error") {
+            NuIoxErrorType::SyntaxError
+        } else {
+            NuIoxErrorType::Unknown
+        }
+    }
+}
+
 impl NuIoxError {
-    pub fn build(data: &str) -> Self {
-        let details = remove_details(data).unwrap().1;
-        let (message0, remainder) = get_message(details).unwrap();
-        let (status0, header0) = get_header(&remainder).unwrap();
+    // Returns None when the error string is not shaped like a gRPC status,
+    // e.g. a transport error raised before the server ever answered, unless
+    // it still reads like one of the known errors
+    pub fn build(data: &str) -> Option<Self> {
+        Self::build_from_status(data).or_else(|| Self::build_from_text(data))
+    }
+
+    fn build_from_status(data: &str) -> Option<Self> {
+        let details = remove_details(data).ok()?.1;
+        let (message0, remainder) = get_message(details).ok()?;
+        let (status0, header0) = get_header(remainder).ok()?;
 
         let header1 = remove_colon_from_string(&header0.to_string());
         let message1 = remove_slash_from_string(&message0.to_string());
 
-        Self {
+        Some(Self {
             start: data.to_string(),
-            error_type: NuIoxErrorType::TableNotFound,
+            error_type: NuIoxErrorType::classify(status0, &message1),
             header: header1,
             status: status0.to_string(),
             message: message1,
+        })
+    }
+
+    // The whole error is the message when it isn't laid out as expected
+    fn build_from_text(data: &str) -> Option<Self> {
+        let message = remove_slash_from_string(&data.trim().to_string());
+
+        match NuIoxErrorType::classify("", &message) {
+            NuIoxErrorType::Unknown => None,
+            error_type => Some(Self {
+                start: data.to_string(),
+                error_type,
+                header: String::new(),
+                status: String::new(),
+                message,
+            }),
         }
     }
 
@@ -75,9 +135,61 @@ impl NuIoxErrorHandler {
             ctype,
             error,
             nu_iox_error,
+            code: None,
+            causes: Vec::new(),
+            batches_received: None,
         }
     }
 
+    /// Like new, but keeps the gRPC status code of a failed remote query and
+    /// the chain of errors that caused it
+    pub fn from_error(ctype: CommandType, error: &Error) -> Self {
+        Self {
+            code: error.grpc_code(),
+            causes: error_causes(error),
+            batches_received: error.batches_received(),
+            ..Self::new(ctype, error.to_string())
+        }
+    }
+
+    // The causes as help text, for errors the parser couldn't classify
+    fn causes_help(&self) -> Option<String> {
+        if self.causes.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.causes
+                .iter()
+                .map(|cause| format!("caused by: {}", cause))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    // Says the query failed partway ahead of any other help
+    fn with_mid_stream_help(&self, help: Option<String>) -> Option<String> {
+        let batches_received = match self.batches_received {
+            None => return help,
+            Some(batches_received) => batches_received,
+        };
+
+        let note = format!(
+            "The query failed partway, after {} batches arrived, and its partial results were dropped",
+            batches_received
+        );
+
+        Some(match help {
+            None => note,
+            Some(help) => format!("{}\n{}", note, help),
+        })
+    }
+
+    fn is_unauthenticated(&self) -> bool {
+        self.code == Some(tonic::Code::Unauthenticated)
+            || matches!(&self.nu_iox_error, Some(e) if e.error_type == NuIoxErrorType::Unauthenticated)
+    }
+
     // Check and see if its an error or a csv
     pub fn nu_iox_error_check(&self) -> Result<String, ShellError> {
         //println!("{:?}", self.error);
@@ -85,17 +197,60 @@ impl NuIoxErrorHandler {
     }
 
     // Trigger an error to see what the Error looks like
+    // If the error could not be parsed the raw error string is surfaced instead
     pub fn nu_iox_error_generic(&self, call: &Call) -> Result<String, ShellError> {
-        return Err(ShellError::GenericError(
-            self.nu_iox_error.message.to_string(),
-            self.nu_iox_error.error_type.to_string(),
-            Some(call.head),
-            None,
-            Vec::new(),
-        ));
+        if self.is_unauthenticated() {
+            return Err(ShellError::GenericError(
+                "Iox authentication failed".into(),
+                "authentication failed: provide --token or set IOX_TOKEN".into(),
+                Some(call.head),
+                None,
+                Vec::new(),
+            ));
+        }
+
+        match &self.nu_iox_error {
+            Some(nu_iox_error) => Err(ShellError::GenericError(
+                nu_iox_error.message.to_string(),
+                nu_iox_error.error_type.to_string(),
+                Some(call.head),
+                self.with_mid_stream_help(match nu_iox_error.error_type {
+                    NuIoxErrorType::Unknown => self.causes_help(),
+                    _ => None,
+                }),
+                Vec::new(),
+            )),
+            None => Err(ShellError::GenericError(
+                "Iox error".to_string(),
+                self.error.trim().to_string(),
+                Some(call.head),
+                self.with_mid_stream_help(self.causes_help()),
+                Vec::new(),
+            )),
+        }
     }
 }
 
+// Expects a lowercased message
+fn is_missing(message: &str) -> bool {
+    ["not found", "does not exist", "doesn't exist"]
+        .iter()
+        .any(|missing| message.contains(missing))
+}
+
+// Walks std::error::Error::source, the error itself is not included
+fn error_causes(error: &dyn std::error::Error) -> Vec<String> {
+    let mut causes = Vec::new();
+    let mut source = error.source();
+
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+
+    causes
+}
+
 fn remove_details(s: &str) -> IResult<&str, &str> {
     let details: &'static str = ", details: ";
     take_until(details)(s)
@@ -118,3 +273,215 @@ fn remove_slash_from_string(s: &String) -> String {
 fn remove_colon_from_string(s: &String) -> String {
     s.replace(&[':'][..], "")
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nu_protocol::Span;
+
+    const TABLE_NOT_FOUND: &str = "Error running remote query: status: InvalidArgument, message: \"Error while planning query: Error during planning: table 'public.iox.cpu' not found\", details: [], metadata: MetadataMap { headers: {} }";
+
+    const CONNECTION_REFUSED: &str =
+        "transport error: error trying to connect: tcp connect error: Connection refused (os error 111)";
+
+    const COLUMN_NOT_FOUND: &str = "Error running remote query: status: InvalidArgument, message: \"Error while planning query: Schema error: No field named 'cpu.bogus'. Valid fields are 'cpu.region', 'cpu.time', 'cpu.user'.\", details: [], metadata: MetadataMap { headers: {} }";
+
+    const SYNTAX_ERROR: &str = "Error running remote query: status: InvalidArgument, message: \"Error while planning query: SQL error: ParserError(\\\"Expected an SQL statement, found: selec\\\")\", details: [], metadata: MetadataMap { headers: {} }";
+
+    const UNAUTHENTICATED: &str = "Error running remote query: status: Unauthenticated, message: \"invalid token\", details: [], metadata: MetadataMap { headers: {} }";
+
+    const UNKNOWN: &str = "Error running remote query: status: Internal, message: \"something broke\", details: [], metadata: MetadataMap { headers: {} }";
+
+    fn error_type(data: &str) -> NuIoxErrorType {
+        NuIoxError::build(data)
+            .expect("error should parse")
+            .error_type
+    }
+
+    #[test]
+    fn classifies_table_not_found() {
+        assert_eq!(error_type(TABLE_NOT_FOUND), NuIoxErrorType::TableNotFound);
+    }
+
+    #[test]
+    fn classifies_table_not_found_across_message_variants() {
+        let variants = [
+            "Error during planning: table 'public.iox.cpu' not found",
+            "Error during planning: Table or CTE with name 'cpu' not found",
+            "Error during planning: Table 'cpu' does not exist",
+            "TABLE cpu NOT FOUND",
+            "table \"cpu\" doesn't exist",
+        ];
+
+        for variant in variants {
+            let error = format!(
+                "Error running remote query: status: InvalidArgument, message: \"{}\", details: [], metadata: MetadataMap {{ headers: {{}} }}",
+                variant
+            );
+            assert_eq!(
+                error_type(&error),
+                NuIoxErrorType::TableNotFound,
+                "{}",
+                variant
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_table_not_found_without_the_usual_layout() {
+        let error = "Error running remote query: status: InvalidArgument, message: \"Table or CTE with name 'cpu' not found\"";
+
+        assert_eq!(error_type(error), NuIoxErrorType::TableNotFound);
+    }
+
+    #[test]
+    fn missing_column_of_a_table_is_a_column_error() {
+        assert_eq!(
+            NuIoxErrorType::classify("", "column 'bogus' not found in table 'cpu'"),
+            NuIoxErrorType::ColumnNotFound
+        );
+    }
+
+    #[test]
+    fn classifies_column_not_found() {
+        assert_eq!(error_type(COLUMN_NOT_FOUND), NuIoxErrorType::ColumnNotFound);
+    }
+
+    #[test]
+    fn classifies_syntax_error() {
+        assert_eq!(error_type(SYNTAX_ERROR), NuIoxErrorType::SyntaxError);
+    }
+
+    #[test]
+    fn classifies_unauthenticated() {
+        assert_eq!(error_type(UNAUTHENTICATED), NuIoxErrorType::Unauthenticated);
+    }
+
+    #[test]
+    fn classifies_everything_else_as_unknown() {
+        assert_eq!(error_type(UNKNOWN), NuIoxErrorType::Unknown);
+    }
+
+    #[test]
+    fn build_parses_grpc_status_error() {
+        let nu_iox_error = NuIoxError::build(TABLE_NOT_FOUND).expect("error should parse");
+
+        assert_eq!(nu_iox_error.status.trim(), "status: InvalidArgument");
+        assert!(nu_iox_error.message.contains("not found"));
+    }
+
+    #[test]
+    fn build_does_not_panic_on_connection_refused() {
+        assert!(NuIoxError::build(CONNECTION_REFUSED).is_none());
+    }
+
+    #[test]
+    fn build_does_not_panic_on_malformed_string() {
+        assert!(NuIoxError::build("").is_none());
+        assert!(NuIoxError::build(", details: status: but no message").is_none());
+    }
+
+    fn generic_error_msg(handler: &NuIoxErrorHandler) -> String {
+        match handler.nu_iox_error_generic(&Call::new(Span::test_data())) {
+            Err(ShellError::GenericError(_, msg, _, _, _)) => msg,
+            _ => panic!("expected a generic error"),
+        }
+    }
+
+    #[test]
+    fn unauthenticated_status_asks_for_a_token() {
+        use super::super::nuclient::remote_query_error;
+        use influxdb_iox_client::flight::Error as FlightError;
+
+        let error = remote_query_error(FlightError::GrpcError(tonic::Status::unauthenticated(
+            "invalid token",
+        )));
+        let handler = NuIoxErrorHandler::from_error(CommandType::Sql, &error);
+
+        assert_eq!(
+            generic_error_msg(&handler),
+            "authentication failed: provide --token or set IOX_TOKEN"
+        );
+
+        let handler = NuIoxErrorHandler::new(CommandType::Sql, UNAUTHENTICATED.to_string());
+        assert_eq!(
+            generic_error_msg(&handler),
+            "authentication failed: provide --token or set IOX_TOKEN"
+        );
+    }
+
+    #[test]
+    fn mid_stream_failure_says_the_results_were_dropped() {
+        let error = Error::RemoteQueryFailedMidStream {
+            source: influxdb_iox_client::flight::Error::GrpcError(tonic::Status::internal(
+                "ingester went away",
+            )),
+            code: Some(tonic::Code::Internal),
+            batches_received: 3,
+        };
+        let handler = NuIoxErrorHandler::from_error(CommandType::Sql, &error);
+
+        match handler.nu_iox_error_generic(&Call::new(Span::test_data())) {
+            Err(ShellError::GenericError(_, _, _, Some(help), _)) => assert!(
+                help.starts_with("The query failed partway, after 3 batches arrived"),
+                "{}",
+                help
+            ),
+            other => panic!("expected an error with help, got {:?}", other),
+        }
+    }
+
+    // One layer of a nested error, pointing at the layer below
+    #[derive(Debug)]
+    struct Layer {
+        msg: &'static str,
+        source: Option<Box<Layer>>,
+    }
+
+    impl fmt::Display for Layer {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.msg)
+        }
+    }
+
+    impl std::error::Error for Layer {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.source.as_deref().map(|s| s as _)
+        }
+    }
+
+    #[test]
+    fn unclassified_error_lists_its_causes_in_the_help() {
+        let error = Error::LoadingRemoteState {
+            source: Box::new(Layer {
+                msg: "h2 protocol error",
+                source: Some(Box::new(Layer {
+                    msg: "connection reset by peer",
+                    source: None,
+                })),
+            }),
+        };
+        let handler = NuIoxErrorHandler::from_error(CommandType::Sql, &error);
+
+        match handler.nu_iox_error_generic(&Call::new(Span::test_data())) {
+            Err(ShellError::GenericError(_, _, _, Some(help), _)) => assert_eq!(
+                help,
+                "caused by: h2 protocol error\ncaused by: connection reset by peer"
+            ),
+            other => panic!("expected an error with help, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn handler_surfaces_raw_error_when_unparsed() {
+        let handler = NuIoxErrorHandler::new(CommandType::Sql, CONNECTION_REFUSED.to_string());
+        let call = Call::new(Span::test_data());
+
+        match handler.nu_iox_error_generic(&call) {
+            Err(ShellError::GenericError(_, msg, _, _, _)) => {
+                assert_eq!(msg, CONNECTION_REFUSED)
+            }
+            _ => panic!("expected a generic error"),
+        }
+    }
+}
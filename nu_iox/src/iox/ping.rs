@@ -0,0 +1,225 @@
+use super::nuclient::Nuclient;
+use super::util::{
+    block_on_iox, iox_connector, resolve_iox_keepalive, resolve_iox_profile, resolve_iox_tls_ca,
+    timeout_from_value, validate_iox_addr, IoxConnector, DEFAULT_IOX_ADDR,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, Spanned,
+    SyntaxShape, Value,
+};
+use std::time::{Duration, Instant};
+
+/// How long ioxping waits for an answer when --timeout is not given
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct Ioxping;
+
+impl Command for Ioxping {
+    fn name(&self) -> &str {
+        "ioxping"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxping")
+            .named(
+                "addr",
+                SyntaxShape::String,
+                format!(
+                    "address of the Iox server, defaults to IOX_ADDR or {}",
+                    DEFAULT_IOX_ADDR
+                ),
+                Some('a'),
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "timeout",
+                SyntaxShape::Duration,
+                "how long to wait for an answer, defaults to 5sec",
+                None,
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "Check that the Iox server is reachable."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Lists the namespaces as a lightweight request. An unreachable server is not an error, the record has reachable set to false and the reason in error."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let addr_flag: Option<Spanned<String>> = call.get_flag(engine_state, stack, "addr")?;
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+        let timeout = timeout_from_value(
+            call.get_flag(engine_state, stack, "timeout")?,
+            DEFAULT_PING_TIMEOUT,
+        )?;
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr_span = addr_flag.as_ref().map_or(call.head, |a| a.span);
+        let addr = profile.resolve_addr(stack, engine_state, addr_flag.map(|a| a.item));
+        let addr = validate_iox_addr(&addr, addr_span)?;
+
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+        let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+            .with_keepalive(keepalive);
+
+        let start = Instant::now();
+        let result = tokio_block_ping(connector, &addr, timeout)?;
+
+        Ok(ping_to_value(&addr, result, start.elapsed(), call.head).into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Check that the default Iox server answers",
+                example: r#"ioxping"#,
+                result: None,
+            },
+            Example {
+                description: "Only run a query when the server is up",
+                example: r#"if (ioxping -a http://iox.example.com:8082).reachable { ioxsql "select * from cpu" }"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+pub fn ping_to_value(
+    addr: &str,
+    result: Result<(), String>,
+    latency: Duration,
+    span: Span,
+) -> Value {
+    let (reachable, error) = match result {
+        Ok(()) => (true, Value::Nothing { span }),
+        Err(error) => (false, Value::String { val: error, span }),
+    };
+
+    Value::Record {
+        cols: vec![
+            "addr".to_string(),
+            "reachable".to_string(),
+            "latency_ms".to_string(),
+            "error".to_string(),
+        ],
+        vals: vec![
+            Value::String {
+                val: addr.to_string(),
+                span,
+            },
+            Value::Bool {
+                val: reachable,
+                span,
+            },
+            Value::Int {
+                val: latency.as_millis() as i64,
+                span,
+            },
+            error,
+        ],
+        span,
+    }
+}
+
+// Only a runtime failure is an Err, anything the server does is in the inner result
+pub fn tokio_block_ping(
+    connector: IoxConnector,
+    addr: &str,
+    timeout: Option<Duration>,
+) -> Result<Result<(), String>, ShellError> {
+    let ping = async move {
+        let connection = connector.connect(addr).await.map_err(|e| match e {
+            ShellError::GenericError(_, msg, ..) => msg,
+            e => e.to_string(),
+        })?;
+
+        let mut repl = Nuclient::new(connection);
+        repl.set_retries(0);
+
+        repl.list_namespace_names()
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    };
+
+    block_on_iox(async move {
+        match timeout {
+            None => ping.await,
+            Some(timeout) => tokio::time::timeout(timeout, ping)
+                .await
+                .unwrap_or_else(|_| Err(format!("no answer within {:?}", timeout))),
+        }
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stopped_server_is_not_reachable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+        let result = tokio_block_ping(connector, &addr, Some(DEFAULT_PING_TIMEOUT)).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn ping_record_reports_the_error() {
+        let span = Span::test_data();
+        let value = ping_to_value(
+            "http://127.0.0.1:8082",
+            Err("connection refused".to_string()),
+            Duration::from_millis(3),
+            span,
+        );
+
+        assert_eq!(
+            value.get_data_by_key("reachable"),
+            Some(Value::Bool { val: false, span })
+        );
+        assert_eq!(
+            value.get_data_by_key("error"),
+            Some(Value::test_string("connection refused"))
+        );
+        assert_eq!(
+            value.get_data_by_key("latency_ms"),
+            Some(Value::Int { val: 3, span })
+        );
+    }
+}
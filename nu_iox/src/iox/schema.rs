@@ -0,0 +1,368 @@
+use super::convert::{array_value_to_nu_value, batches_to_value};
+use super::dbnames::dbname_shape;
+use super::nuclient::{Error, RemoteDatabase};
+use super::nuerror::NuIoxErrorHandler;
+use super::sql::{tokio_block_sql_batches, SqlQueryOptions};
+use super::util::{
+    headers_from_value, iox_connector, resolve_iox_dbname, resolve_iox_keepalive,
+    resolve_iox_profile, resolve_iox_tls_ca, retries_from_flag, timeout_from_value,
+    validate_iox_addr, IoxConnector, DEFAULT_IOX_ADDR, DEFAULT_QUERY_TIMEOUT,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, Spanned,
+    SyntaxShape,
+};
+
+use arrow::record_batch::RecordBatch;
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct Ioxschema;
+
+impl Command for Ioxschema {
+    fn name(&self) -> &str {
+        "ioxschema"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        with_catalog_flags(Signature::build("ioxschema").optional(
+            "table",
+            SyntaxShape::String,
+            "only show the columns of this table",
+        ))
+    }
+
+    fn usage(&self) -> &str {
+        "Show the tables and columns of an Iox Database."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Reads information_schema.columns of the database, one row per column with its table and SQL data type."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let table: Option<String> = call.opt(engine_state, stack, 0)?;
+        let batches = run_catalog_query(engine_state, stack, call, schema_sql(table.as_deref()))?;
+
+        Ok(batches_to_value(&batches, call.head)?.into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Show every table and column of the default database",
+                example: r#"ioxschema"#,
+                result: None,
+            },
+            Example {
+                description: "Show the columns of the cpu table in the bananas database",
+                example: r#"ioxschema -d bananas cpu"#,
+                result: None,
+            },
+            Example {
+                description: "List the tables of the default database",
+                example: r#"ioxschema | get table_name | uniq"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+/// Adds the connection flags of the commands that read the information schema
+pub fn with_catalog_flags(signature: Signature) -> Signature {
+    signature
+        .named(
+            "dbname",
+            dbname_shape(),
+            "name of the database to describe",
+            Some('d'),
+        )
+        .named(
+            "addr",
+            SyntaxShape::String,
+            format!(
+                "address of the Iox server, defaults to IOX_ADDR or {}",
+                DEFAULT_IOX_ADDR
+            ),
+            Some('a'),
+        )
+        .named(
+            "token",
+            SyntaxShape::String,
+            "authorization token for the Iox server, defaults to IOX_TOKEN",
+            Some('t'),
+        )
+        .named(
+            "tls-ca",
+            SyntaxShape::Filepath,
+            "PEM certificate authority for an https Iox server, defaults to IOX_TLS_CA",
+            None,
+        )
+        .named(
+            "header",
+            SyntaxShape::Any,
+            "metadata header sent with every request as key=value, or a list of them, e.g. x-tenant-id=bananas",
+            None,
+        )
+        .named(
+            "retries",
+            SyntaxShape::Int,
+            "retries for an unavailable server, with exponential backoff, defaults to 3",
+            None,
+        )
+        .named(
+            "timeout",
+            SyntaxShape::Duration,
+            "how long the query may run, defaults to 30sec, 0sec waits forever",
+            None,
+        )
+        .named(
+            "profile",
+            SyntaxShape::String,
+            "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+            None,
+        )
+        .category(Category::Filters)
+}
+
+/// The server, database and query settings given by the flags of with_catalog_flags
+pub struct CatalogConnection {
+    pub connector: IoxConnector,
+    pub addr: String,
+    pub database: RemoteDatabase,
+    pub timeout: Option<Duration>,
+    pub retries: usize,
+}
+
+/// Resolves the flags from with_catalog_flags, falling back to the env and config
+pub fn catalog_connection(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+) -> Result<CatalogConnection, ShellError> {
+    let db: Option<String> = call.get_flag(engine_state, stack, "dbname")?;
+    let addr_flag: Option<Spanned<String>> = call.get_flag(engine_state, stack, "addr")?;
+    let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+    let retries = retries_from_flag(call.get_flag(engine_state, stack, "retries")?)?;
+    let timeout = timeout_from_value(
+        call.get_flag(engine_state, stack, "timeout")?,
+        DEFAULT_QUERY_TIMEOUT,
+    )?;
+
+    let database = RemoteDatabase::new(
+        resolve_iox_dbname(stack, engine_state, db, call.head)?,
+        None,
+    );
+
+    let profile = resolve_iox_profile(
+        stack,
+        engine_state,
+        call.get_flag(engine_state, stack, "profile")?,
+    )?;
+    let addr_span = addr_flag.as_ref().map_or(call.head, |a| a.span);
+    let addr = profile.resolve_addr(stack, engine_state, addr_flag.map(|a| a.item));
+    let addr = validate_iox_addr(&addr, addr_span)?;
+
+    let token = profile.resolve_token(stack, engine_state, token);
+    let tls_ca = resolve_iox_tls_ca(
+        stack,
+        engine_state,
+        call.get_flag(engine_state, stack, "tls-ca")?,
+        call.head,
+    );
+    let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+    let headers = headers_from_value(call.get_flag(engine_state, stack, "header")?)?;
+    let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+        .with_keepalive(keepalive)
+        .with_headers(headers);
+
+    Ok(CatalogConnection {
+        connector,
+        addr,
+        database,
+        timeout,
+        retries,
+    })
+}
+
+/// Runs an information schema query with the flags from with_catalog_flags
+pub fn run_catalog_query(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    sql: String,
+) -> Result<Vec<RecordBatch>, ShellError> {
+    let CatalogConnection {
+        connector,
+        addr,
+        database,
+        timeout,
+        retries,
+    } = catalog_connection(engine_state, stack, call)?;
+
+    let sql = Spanned {
+        item: sql,
+        span: call.head,
+    };
+
+    let options = SqlQueryOptions {
+        timeout,
+        retries,
+        ctrlc: engine_state.ctrlc.clone(),
+        ..Default::default()
+    };
+    let batches = tokio_block_sql_batches(connector, &addr, &database, &sql, &options)?;
+
+    match batches {
+        Ok((batches, _)) => Ok(batches),
+        Err(Error::Timeout { timeout }) => Err(ShellError::GenericError(
+            "Iox query timed out".into(),
+            format!("no result within {:?}", timeout),
+            Some(call.head),
+            Some("Raise the limit with --timeout, or use 0sec to wait forever".into()),
+            Vec::new(),
+        )),
+        Err(error) => {
+            let nierrorhandler =
+                NuIoxErrorHandler::from_error(super::nuerror::CommandType::Sql, &error);
+
+            nierrorhandler.nu_iox_error_check()?;
+            nierrorhandler.nu_iox_error_generic(call)?;
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// The information schema query behind `ioxschema`
+///
+/// Only the tables holding data are listed, the system and information
+/// schema tables are left out. Quotes in the table name are escaped.
+pub fn schema_sql(table: Option<&str>) -> String {
+    let mut sql = "SELECT table_name, column_name, data_type \
+        FROM information_schema.columns \
+        WHERE table_schema = 'iox'"
+        .to_string();
+
+    if let Some(table) = table {
+        sql.push_str(&format!(
+            " AND table_name = '{}'",
+            table.replace('\'', "''")
+        ));
+    }
+
+    sql.push_str(" ORDER BY table_name, ordinal_position");
+    sql
+}
+
+/// Arrow type Iox gives its tag columns
+pub const TAG_DATA_TYPE: &str = "Dictionary(Int32, Utf8)";
+
+/// A row of the `schema_sql` result
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatalogColumn {
+    pub table_name: String,
+    pub column_name: String,
+    pub data_type: String,
+}
+
+impl CatalogColumn {
+    pub fn is_tag(&self) -> bool {
+        self.data_type == TAG_DATA_TYPE
+    }
+
+    pub fn is_time(&self) -> bool {
+        self.column_name == "time"
+    }
+}
+
+/// Reads the table, column and data type of every row of a `schema_sql` result
+pub fn catalog_columns(
+    batches: &[RecordBatch],
+    span: Span,
+) -> Result<Vec<CatalogColumn>, ShellError> {
+    let mut columns = Vec::new();
+
+    for batch in batches.iter().filter(|b| b.num_columns() >= 3) {
+        let cell = |column: usize, row: usize| {
+            array_value_to_nu_value(batch.column(column), row, span)?.as_string()
+        };
+
+        for row in 0..batch.num_rows() {
+            columns.push(CatalogColumn {
+                table_name: cell(0, row)?,
+                column_name: cell(1, row)?,
+                data_type: cell(2, row)?,
+            });
+        }
+    }
+
+    Ok(columns)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn schema_sql_lists_every_table() {
+        assert_eq!(
+            schema_sql(None),
+            "SELECT table_name, column_name, data_type FROM information_schema.columns \
+            WHERE table_schema = 'iox' ORDER BY table_name, ordinal_position"
+        );
+    }
+
+    #[test]
+    fn catalog_columns_are_read_from_every_batch() {
+        use arrow::array::{ArrayRef, StringArray};
+        use std::sync::Arc;
+
+        let batch = |columns: [&str; 3]| {
+            RecordBatch::try_from_iter(vec![
+                (
+                    "table_name",
+                    Arc::new(StringArray::from(vec![columns[0]])) as ArrayRef,
+                ),
+                (
+                    "column_name",
+                    Arc::new(StringArray::from(vec![columns[1]])) as ArrayRef,
+                ),
+                (
+                    "data_type",
+                    Arc::new(StringArray::from(vec![columns[2]])) as ArrayRef,
+                ),
+            ])
+            .unwrap()
+        };
+
+        let columns = catalog_columns(
+            &[
+                batch(["cpu", "host", TAG_DATA_TYPE]),
+                batch(["cpu", "time", "Timestamp(Nanosecond, None)"]),
+            ],
+            Span::test_data(),
+        )
+        .unwrap();
+
+        assert_eq!(columns.len(), 2);
+        assert!(columns[0].is_tag() && !columns[0].is_time());
+        assert!(columns[1].is_time() && !columns[1].is_tag());
+        assert_eq!(columns[1].table_name, "cpu");
+    }
+
+    #[test]
+    fn schema_sql_filters_to_one_escaped_table() {
+        let sql = schema_sql(Some("o'neil"));
+
+        assert!(sql.contains("AND table_name = 'o''neil' ORDER BY"));
+    }
+}
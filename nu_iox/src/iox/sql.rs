@@ -1,15 +1,44 @@
-use super::delimited::from_delimited_data;
+use super::convert::{batch_to_values, batches_to_value, schema_to_value, BatchValueStream};
+use super::dbnames::dbname_shape;
+use super::delimited::{from_delimited_data, DelimitedOptions};
+use super::export::{export_to_value, write_parquet, write_text};
+use super::json::from_json_data;
+use super::namespace::tokio_block_namespace_names;
+use super::ndjson::{batches_to_ndjson, record_to_json_line};
+use super::nuclient::{
+    format_batches, row_count, Compression, Error, Nuclient, QueryProtocol, QueryStats,
+    RemoteDatabase, DEFAULT_RETRIES,
+};
 use super::nuerror::NuIoxErrorHandler;
 
-use super::util::{get_env_var_from_engine, get_runtime, number_of_csv_records};
+use super::util::{
+    block_on_iox_with_threads, check_database_exists, connect_error, forget_connection_on_error,
+    headers_from_value, iox_connector, new_trace_id, num_threads_from_flag, resolve_iox_database,
+    resolve_iox_format, resolve_iox_keepalive, resolve_iox_profile, resolve_iox_quiet,
+    resolve_iox_tls_ca, resolve_pretty_color, retries_from_flag, timeout_from_value, token_hash,
+    trace_id_from_flag, traceparent_header, validate_iox_addr, IoxConnector,
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_IOX_ADDR, DEFAULT_QUERY_TIMEOUT,
+};
 use nu_engine::CallExt;
 use nu_protocol::ast::Call;
 use nu_protocol::engine::{Command, EngineState, Stack};
 use nu_protocol::{
-    Category, Example, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape, Value,
+    Category, Example, IntoInterruptiblePipelineData, IntoPipelineData, PipelineData, ShellError,
+    Signature, Span, Spanned, SyntaxShape, Value,
 };
 
-use csv::Trim;
+use arrow::compute::{lexsort_to_indices, take, SortColumn, SortOptions};
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use http::header::{HeaderName, HeaderValue};
+use influxdb_iox_client::flight::PerformQuery;
+use influxdb_iox_client::format::QueryOutputFormat;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Clone)]
 pub struct Ioxsql;
@@ -21,17 +50,263 @@ impl Command for Ioxsql {
 
     fn signature(&self) -> nu_protocol::Signature {
         Signature::build("ioxsql")
-            .required(
+            .optional(
                 "query",
                 SyntaxShape::String,
                 "SQL to execute against the database",
             )
+            .named(
+                "file",
+                SyntaxShape::Filepath,
+                "read the SQL to execute from this file instead",
+                None,
+            )
             .named(
                 "dbname",
-                SyntaxShape::String,
+                dbname_shape(),
                 "name of the database to search over",
                 Some('d'),
             )
+            .named(
+                "namespace",
+                SyntaxShape::String,
+                "namespace sent to the server as is, e.g. myorg_bananas, when it differs from the database name",
+                None,
+            )
+            .named(
+                "dbnames",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "run the query against each of these databases, tagging the rows with a namespace column",
+                None,
+            )
+            .switch(
+                "fail-fast",
+                "with --dbnames, stop at the first database that fails instead of reporting it and going on",
+                None,
+            )
+            .switch(
+                "check-db",
+                "check the database exists before querying it, suggesting the closest name when it doesn't",
+                None,
+            )
+            .named(
+                "addr",
+                SyntaxShape::String,
+                format!(
+                    "address of the Iox server, defaults to IOX_ADDR or {}",
+                    DEFAULT_IOX_ADDR
+                ),
+                Some('a'),
+            )
+            .named(
+                "format",
+                SyntaxShape::String,
+                "format of the results: arrow (default), csv, json, ndjson or pretty, IOX_FORMAT sets the default",
+                Some('f'),
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "tls-ca",
+                SyntaxShape::Filepath,
+                "PEM certificate authority for an https Iox server, defaults to IOX_TLS_CA",
+                None,
+            )
+            .named(
+                "param",
+                SyntaxShape::Any,
+                "value for a $name placeholder in the query as name=value, a list of them or a record, strings are quoted",
+                Some('p'),
+            )
+            .named(
+                "header",
+                SyntaxShape::Any,
+                "metadata header sent with every request as key=value, or a list of them, e.g. x-tenant-id=bananas",
+                None,
+            )
+            .named(
+                "trace-id",
+                SyntaxShape::String,
+                "send the query as part of this trace, 32 hex digits, to find it in the traces of the server",
+                None,
+            )
+            .switch(
+                "trace",
+                "send the query as part of a new trace, whose id is printed on stderr",
+                None,
+            )
+            .switch(
+                "no-headers",
+                "with --format csv, don't treat the first row as column names",
+                None,
+            )
+            .switch(
+                "no-infer",
+                "with --format csv, no field type inferencing",
+                None,
+            )
+            .named(
+                "separator",
+                SyntaxShape::String,
+                "with --format csv, a character to separate columns, defaults to ','",
+                Some('s'),
+            )
+            .named(
+                "trim",
+                SyntaxShape::String,
+                "with --format csv, drop whitespace around headers and/or fields: none, all, headers or fields",
+                None,
+            )
+            .named(
+                "retries",
+                SyntaxShape::Int,
+                "retries for an unavailable server, with exponential backoff, defaults to 3",
+                None,
+            )
+            .named(
+                "protocol",
+                SyntaxShape::String,
+                "protocol to query with, legacy (the default) or flightsql",
+                None,
+            )
+            .named(
+                "compression",
+                SyntaxShape::String,
+                "compression asked for the results of --protocol flightsql queries, none (the default) or gzip",
+                None,
+            )
+            .named(
+                "num-threads",
+                SyntaxShape::Int,
+                "worker threads for this command's own runtime instead of the shared one",
+                None,
+            )
+            .named(
+                "timeout",
+                SyntaxShape::Duration,
+                "how long the query may run, defaults to 30sec, 0sec waits forever",
+                None,
+            )
+            .named(
+                "stall-timeout",
+                SyntaxShape::Duration,
+                "how long to wait for the next batch of results before giving up on a stalled query, restarted by every batch",
+                None,
+            )
+            .named(
+                "connect-timeout",
+                SyntaxShape::Duration,
+                "how long connecting to the server may take, defaults to 5sec, 0sec waits forever",
+                None,
+            )
+            .named(
+                "limit",
+                SyntaxShape::Int,
+                "return at most this many rows, on top of any LIMIT in the query",
+                Some('l'),
+            )
+            .named(
+                "columns",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "keep just these columns of the results, in this order",
+                None,
+            )
+            .named(
+                "sort",
+                SyntaxShape::Any,
+                "sort the results by column, column:asc or column:desc, or a list of them",
+                None,
+            )
+            .named(
+                "out",
+                SyntaxShape::Filepath,
+                "write the results to this parquet file instead of returning them",
+                Some('o'),
+            )
+            .named(
+                "output-file",
+                SyntaxShape::Filepath,
+                "write the results formatted as csv, json or ndjson to this file instead of returning them",
+                None,
+            )
+            .named(
+                "index-by",
+                SyntaxShape::String,
+                "return a record of the rows keyed by the values of this column",
+                None,
+            )
+            .switch(
+                "allow-dup",
+                "with --index-by, keep the last row of a duplicate key instead of failing",
+                None,
+            )
+            .switch(
+                "color",
+                "keep the ANSI colors of the pretty format, it is plain by default",
+                None,
+            )
+            .switch(
+                "no-color",
+                "never color the pretty format, like setting NO_COLOR",
+                None,
+            )
+            .switch(
+                "raw",
+                "return the csv text exactly as the server sent it instead of parsing it into a table",
+                None,
+            )
+            .switch(
+                "stream",
+                "stream the rows into the pipeline as they arrive instead of collecting them first",
+                None,
+            )
+            .switch(
+                "schema",
+                "return the column names and Arrow types of the result instead of the rows",
+                None,
+            )
+            .switch(
+                "explain",
+                "return the query plan instead of the results",
+                None,
+            )
+            .switch(
+                "analyze",
+                "like --explain, but run the query and include its metrics in the plan",
+                None,
+            )
+            .named(
+                "watch",
+                SyntaxShape::Duration,
+                "run the query again every interval, until ctrl-c, emitting the results of each run",
+                Some('w'),
+            )
+            .switch(
+                "count-only",
+                "return just the number of rows the query produces instead of the rows",
+                None,
+            )
+            .named(
+                "cache",
+                SyntaxShape::Duration,
+                "reuse the results of the same query on the same database for this long",
+                None,
+            )
+            .switch(
+                "stats",
+                "return the results along with the row, batch and byte counts and the query time",
+                None,
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
             .category(Category::Filters)
     }
 
@@ -39,6 +314,10 @@ impl Command for Ioxsql {
         "Sql query against the Iox Database."
     }
 
+    fn extra_usage(&self) -> &str {
+        "The json format parses the results from a single json array into records, while ndjson returns them as a string with one json object per line, ready to be saved or piped to other tools. With --watch the command keeps running, and blocks the rest of the pipeline, until ctrl-c is pressed. --protocol flightsql sends the query over standard Flight SQL instead of the legacy Iox flight ticket, it needs nu-command built with the iox-flightsql feature."
+    }
+
     fn run(
         &self,
         engine_state: &EngineState,
@@ -46,52 +325,442 @@ impl Command for Ioxsql {
         call: &Call,
         _input: PipelineData,
     ) -> Result<PipelineData, ShellError> {
-        let sql: Spanned<String> = call.req(engine_state, stack, 0)?;
+        let sql = sql_from_args(
+            call.opt(engine_state, stack, 0)?,
+            call.get_flag(engine_state, stack, "file")?,
+            call.head,
+        )?;
         let db: Option<String> = call.get_flag(engine_state, stack, "dbname")?;
+        let namespace: Option<String> = call.get_flag(engine_state, stack, "namespace")?;
+        let dbnames: Option<Vec<String>> = call.get_flag(engine_state, stack, "dbnames")?;
+        let format = sql_format_from_str(resolve_iox_format(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "format")?,
+            call.head,
+        ))?;
+        let options = DelimitedOptions::from_call(engine_state, stack, call)?;
+        let stats = call.has_flag("stats");
+        let stream = call.has_flag("stream");
+        let schema = call.has_flag("schema");
+        let count_only = call.has_flag("count-only");
+        let raw = call.has_flag("raw");
+        let explain = call.has_flag("explain");
+        let analyze = call.has_flag("analyze");
+        let color = resolve_pretty_color(
+            stack,
+            engine_state,
+            call.has_flag("color"),
+            call.has_flag("no-color"),
+        );
+        let watch = watch_interval_from_value(call.get_flag(engine_state, stack, "watch")?)?;
+        let limit = limit_from_flag(call.get_flag(engine_state, stack, "limit")?)?;
+        let columns = columns_from_flag(call.get_flag(engine_state, stack, "columns")?)?;
+        let sort = sort_keys_from_value(call.get_flag(engine_state, stack, "sort")?)?;
+        let cache_ttl = cache_ttl_from_value(call.get_flag(engine_state, stack, "cache")?)?;
+        let out: Option<Spanned<String>> = call.get_flag(engine_state, stack, "out")?;
+        let output_file: Option<Spanned<String>> =
+            call.get_flag(engine_state, stack, "output-file")?;
+        let index_by: Option<Spanned<String>> = call.get_flag(engine_state, stack, "index-by")?;
+        let query_options = sql_query_options(engine_state, stack, call)?;
+        let compression =
+            compression_from_flag(call.get_flag(engine_state, stack, "compression")?)?;
 
-        let dbname = if let Some(name) = db {
-            name
+        let params = sql_params_from_value(call.get_flag(engine_state, stack, "param")?)?;
+        let sql = if params.is_empty() {
+            sql
         } else {
-            get_env_var_from_engine(stack, engine_state, "IOX_DBNAME").unwrap()
+            substitute_sql_params(&sql, &params)?
         };
+        let statements = split_sql_statements(&sql.item);
+
+        // flags whose value matters to the conflicts are named with it, see
+        // SQL_FLAG_CONFLICTS, the csv format only counts when it is parsed
+        let given: Vec<(&str, Span)> = [
+            ("--dbname", db.is_some().then(|| call.head)),
+            ("--namespace", namespace.is_some().then(|| call.head)),
+            ("--dbnames", dbnames.is_some().then(|| call.head)),
+            (
+                "a script of several statements",
+                (statements.len() > 1).then(|| sql.span),
+            ),
+            ("--format", call.has_flag("format").then(|| call.head)),
+            (
+                "--format csv",
+                (format == SqlFormat::Csv && output_file.is_none()).then(|| call.head),
+            ),
+            ("--stats", stats.then(|| call.head)),
+            ("--stream", stream.then(|| call.head)),
+            ("--schema", schema.then(|| call.head)),
+            ("--count-only", count_only.then(|| call.head)),
+            ("--raw", raw.then(|| call.head)),
+            ("--explain", explain.then(|| call.head)),
+            ("--analyze", analyze.then(|| call.head)),
+            ("--watch", watch.is_some().then(|| call.head)),
+            ("--limit", limit.is_some().then(|| call.head)),
+            ("--columns", columns.is_some().then(|| call.head)),
+            ("--sort", sort.is_some().then(|| call.head)),
+            ("--cache", cache_ttl.is_some().then(|| call.head)),
+            ("--out", out.as_ref().map(|out| out.span)),
+            ("--output-file", output_file.as_ref().map(|file| file.span)),
+            (
+                "--index-by",
+                index_by.as_ref().map(|index_by| index_by.span),
+            ),
+            (
+                "--num-threads",
+                query_options.num_threads.is_some().then(|| call.head),
+            ),
+            (
+                "--protocol legacy",
+                (query_options.protocol == QueryProtocol::Legacy).then(|| call.head),
+            ),
+            (
+                "--protocol flightsql",
+                (query_options.protocol == QueryProtocol::FlightSql).then(|| call.head),
+            ),
+            (
+                "--compression gzip",
+                (call.has_flag("compression") && compression == Compression::Gzip)
+                    .then(|| call.head),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(flag, span)| span.map(|span| (flag, span)))
+        .collect();
+        check_flag_conflicts(SQL_FLAG_CONFLICTS, &given)?;
+
+        if let Some(index_by) = &index_by {
+            if !matches!(format, SqlFormat::Arrow | SqlFormat::Json) {
+                return Err(ShellError::UnsupportedInput(
+                    "--index-by keys the records of the result, use it with --format arrow or json"
+                        .into(),
+                    index_by.span,
+                ));
+            }
+        }
+
+        if let Some(output_file) = &output_file {
+            if !matches!(format, SqlFormat::Csv | SqlFormat::Json | SqlFormat::Ndjson) {
+                return Err(ShellError::UnsupportedInput(
+                    "--output-file writes text, use it with --format csv, json or ndjson".into(),
+                    output_file.span,
+                ));
+            }
+        }
+
+        if raw && call.has_flag("format") && format != SqlFormat::Csv {
+            return Err(ShellError::IncompatibleParametersSingle(
+                "--raw returns the csv text of the server, it can't be combined with another --format".into(),
+                call.head,
+            ));
+        }
 
-        let sql_result = tokio_block_sql(&dbname, &sql);
-        //println!("sql_result = {:?}", sql_result);
+        // --count-only --stream counts the rows before the command returns,
+        // only the rows of a plain --stream are read by the pipeline after it
+        if stream && !count_only {
+            // json, csv and pretty need the whole result set before formatting
+            if !matches!(format, SqlFormat::Arrow | SqlFormat::Ndjson) {
+                return Err(ShellError::IncompatibleParametersSingle(
+                    "--stream only supports the arrow and ndjson formats".into(),
+                    call.head,
+                ));
+            }
 
-        let numofrecords = number_of_csv_records(&sql_result.as_ref().unwrap());
-        //println!("number of csv records = {:?}", numofrecords);
+            if query_options.stall_timeout.is_some() {
+                return Err(ShellError::IncompatibleParametersSingle(
+                    "--stall-timeout watches collected queries, --stream hands the batches on as they arrive".into(),
+                    call.head,
+                ));
+            }
 
-        let not_csv_data = match numofrecords.unwrap() {
-            d if d > 0 => false,
-            _ => true,
+            if query_options.num_threads.is_some() {
+                return Err(ShellError::IncompatibleParametersSingle(
+                    "--stream reads the results on the shared runtime after the query starts, it can't be combined with --num-threads".into(),
+                    call.head,
+                ));
+            }
+        }
+
+        let connection = sql_connection(engine_state, stack, call, compression)?;
+        let addr = connection.addr.clone();
+
+        let sql = if explain || analyze {
+            Spanned {
+                item: explain_sql(&sql.item, analyze),
+                span: sql.span,
+            }
+        } else {
+            sql
         };
 
-        if not_csv_data {
-            let nierrorhandler = NuIoxErrorHandler::new(
-                super::nuerror::CommandType::Sql,
-                sql_result.as_ref().unwrap().to_string(),
-            );
+        if let Some(dbnames) = dbnames {
+            let names = if call.has_flag("check-db") {
+                Some(list_databases(connection.connector()?, &addr, call.head)?)
+            } else {
+                None
+            };
+
+            let (table, failures) =
+                query_namespaces(&dbnames, call.has_flag("fail-fast"), call.head, |dbname| {
+                    if let Some(names) = &names {
+                        let dbname = Spanned {
+                            item: dbname.to_string(),
+                            span: call.head,
+                        };
+                        check_database_exists(&dbname, names)?;
+                    }
+
+                    let batches = tokio_block_sql_batches(
+                        connection.connector()?,
+                        &addr,
+                        &RemoteDatabase::new(dbname.to_string(), None),
+                        &sql,
+                        &query_options,
+                    )?;
+
+                    batches
+                        .map(|(batches, _)| limit_batches(batches, limit))
+                        .map_err(|e| {
+                            ShellError::GenericError(
+                                format!("Query against database '{}' failed", dbname),
+                                e.to_string(),
+                                Some(call.head),
+                                None,
+                                Vec::new(),
+                            )
+                        })
+                        .and_then(|batches| match &columns {
+                            Some(columns) => project_batches(batches, columns),
+                            None => Ok(batches),
+                        })
+                })?;
+
+            for failure in failures {
+                eprintln!("ioxsql: {}", failure);
+            }
+
+            return Ok(table.into_pipeline_data());
+        }
+
+        let database = resolve_iox_database(stack, engine_state, db, namespace, call.head)?;
+
+        if call.has_flag("check-db") {
+            let names = list_databases(connection.connector()?, &addr, call.head)?;
+            let namespace = Spanned {
+                item: database.namespace.clone(),
+                span: call.head,
+            };
+            check_database_exists(&namespace, &names)?;
+        }
+
+        if statements.len() > 1 {
+            let results = run_statements(&statements, call.head, |statement| {
+                let statement = Spanned {
+                    item: statement.to_string(),
+                    span: sql.span,
+                };
+                let (batches, _) = tokio_block_sql_batches(
+                    connection.connector()?,
+                    &addr,
+                    &database,
+                    &statement,
+                    &query_options,
+                )?
+                .map_err(|error| query_error(error, call))?;
+                let batches = limit_batches(batches, limit);
+
+                results_to_value(format, &batches, None, color, call.head)
+            })?;
+
+            return Ok(results.into_pipeline_data());
+        }
+
+        if let Some(interval) = watch {
+            let call = call.clone();
+            let results = watch_results(interval, engine_state.ctrlc.clone(), move || {
+                let (batches, query_stats) = tokio_block_sql_batches(
+                    connection.connector()?,
+                    &addr,
+                    &database,
+                    &sql,
+                    &query_options,
+                )?
+                .map_err(|error| query_error(error, &call))?;
+                let batches = match &sort {
+                    Some(sort) => sort_batches(batches, sort)?,
+                    None => batches,
+                };
+                let batches = limit_batches(batches, limit);
+                let batches = match &columns {
+                    Some(columns) => project_batches(batches, columns)?,
+                    None => batches,
+                };
+
+                results_to_value(
+                    format,
+                    &batches,
+                    stats.then(|| &query_stats),
+                    color,
+                    call.head,
+                )
+            });
+
+            return Ok(results.into_pipeline_data(engine_state.ctrlc.clone()));
+        }
+
+        let connector = connection.connector()?;
+
+        if schema {
+            let schema = tokio_block_sql_schema(connector, &addr, &database, &sql, &query_options)?;
+
+            return match schema {
+                Ok(Some(schema)) => Ok(schema_to_value(&schema, call.head).into_pipeline_data()),
+                Ok(None) => Ok(Value::List {
+                    vals: vec![],
+                    span: call.head,
+                }
+                .into_pipeline_data()),
+                Err(error) => handled_query_error(error, call),
+            };
+        }
+
+        if count_only {
+            // only --stream counts the batches as they arrive, otherwise
+            // the whole result is collected first like any other query
+            let count = if stream {
+                tokio_block_sql_count(connector, &addr, &database, &sql, &query_options)?
+            } else {
+                tokio_block_sql_batches(connector, &addr, &database, &sql, &query_options)?
+                    .map(|(batches, _)| row_count(&batches))
+            };
+
+            return match count {
+                Ok(count) => Ok(Value::Int {
+                    val: limit.map_or(count, |limit| count.min(limit)) as i64,
+                    span: call.head,
+                }
+                .into_pipeline_data()),
+                Err(error) => handled_query_error(error, call),
+            };
+        }
+
+        if stream {
+            let query = tokio_block_sql_stream(connector, &addr, &database, &sql, &query_options)?;
+
+            return match query {
+                Ok(query) => {
+                    let rows =
+                        BatchValueStream::new(query, call.head).take(limit.unwrap_or(usize::MAX));
+                    match format {
+                        SqlFormat::Ndjson => {
+                            let span = call.head;
+                            Ok(rows
+                                .map(move |row| match record_to_json_line(&row) {
+                                    Ok(val) => Value::String { val, span },
+                                    Err(error) => Value::Error { error },
+                                })
+                                .into_pipeline_data(engine_state.ctrlc.clone()))
+                        }
+                        _ => Ok(rows.into_pipeline_data(engine_state.ctrlc.clone())),
+                    }
+                }
+                Err(error) => handled_query_error(error, call),
+            };
+        }
+
+        // the file is written from the batches, so csv doesn't need the csv path below
+        if (format != SqlFormat::Csv && !raw) || output_file.is_some() {
+            let run = || tokio_block_sql_batches(connector, &addr, &database, &sql, &query_options);
+            let batches = match cache_ttl {
+                Some(ttl) => cached_sql_batches(
+                    (
+                        addr.clone(),
+                        token_hash(connection.token.as_deref()),
+                        database.namespace.clone(),
+                        sql.item.clone(),
+                    ),
+                    ttl,
+                    Instant::now(),
+                    run,
+                )?,
+                None => run()?,
+            };
+
+            let (batches, query_stats) = batches.map_err(|error| query_error(error, call))?;
+            let batches = match &sort {
+                Some(sort) => sort_batches(batches, sort)?,
+                None => batches,
+            };
+            let batches = limit_batches(batches, limit);
+            let batches = match &columns {
+                Some(columns) => project_batches(batches, columns)?,
+                None => batches,
+            };
+
+            if let Some(out) = out {
+                let rows = write_parquet(&out, &batches)?;
+                return Ok(export_to_value(&out.item, rows, call.head).into_pipeline_data());
+            }
+
+            if let Some(output_file) = output_file {
+                write_text(&output_file, &results_to_text(format, &batches, call.head)?)?;
+                return Ok(
+                    export_to_value(&output_file.item, row_count(&batches), call.head)
+                        .into_pipeline_data(),
+                );
+            }
+
+            let value = results_to_value(
+                format,
+                &batches,
+                stats.then(|| &query_stats),
+                color,
+                call.head,
+            )?;
+
+            if let Some(index_by) = index_by {
+                validate_index_column(&batches, &index_by)?;
+                let value = index_rows_by(value, &index_by, call.has_flag("allow-dup"), call.head)?;
+                return Ok(value.into_pipeline_data());
+            }
+
+            return Ok(value.into_pipeline_data());
+        }
+
+        let sql_result = tokio_block_sql(connector, &addr, &database, &sql, &query_options)?;
 
-            nierrorhandler.nu_iox_error_check()?;
-            nierrorhandler.nu_iox_error_generic(call)?;
+        if raw {
+            return Ok(raw_result(sql_result, call)?.into_pipeline_data());
         }
-        let no_infer = false;
-        let noheaders = false;
-        let separator: char = ',';
-        let trim = Trim::None;
+
+        // a header without records is a legitimate empty result, only a
+        // failed query goes through the error handler
+        let sql_result = match sql_result {
+            Ok(csv) => csv,
+            Err(error) => return handled_query_error(error, call),
+        };
 
         let input = PipelineData::Value(
             Value::String {
-                val: sql_result.unwrap(),
+                val: sql_result,
                 span: call.head,
             },
             None,
         );
 
-        let name = Span::new(0, 0);
         let config = engine_state.get_config();
 
-        from_delimited_data(noheaders, no_infer, separator, trim, input, name, config)
+        // parse errors point at the ioxsql call that got the unexpected data
+        from_delimited_data(
+            options.noheaders,
+            options.no_infer,
+            options.separator,
+            options.trim,
+            input,
+            call.head,
+            config,
+        )
     }
 
     fn examples(&self) -> Vec<Example> {
@@ -106,36 +775,2986 @@ impl Command for Ioxsql {
                 example: r#"ioxsql "select * from cpu"#,
                 result: None,
             },
+            Example {
+                description: "Run the sql query kept in a file",
+                example: r#"ioxsql --file ./queries/cpu_by_region.sql"#,
+                result: None,
+            },
+            Example {
+                description: "Run an sql query and parse the results from csv",
+                example: r#"ioxsql --format csv "select * from cpu"#,
+                result: None,
+            },
+            Example {
+                description: "Run an sql query and parse the csv results without type inference",
+                example: r#"ioxsql -f csv --no-infer "select * from cpu"#,
+                result: None,
+            },
+            Example {
+                description: "Run an sql query and hand one json object per row to jq",
+                example: r#"ioxsql -f ndjson "select * from cpu" | ^jq .user"#,
+                result: None,
+            },
+            Example {
+                description: "Run an sql query and return the pretty printed table",
+                example: r#"ioxsql -f pretty "select * from cpu"#,
+                result: None,
+            },
+            Example {
+                description:
+                    "Run an sql query and report how many rows it returned and how long it took",
+                example: r#"(ioxsql --stats "select * from cpu").stats"#,
+                result: None,
+            },
+            Example {
+                description:
+                    "Query an Iox server behind TLS signed by a private certificate authority",
+                example: r#"ioxsql -a https://iox.example.com:443 --tls-ca ./certs/ca.pem "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Show the plan of a slow query, keeping its line breaks",
+                example: r#"(ioxsql --explain "select * from cpu where user > 10").plan"#,
+                result: None,
+            },
+            Example {
+                description:
+                    "Count the rows of a large query without transferring them to the pipeline",
+                example: r#"ioxsql --count-only --stream "select * from cpu where user > 10""#,
+                result: None,
+            },
+            Example {
+                description: "Show the columns and types a query returns without fetching its rows",
+                example: r#"ioxsql --schema "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Let a long query run as long as a batch arrives at least every 10 seconds",
+                example: r#"ioxsql --timeout 0sec --stall-timeout 10sec "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Check the database exists first, a typo gets a list of the databases instead of a server error",
+                example: r#"ioxsql --check-db -d bananas "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Compare row counts of the same table in two databases",
+                example: r#"ioxsql --dbnames [staging production] "select count(*) as rows from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Query a namespace named after its org and bucket",
+                example: r#"ioxsql -d bananas --namespace myorg_bananas "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Archive the results to a parquet file, keeping the Arrow types",
+                example: r#"ioxsql --out cpu.parquet "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Fill in the placeholders of a query template, the host is quoted as a string",
+                example: r#"ioxsql --param [host=a min=10] "select * from cpu where host = $host and user > $min""#,
+                result: None,
+            },
+            Example {
+                description: "Look up the latest row of a host by name",
+                example: r#"(ioxsql --index-by host "select host, max(user) as user from cpu group by host").server01"#,
+                result: None,
+            },
+            Example {
+                description: "Export the results to a csv file, returning its path and the rows written",
+                example: r#"ioxsql --format csv --output-file cpu.csv "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Show the busiest hosts again every ten seconds until ctrl-c, this blocks the pipeline while watching",
+                example: r#"ioxsql --watch 10sec "select host, max(user) as user from cpu group by host order by user desc limit 5""#,
+                result: None,
+            },
+            Example {
+                description: "Look at the csv exactly as the server sent it",
+                example: r#"ioxsql --raw "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Peek at the first rows of a large table",
+                example: r#"ioxsql --limit 5 "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Run a query as part of a trace, to find it in the traces of the server",
+                example: r#"ioxsql --trace-id 4bf92f3577b34da6a3ce929d0e0e4736 "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Sort the results by host, and the busiest first for each host",
+                example: r#"ioxsql --sort [host user:desc] "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Keep just the host and user columns of the results",
+                example: r#"ioxsql --columns [host user] "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Query with Flight SQL, asking for the results gzipped",
+                example: r#"ioxsql --protocol flightsql --compression gzip "select * from cpu""#,
+                result: None,
+            },
+            Example {
+                description: "Run a script of several statements, getting one table for each",
+                example: r#"ioxsql "select * from cpu; select * from mem""#,
+                result: None,
+            },
+            Example {
+                description: "Stream the rows of a large table, only reading what is needed",
+                example: r#"ioxsql --stream "select * from cpu" | first 10"#,
+                result: None,
+            },
+            Example {
+                description: "Run an sql query against a remote Iox server",
+                example: r#"ioxsql -a http://iox.example.com:8082 -d bananas "select * from cpu"#,
+                result: None,
+            },
         ]
     }
 }
 
-pub fn tokio_block_sql(dbname: &String, sql: &Spanned<String>) -> Result<String, std::io::Error> {
-    use crate::iox::Nuclient;
-    use influxdb_iox_client::connection::Builder;
-    let num_threads: Option<usize> = None;
-    let tokio_runtime = get_runtime(num_threads)?;
+/// A flag, what it does, and the flags it can't be combined with
+///
+/// Flags are named as given on the command line. When a value of the flag
+/// is what conflicts, the value is part of the name, e.g. "--format csv".
+pub type FlagConflict = (&'static str, &'static str, &'static [&'static str]);
 
-    let sql_result = tokio_runtime.block_on(async move {
-        let connection = Builder::default()
-            .build("http://127.0.0.1:8082")
-            .await
-            .expect("client should be valid");
+/// The flags of ioxsql that can't be combined, see check_flag_conflicts
+pub const SQL_FLAG_CONFLICTS: &[FlagConflict] = &[
+    (
+        "--dbnames",
+        "runs the query against each of the databases",
+        &[
+            "--dbname",
+            "--namespace",
+            "--format",
+            "--stream",
+            "--schema",
+            "--count-only",
+            "--stats",
+            "--watch",
+            "--out",
+        ],
+    ),
+    (
+        "a script of several statements",
+        "returns one table per statement",
+        &[
+            "--format csv",
+            "--raw",
+            "--stream",
+            "--schema",
+            "--count-only",
+            "--stats",
+            "--explain",
+            "--analyze",
+            "--dbnames",
+            "--watch",
+            "--out",
+            "--output-file",
+            "--index-by",
+            "--sort",
+            "--columns",
+            "--cache",
+        ],
+    ),
+    (
+        "--index-by",
+        "returns a record of the rows",
+        &[
+            "--out",
+            "--output-file",
+            "--stream",
+            "--schema",
+            "--stats",
+            "--count-only",
+            "--watch",
+            "--dbnames",
+            "--explain",
+            "--analyze",
+        ],
+    ),
+    (
+        "--output-file",
+        "writes the results to a text file",
+        &[
+            "--out",
+            "--stream",
+            "--schema",
+            "--stats",
+            "--count-only",
+            "--watch",
+            "--dbnames",
+            "--explain",
+            "--analyze",
+        ],
+    ),
+    (
+        "--raw",
+        "returns the csv text of the server",
+        &[
+            "--out",
+            "--output-file",
+            "--index-by",
+            "--limit",
+            "--stream",
+            "--schema",
+            "--stats",
+            "--count-only",
+            "--watch",
+            "--dbnames",
+        ],
+    ),
+    (
+        "--columns",
+        "projects the record batches of the result",
+        &[
+            "--format csv",
+            "--raw",
+            "--stream",
+            "--schema",
+            "--count-only",
+        ],
+    ),
+    (
+        "--sort",
+        "orders the record batches of the result",
+        &[
+            "--format csv",
+            "--raw",
+            "--stream",
+            "--schema",
+            "--count-only",
+            "--dbnames",
+        ],
+    ),
+    (
+        "--cache",
+        "keeps the record batches of the result",
+        &[
+            "--format csv",
+            "--raw",
+            "--stream",
+            "--schema",
+            "--count-only",
+            "--watch",
+            "--dbnames",
+        ],
+    ),
+    (
+        "--explain",
+        "returns the plan as records",
+        &[
+            "--format",
+            "--stream",
+            "--schema",
+            "--count-only",
+            "--watch",
+            "--out",
+        ],
+    ),
+    (
+        "--analyze",
+        "returns the plan as records",
+        &[
+            "--format",
+            "--stream",
+            "--schema",
+            "--count-only",
+            "--watch",
+            "--out",
+        ],
+    ),
+    (
+        "--watch",
+        "re-runs the query on the shared runtime",
+        &[
+            "--format csv",
+            "--stream",
+            "--schema",
+            "--count-only",
+            "--out",
+            "--num-threads",
+        ],
+    ),
+    (
+        "--out",
+        "writes parquet",
+        &["--format", "--stream", "--schema", "--stats"],
+    ),
+    (
+        "--schema",
+        "returns the columns of the result",
+        &["--stream", "--stats", "--count-only"],
+    ),
+    (
+        "--count-only",
+        "returns a single number",
+        &["--format", "--stats", "--out"],
+    ),
+    ("--stream", "hands the rows on as they arrive", &["--stats"]),
+    (
+        "--stats",
+        "counts the record batches of the result",
+        &["--format csv"],
+    ),
+    (
+        "--limit",
+        "cuts the record batches of the result",
+        &["--format csv"],
+    ),
+    (
+        "--protocol flightsql",
+        "collects the results",
+        &["--stream", "--schema"],
+    ),
+    (
+        "--compression gzip",
+        "is asked for by the Flight SQL client",
+        &["--protocol legacy"],
+    ),
+];
 
-        let mut repl = Nuclient::new(connection);
-        repl.use_database(dbname.to_string());
-        let _output_format = repl.set_output_format("csv");
+/// Fails on the first flag of the table given together with one it can't
+/// be combined with, naming all of those that were given
+///
+/// The given flags are named like in the table, the error points at the
+/// span of the flag the conflict is listed for.
+pub fn check_flag_conflicts(
+    conflicts: &[FlagConflict],
+    given: &[(&str, Span)],
+) -> Result<(), ShellError> {
+    let span_of = |flag: &str| {
+        given
+            .iter()
+            .find(|(name, _)| *name == flag)
+            .map(|(_, span)| *span)
+    };
+
+    for (flag, what, others) in conflicts {
+        let span = match span_of(flag) {
+            Some(span) => span,
+            None => continue,
+        };
 
-        // let rsql = repl.run_sql(sql.item.to_string()).await.expect("run_sql");
-        // rsql
+        let clashing: Vec<&str> = others
+            .iter()
+            .copied()
+            .filter(|other| span_of(other).is_some())
+            .collect();
+        if !clashing.is_empty() {
+            return Err(ShellError::IncompatibleParametersSingle(
+                format!(
+                    "{} {}, it can't be combined with {}",
+                    flag,
+                    what,
+                    clashing.join(", ")
+                ),
+                span,
+            ));
+        }
+    }
+
+    Ok(())
+}
 
-        let rsql = repl.run_sql(sql.item.to_string()).await;
+/// Picks the SQL from the query argument or from the --file flag
+///
+/// Exactly one of them has to be given, the file contents are used verbatim.
+/// A query of nothing but whitespace is an error before anything is sent.
+pub fn sql_from_args(
+    query: Option<Spanned<String>>,
+    file: Option<Spanned<String>>,
+    span: Span,
+) -> Result<Spanned<String>, ShellError> {
+    let sql = match (query, file) {
+        (Some(query), None) => query,
+        (None, Some(file)) => {
+            let item = std::fs::read_to_string(&file.item)
+                .map_err(|e| ShellError::ReadingFile(format!("{}: {}", file.item, e), file.span))?;
 
-        match rsql {
-            Ok(res) => res,
-            Err(error) => error.to_string(),
+            Spanned {
+                item,
+                span: file.span,
+            }
         }
-    });
+        (Some(query), Some(file)) => {
+            return Err(ShellError::IncompatibleParameters {
+                left_message: "query given here".into(),
+                left_span: query.span,
+                right_message: "and --file given here".into(),
+                right_span: file.span,
+            })
+        }
+        (None, None) => return Err(ShellError::MissingParameter("query or --file".into(), span)),
+    };
+
+    if sql.item.trim().is_empty() {
+        return Err(ShellError::GenericError(
+            "Empty query".into(),
+            "there is no SQL to run, only whitespace".into(),
+            Some(sql.span),
+            Some(r#"Pass the SQL to run, e.g. ioxsql "select * from cpu""#.into()),
+            Vec::new(),
+        ));
+    }
+
+    Ok(sql)
+}
+
+/// How the results of `ioxsql` are returned to the pipeline
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SqlFormat {
+    /// Records typed from the Arrow schema
+    Arrow,
+    /// Records parsed back from the csv formatted results
+    Csv,
+    /// Records parsed from the json formatted results
+    Json,
+    /// A string of newline delimited json, one object per row
+    Ndjson,
+    /// A single string holding the pretty printed table
+    Pretty,
+}
+
+pub fn sql_format_from_str(format: Option<Value>) -> Result<SqlFormat, ShellError> {
+    match format {
+        Some(Value::String { val: item, span }) => match item.as_str() {
+            "arrow" => Ok(SqlFormat::Arrow),
+            "csv" => Ok(SqlFormat::Csv),
+            "json" => Ok(SqlFormat::Json),
+            "ndjson" => Ok(SqlFormat::Ndjson),
+            "pretty" => Ok(SqlFormat::Pretty),
+            _ => Err(ShellError::UnsupportedInput(
+                "the only possible values for format are 'arrow', 'csv', 'json', 'ndjson' and 'pretty'"
+                    .into(),
+                span,
+            )),
+        },
+        _ => Ok(SqlFormat::Arrow),
+    }
+}
+
+/// Reads a --protocol flag value, queries use the legacy protocol by default
+pub fn query_protocol_from_flag(
+    protocol: Option<Spanned<String>>,
+) -> Result<QueryProtocol, ShellError> {
+    match protocol {
+        None => Ok(QueryProtocol::Legacy),
+        Some(Spanned { item, span }) => match item.as_str() {
+            "legacy" => Ok(QueryProtocol::Legacy),
+            "flightsql" => Ok(QueryProtocol::FlightSql),
+            _ => Err(ShellError::UnsupportedInput(
+                "the only possible values for protocol are 'legacy' and 'flightsql'".into(),
+                span,
+            )),
+        },
+    }
+}
+
+/// Reads a --compression flag value, results are asked for as they are by
+/// default until gzip is measured to pay off for Flight SQL results
+pub fn compression_from_flag(
+    compression: Option<Spanned<String>>,
+) -> Result<Compression, ShellError> {
+    match compression {
+        None => Ok(Compression::None),
+        Some(Spanned { item, span }) => match item.as_str() {
+            "gzip" => Ok(Compression::Gzip),
+            "none" => Ok(Compression::None),
+            _ => Err(ShellError::UnsupportedInput(
+                "the only possible values for compression are 'gzip' and 'none'".into(),
+                span,
+            )),
+        },
+    }
+}
+
+pub fn limit_from_flag(limit: Option<Spanned<i64>>) -> Result<Option<usize>, ShellError> {
+    match limit {
+        None => Ok(None),
+        Some(Spanned { item, span }) if item < 0 => Err(ShellError::UnsupportedInput(
+            format!("--limit can't be negative, got {}", item),
+            span,
+        )),
+        Some(Spanned { item, .. }) => Ok(Some(item as usize)),
+    }
+}
+
+/// Prefixes the query with EXPLAIN, or EXPLAIN ANALYZE
+///
+/// A query that already starts with EXPLAIN is left alone.
+pub fn explain_sql(sql: &str, analyze: bool) -> String {
+    let already_explained = sql
+        .split_whitespace()
+        .next()
+        .map_or(false, |word| word.eq_ignore_ascii_case("explain"));
+
+    match (already_explained, analyze) {
+        (true, _) => sql.to_string(),
+        (false, false) => format!("EXPLAIN {}", sql),
+        (false, true) => format!("EXPLAIN ANALYZE {}", sql),
+    }
+}
+
+/// Parses the --param flag into the SQL literal for each placeholder name
+///
+/// Takes `name=value` strings, a list of them, or a record. A value given
+/// as text is a number when it parses as one and a string otherwise, the
+/// values of a record keep their type. Strings are single quoted with any
+/// quote in them doubled, so they can't end the literal early.
+pub fn sql_params_from_value(params: Option<Value>) -> Result<HashMap<String, String>, ShellError> {
+    let mut literals = HashMap::new();
 
-    Ok(sql_result)
+    let params = match params {
+        None => return Ok(literals),
+        Some(Value::Record { cols, vals, .. }) => {
+            for (name, val) in cols.into_iter().zip(vals) {
+                let span = val.span()?;
+                let literal = match val {
+                    Value::Int { val, .. } => val.to_string(),
+                    Value::Float { val, .. } if val.is_finite() => val.to_string(),
+                    Value::Bool { val, .. } => val.to_string(),
+                    Value::Nothing { .. } => "NULL".to_string(),
+                    Value::String { val, .. } => quote_sql_string(&val),
+                    other => {
+                        return Err(ShellError::UnsupportedInput(
+                            format!(
+                                "param '{}' is a {}, expected a string, number, bool or null",
+                                name,
+                                other.get_type()
+                            ),
+                            span,
+                        ))
+                    }
+                };
+                literals.insert(param_name(&name, span)?, literal);
+            }
+            return Ok(literals);
+        }
+        Some(Value::List { vals, .. }) => vals,
+        Some(value) => vec![value],
+    };
+
+    for param in params {
+        let span = param.span()?;
+        let param = param.as_string()?;
+        let (name, value) = param.split_once('=').ok_or_else(|| {
+            ShellError::UnsupportedInput(
+                format!("param '{}' is not of the form name=value", param),
+                span,
+            )
+        })?;
+
+        let is_number =
+            value.parse::<i64>().is_ok() || value.parse::<f64>().map_or(false, |v| v.is_finite());
+        let literal = if is_number {
+            value.to_string()
+        } else {
+            quote_sql_string(value)
+        };
+        literals.insert(param_name(name, span)?, literal);
+    }
+
+    Ok(literals)
+}
+
+fn param_name(name: &str, span: Span) -> Result<String, ShellError> {
+    let name = name.trim();
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(ShellError::UnsupportedInput(
+            format!(
+                "'{}' is not a valid param name, use letters, digits and _",
+                name
+            ),
+            span,
+        ))
+    }
+}
+
+fn quote_sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Replaces the $name placeholders of the query with the param literals
+///
+/// Placeholders inside quoted strings and identifiers are left alone. A
+/// placeholder without a param is an error naming every one missing,
+/// rather than sending the query with the placeholder still in it.
+pub fn substitute_sql_params(
+    sql: &Spanned<String>,
+    params: &HashMap<String, String>,
+) -> Result<Spanned<String>, ShellError> {
+    let mut substituted = String::with_capacity(sql.item.len());
+    let mut missing: Vec<&str> = Vec::new();
+    let mut quote = None;
+    let mut rest = sql.item.as_str();
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+
+        match (quote, c) {
+            // a doubled quote inside a literal toggles twice and stays inside
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), c) => {
+                substituted.push(c);
+                continue;
+            }
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '$') => {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let name = &rest[..len];
+
+                if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+                    substituted.push(c);
+                    continue;
+                }
+
+                match params.get(name) {
+                    Some(literal) => substituted.push_str(literal),
+                    None if !missing.contains(&name) => missing.push(name),
+                    None => {}
+                }
+                rest = &rest[len..];
+                continue;
+            }
+            _ => {}
+        }
+        substituted.push(c);
+    }
+
+    if !missing.is_empty() {
+        return Err(ShellError::GenericError(
+            "Unresolved query placeholders".into(),
+            format!(
+                "no param for {}",
+                missing
+                    .iter()
+                    .map(|name| format!("${}", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Some(sql.span),
+            Some("Pass a value for each with --param name=value".into()),
+            Vec::new(),
+        ));
+    }
+
+    Ok(Spanned {
+        item: substituted,
+        span: sql.span,
+    })
+}
+
+/// Splits a script into its statements on the semicolons between them
+///
+/// A semicolon inside a quoted literal or identifier doesn't end the
+/// statement. Statements are trimmed and empty ones, like the one after a
+/// trailing semicolon, are dropped.
+pub fn split_sql_statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut statement = String::new();
+    let mut quote = None;
+
+    for c in sql.chars() {
+        match (quote, c) {
+            // a doubled quote inside a literal toggles twice and stays inside
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ';') => {
+                statements.push(std::mem::take(&mut statement));
+                continue;
+            }
+            _ => {}
+        }
+        statement.push(c);
+    }
+    statements.push(statement);
+
+    statements
+        .into_iter()
+        .map(|statement| statement.trim().to_string())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+/// Lists the databases on the server, once for all the databases --check-db checks
+fn list_databases(
+    connector: IoxConnector,
+    addr: &str,
+    span: Span,
+) -> Result<Vec<String>, ShellError> {
+    tokio_block_namespace_names(connector, addr)?.map_err(|e| {
+        ShellError::GenericError(
+            "Failed to list the Iox databases".into(),
+            e.to_string(),
+            Some(span),
+            None,
+            Vec::new(),
+        )
+    })
+}
+
+/// Runs the query against each database and concatenates the rows
+///
+/// Every row starts with a namespace column naming the database it came
+/// from. A database that fails is skipped and its error returned in the
+/// list of failures, unless fail_fast is set and it aborts the rest.
+pub fn query_namespaces<F>(
+    dbnames: &[String],
+    fail_fast: bool,
+    span: Span,
+    mut run: F,
+) -> Result<(Value, Vec<String>), ShellError>
+where
+    F: FnMut(&str) -> Result<Vec<RecordBatch>, ShellError>,
+{
+    let mut rows = vec![];
+    let mut failures = vec![];
+
+    for dbname in dbnames {
+        let batches = match run(dbname) {
+            Ok(batches) => batches,
+            Err(error) if fail_fast => return Err(error),
+            Err(ShellError::GenericError(_, msg, ..)) => {
+                failures.push(format!("database '{}' failed: {}", dbname, msg));
+                continue;
+            }
+            Err(error) => {
+                failures.push(format!("database '{}' failed: {}", dbname, error));
+                continue;
+            }
+        };
+
+        for batch in &batches {
+            for row in batch_to_values(batch, span)? {
+                if let Value::Record {
+                    mut cols,
+                    mut vals,
+                    span,
+                } = row
+                {
+                    cols.insert(0, "namespace".to_string());
+                    vals.insert(
+                        0,
+                        Value::String {
+                            val: dbname.to_string(),
+                            span,
+                        },
+                    );
+                    rows.push(Value::Record { cols, vals, span });
+                }
+            }
+        }
+    }
+
+    Ok((Value::List { vals: rows, span }, failures))
+}
+
+/// Runs each statement of a script in order and lists their results
+///
+/// Every result is a record with the statement's index, its SQL and the
+/// table it returned. The first statement that fails stops the script, its
+/// error names the statement so the rest of the script can be fixed.
+pub fn run_statements<F>(statements: &[String], span: Span, mut run: F) -> Result<Value, ShellError>
+where
+    F: FnMut(&str) -> Result<Value, ShellError>,
+{
+    let mut results = vec![];
+
+    for (index, statement) in statements.iter().enumerate() {
+        let result = run(statement).map_err(|error| {
+            let (msg, error_span, help) = match error {
+                ShellError::GenericError(title, msg, error_span, help, _) => {
+                    (format!("{}: {}", title, msg), error_span, help)
+                }
+                error => (error.to_string(), None, None),
+            };
+            ShellError::GenericError(
+                format!("Statement {} failed", index),
+                msg,
+                error_span.or(Some(span)),
+                help.or_else(|| Some(format!("The statement was: {}", statement))),
+                Vec::new(),
+            )
+        })?;
+
+        results.push(Value::Record {
+            cols: vec!["index".into(), "statement".into(), "result".into()],
+            vals: vec![
+                Value::Int {
+                    val: index as i64,
+                    span,
+                },
+                Value::String {
+                    val: statement.clone(),
+                    span,
+                },
+                result,
+            ],
+            span,
+        });
+    }
+
+    Ok(Value::List {
+        vals: results,
+        span,
+    })
+}
+
+/// Keeps at most `limit` rows of the batches, in order
+///
+/// A LIMIT already in the query applies first, so the smaller of the two
+/// wins. Batches past the limit are dropped and the last one kept is sliced.
+pub fn limit_batches(batches: Vec<RecordBatch>, limit: Option<usize>) -> Vec<RecordBatch> {
+    let mut remaining = match limit {
+        Some(limit) => limit,
+        None => return batches,
+    };
+
+    let mut limited = Vec::new();
+    for batch in batches {
+        if remaining == 0 {
+            break;
+        }
+
+        if batch.num_rows() <= remaining {
+            remaining -= batch.num_rows();
+            limited.push(batch);
+        } else {
+            limited.push(batch.slice(0, remaining));
+            remaining = 0;
+        }
+    }
+
+    limited
+}
+
+/// Reads the --columns list, each name keeps its own span for the errors
+pub fn columns_from_flag(
+    columns: Option<Vec<Value>>,
+) -> Result<Option<Vec<Spanned<String>>>, ShellError> {
+    let columns = match columns {
+        Some(columns) => columns,
+        None => return Ok(None),
+    };
+
+    columns
+        .into_iter()
+        .map(|column| {
+            Ok(Spanned {
+                span: column.span()?,
+                item: column.as_string()?,
+            })
+        })
+        .collect::<Result<Vec<_>, ShellError>>()
+        .map(Some)
+}
+
+/// A --sort key, the column and whether it sorts in descending order
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub column: Spanned<String>,
+    pub descending: bool,
+}
+
+/// Reads the --sort value, a column, column:asc or column:desc or a list of them
+///
+/// A suffix other than asc or desc is taken to be part of the column name.
+pub fn sort_keys_from_value(sort: Option<Value>) -> Result<Option<Vec<SortKey>>, ShellError> {
+    let keys = match sort {
+        None => return Ok(None),
+        Some(Value::List { vals, .. }) => vals,
+        Some(value) => vec![value],
+    };
+
+    keys.iter()
+        .map(|key| {
+            let span = key.span()?;
+            let key = key.as_string()?;
+            let (column, descending) = match key.rsplit_once(':') {
+                Some((column, order)) if order.eq_ignore_ascii_case("asc") => (column, false),
+                Some((column, order)) if order.eq_ignore_ascii_case("desc") => (column, true),
+                _ => (key.as_str(), false),
+            };
+
+            Ok(SortKey {
+                column: Spanned {
+                    item: column.to_string(),
+                    span,
+                },
+                descending,
+            })
+        })
+        .collect::<Result<Vec<_>, ShellError>>()
+        .map(Some)
+}
+
+/// Sorts the rows of the batches by the keys, comparing the typed values
+///
+/// Numbers sort as numbers and times as times rather than as text. Nulls
+/// come last in ascending and first in descending order, like in SQL. The
+/// rows are returned in a single batch.
+pub fn sort_batches(
+    batches: Vec<RecordBatch>,
+    keys: &[SortKey],
+) -> Result<Vec<RecordBatch>, ShellError> {
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => return Ok(batches),
+    };
+
+    for key in keys {
+        validate_index_column(&batches, &key.column)?;
+    }
+
+    let sort_error = |e: arrow::error::ArrowError| {
+        ShellError::GenericError(
+            "Error sorting the results".into(),
+            e.to_string(),
+            keys.first().map(|key| key.column.span),
+            None,
+            Vec::new(),
+        )
+    };
+
+    let batch = RecordBatch::concat(&schema, &batches).map_err(sort_error)?;
+    let sort_columns = keys
+        .iter()
+        .map(|key| {
+            Ok(SortColumn {
+                values: batch.column(schema.index_of(&key.column.item)?).clone(),
+                options: Some(SortOptions {
+                    descending: key.descending,
+                    nulls_first: key.descending,
+                }),
+            })
+        })
+        .collect::<Result<Vec<_>, arrow::error::ArrowError>>()
+        .map_err(sort_error)?;
+    let indices = lexsort_to_indices(&sort_columns, None).map_err(sort_error)?;
+
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sort_error)?;
+
+    Ok(vec![
+        RecordBatch::try_new(schema, columns).map_err(sort_error)?
+    ])
+}
+
+/// Keeps just the named columns of the batches, in the order they are named
+///
+/// A name missing from the schema is an error at its span. Without batches
+/// there is no schema to check against, so nothing is returned either way.
+pub fn project_batches(
+    batches: Vec<RecordBatch>,
+    columns: &[Spanned<String>],
+) -> Result<Vec<RecordBatch>, ShellError> {
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => return Ok(batches),
+    };
+
+    let indices = columns
+        .iter()
+        .map(|column| {
+            schema.index_of(&column.item).map_err(|_| {
+                let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+                ShellError::UnsupportedInput(
+                    format!(
+                        "column '{}' is not in the results, the columns are {}",
+                        column.item,
+                        names.join(", ")
+                    ),
+                    column.span,
+                )
+            })
+        })
+        .collect::<Result<Vec<usize>, ShellError>>()?;
+
+    batches
+        .iter()
+        .map(|batch| {
+            batch.project(&indices).map_err(|e| {
+                ShellError::GenericError(
+                    "Error projecting the results".into(),
+                    e.to_string(),
+                    columns.first().map(|c| c.span),
+                    None,
+                    Vec::new(),
+                )
+            })
+        })
+        .collect()
+}
+
+pub fn query_stats_to_value(stats: &QueryStats, span: Span) -> Value {
+    Value::Record {
+        cols: vec![
+            "rows".to_string(),
+            "batches".to_string(),
+            "bytes".to_string(),
+            "elapsed_ms".to_string(),
+            "cached".to_string(),
+        ],
+        vals: vec![
+            Value::Int {
+                val: stats.rows as i64,
+                span,
+            },
+            Value::Int {
+                val: stats.batches as i64,
+                span,
+            },
+            Value::Filesize {
+                val: stats.bytes as i64,
+                span,
+            },
+            Value::Int {
+                val: stats.elapsed.as_millis() as i64,
+                span,
+            },
+            Value::Bool {
+                val: stats.cached,
+                span,
+            },
+        ],
+        span,
+    }
+}
+
+/// Converts the batches of a query result into the value of the given format
+///
+/// With stats the value is returned in a record alongside the query stats.
+pub fn results_to_value(
+    format: SqlFormat,
+    batches: &[RecordBatch],
+    stats: Option<&QueryStats>,
+    color: bool,
+    span: Span,
+) -> Result<Value, ShellError> {
+    let value = match format {
+        SqlFormat::Json => {
+            let json = format_batches(&QueryOutputFormat::Json, batches)
+                .map_err(|e| format_error(e, span))?;
+            from_json_data(&json, span)?
+        }
+        SqlFormat::Pretty => Value::String {
+            val: pretty_text(batches, color, span)?,
+            span,
+        },
+        SqlFormat::Ndjson => Value::String {
+            val: batches_to_ndjson(batches, span)?,
+            span,
+        },
+        _ => batches_to_value(batches, span)?,
+    };
+
+    Ok(match stats {
+        Some(stats) => Value::Record {
+            cols: vec!["results".to_string(), "stats".to_string()],
+            vals: vec![value, query_stats_to_value(stats, span)],
+            span,
+        },
+        None => value,
+    })
+}
+
+/// Formats the batches as a pretty printed table, without ANSI colors
+/// unless `color` is set
+pub fn pretty_text(batches: &[RecordBatch], color: bool, span: Span) -> Result<String, ShellError> {
+    let text =
+        format_batches(&QueryOutputFormat::Pretty, batches).map_err(|e| format_error(e, span))?;
+
+    Ok(if color { text } else { strip_colors(&text) })
+}
+
+/// Removes the ANSI escape codes from the text
+pub fn strip_colors(text: &str) -> String {
+    match strip_ansi_escapes::strip(text) {
+        Ok(stripped) => String::from_utf8_lossy(&stripped).to_string(),
+        Err(_) => text.to_string(),
+    }
+}
+
+/// Checks that the --index-by column is one of the result columns
+///
+/// An empty result has no schema to check against, and nothing to index.
+pub fn validate_index_column(
+    batches: &[RecordBatch],
+    column: &Spanned<String>,
+) -> Result<(), ShellError> {
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => return Ok(()),
+    };
+
+    if schema.field_with_name(&column.item).is_ok() {
+        return Ok(());
+    }
+
+    let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    Err(ShellError::UnsupportedInput(
+        format!(
+            "column '{}' is not in the results, the columns are {}",
+            column.item,
+            names.join(", ")
+        ),
+        column.span,
+    ))
+}
+
+/// Turns the list of rows into a record keyed by the values of the column
+///
+/// A key seen twice is an error unless allow_dup is set, then the last row
+/// with the key is kept. Rows keep their index column.
+pub fn index_rows_by(
+    rows: Value,
+    column: &Spanned<String>,
+    allow_dup: bool,
+    span: Span,
+) -> Result<Value, ShellError> {
+    let rows = match rows {
+        Value::List { vals, .. } => vals,
+        other => {
+            return Err(ShellError::UnsupportedInput(
+                format!("--index-by needs a list of rows, got {}", other.get_type()),
+                span,
+            ))
+        }
+    };
+
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut cols = Vec::new();
+    let mut vals = Vec::new();
+
+    for row in rows {
+        let key = match row.get_data_by_key(&column.item) {
+            None | Some(Value::Nothing { .. }) => {
+                return Err(ShellError::GenericError(
+                    "Row without an index key".into(),
+                    format!("a row has no value in column '{}'", column.item),
+                    Some(column.span),
+                    None,
+                    Vec::new(),
+                ))
+            }
+            Some(key) => key.as_string()?,
+        };
+
+        match positions.get(&key) {
+            Some(&position) if allow_dup => vals[position] = row,
+            Some(_) => {
+                return Err(ShellError::GenericError(
+                    "Duplicate index key".into(),
+                    format!(
+                        "'{}' appears more than once in column '{}'",
+                        key, column.item
+                    ),
+                    Some(column.span),
+                    Some("Pass --allow-dup to keep the last row of each key".into()),
+                    Vec::new(),
+                ))
+            }
+            None => {
+                positions.insert(key.clone(), cols.len());
+                cols.push(key);
+                vals.push(row);
+            }
+        }
+    }
+
+    Ok(Value::Record { cols, vals, span })
+}
+
+/// Formats the results as the text --output-file writes
+pub fn results_to_text(
+    format: SqlFormat,
+    batches: &[RecordBatch],
+    span: Span,
+) -> Result<String, ShellError> {
+    match format {
+        SqlFormat::Csv => {
+            format_batches(&QueryOutputFormat::Csv, batches).map_err(|e| format_error(e, span))
+        }
+        SqlFormat::Json => {
+            format_batches(&QueryOutputFormat::Json, batches).map_err(|e| format_error(e, span))
+        }
+        SqlFormat::Ndjson => batches_to_ndjson(batches, span),
+        _ => Err(ShellError::UnsupportedInput(
+            "only csv, json and ndjson results can be written as text".into(),
+            span,
+        )),
+    }
+}
+
+/// The csv text of the server as a single string, for `--raw`
+///
+/// Nothing is parsed, so a failed query is reported with `query_error`
+/// rather than going through the error handler.
+pub fn raw_result(sql_result: Result<String, Error>, call: &Call) -> Result<Value, ShellError> {
+    sql_result
+        .map(|val| Value::String {
+            val,
+            span: call.head,
+        })
+        .map_err(|error| query_error(error, call))
+}
+
+/// Parses --stall-timeout, unlike --timeout it is off unless given
+pub fn stall_timeout_from_value(
+    stall_timeout: Option<Value>,
+) -> Result<Option<Duration>, ShellError> {
+    match stall_timeout {
+        None => Ok(None),
+        stall_timeout => timeout_from_value(stall_timeout, Duration::ZERO),
+    }
+}
+
+/// Hands a failed query to the error handler, the query returns nothing
+/// when the handler doesn't turn it into an error
+fn handled_query_error(error: Error, call: &Call) -> Result<PipelineData, ShellError> {
+    let nierrorhandler = NuIoxErrorHandler::from_error(super::nuerror::CommandType::Sql, &error);
+
+    nierrorhandler.nu_iox_error_check()?;
+    nierrorhandler.nu_iox_error_generic(call)?;
+    Ok(PipelineData::new(call.head))
+}
+
+/// Turns a failed query into the error reported to the user
+pub fn query_error(error: Error, call: &Call) -> ShellError {
+    match error {
+        Error::Timeout { timeout } => ShellError::GenericError(
+            "Iox query timed out".into(),
+            format!("no result within {:?}", timeout),
+            Some(call.head),
+            Some("Raise the limit with --timeout, or use 0sec to wait forever".into()),
+            Vec::new(),
+        ),
+        Error::Stalled { timeout } => ShellError::GenericError(
+            "Iox query stalled".into(),
+            format!("no results arrived for {:?}", timeout),
+            Some(call.head),
+            Some("Raise the limit with --stall-timeout, or use 0sec to wait forever".into()),
+            Vec::new(),
+        ),
+        Error::Cancelled => ShellError::GenericError(
+            "Iox query cancelled".into(),
+            "interrupted with ctrl-c, partial results were dropped".into(),
+            Some(call.head),
+            None,
+            Vec::new(),
+        ),
+        error => {
+            let nierrorhandler =
+                NuIoxErrorHandler::from_error(super::nuerror::CommandType::Sql, &error);
+
+            match nierrorhandler.nu_iox_error_generic(call) {
+                Err(error) => error,
+                Ok(msg) => ShellError::GenericError(
+                    "Iox error".into(),
+                    msg,
+                    Some(call.head),
+                    None,
+                    Vec::new(),
+                ),
+            }
+        }
+    }
+}
+
+/// Reads a --watch flag value, the interval has to be longer than zero
+pub fn watch_interval_from_value(interval: Option<Value>) -> Result<Option<Duration>, ShellError> {
+    match interval {
+        None => Ok(None),
+        Some(Value::Duration { val, .. }) if val > 0 => Ok(Some(Duration::from_nanos(val as u64))),
+        Some(Value::Duration { span, .. }) => Err(ShellError::UnsupportedInput(
+            "the watch interval must be longer than zero".into(),
+            span,
+        )),
+        Some(value) => Err(ShellError::CantConvert(
+            "duration".into(),
+            value.get_type().to_string(),
+            value.span()?,
+            None,
+        )),
+    }
+}
+
+/// Reads a --cache flag value, the results have to be kept for some time
+pub fn cache_ttl_from_value(ttl: Option<Value>) -> Result<Option<Duration>, ShellError> {
+    match ttl {
+        None => Ok(None),
+        Some(Value::Duration { val, .. }) if val > 0 => Ok(Some(Duration::from_nanos(val as u64))),
+        Some(Value::Duration { span, .. }) => Err(ShellError::UnsupportedInput(
+            "the cache time to live must be longer than zero".into(),
+            span,
+        )),
+        Some(value) => Err(ShellError::CantConvert(
+            "duration".into(),
+            value.get_type().to_string(),
+            value.span()?,
+            None,
+        )),
+    }
+}
+
+/// The server address, token hash, namespace and sql a --cache result was
+/// produced by, see token_hash
+pub type QueryCacheKey = (String, Option<u64>, String, String);
+
+type QueryResult = Result<(Vec<RecordBatch>, QueryStats), Error>;
+
+/// A result kept by --cache, with when it expires
+type CachedQuery = (Instant, Vec<RecordBatch>, QueryStats);
+
+lazy_static! {
+    // The results of queries run with --cache
+    static ref QUERY_RESULTS: Mutex<HashMap<QueryCacheKey, CachedQuery>> =
+        Mutex::new(HashMap::new());
+}
+
+/// How many query results --cache keeps, the one expiring first makes room
+pub const MAX_CACHED_QUERIES: usize = 32;
+
+/// Returns the cached result of the query while it is fresh, otherwise runs it
+///
+/// A hit is reported as cached in the stats and takes no time. The cache is
+/// not locked while the query runs, so other queries aren't held up by it. A
+/// failed query is not cached, the next run tries again.
+pub fn cached_sql_batches<F>(
+    key: QueryCacheKey,
+    ttl: Duration,
+    now: Instant,
+    run: F,
+) -> Result<QueryResult, ShellError>
+where
+    F: FnOnce() -> Result<QueryResult, ShellError>,
+{
+    let cache = || {
+        QUERY_RESULTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    };
+
+    {
+        let mut cache = cache();
+        cache.retain(|_, (expires, ..)| now < *expires);
+
+        if let Some((_, batches, stats)) = cache.get(&key) {
+            return Ok(Ok((
+                batches.clone(),
+                QueryStats {
+                    elapsed: Duration::ZERO,
+                    cached: true,
+                    ..stats.clone()
+                },
+            )));
+        }
+    }
+
+    let result = run()?;
+    if let Ok((batches, stats)) = &result {
+        let mut cache = cache();
+        if !cache.contains_key(&key) {
+            make_room(&mut cache, MAX_CACHED_QUERIES);
+        }
+        cache.insert(key, (now + ttl, batches.clone(), stats.clone()));
+    }
+
+    Ok(result)
+}
+
+/// Drops the results expiring first until there is room for one more
+fn make_room(cache: &mut HashMap<QueryCacheKey, CachedQuery>, max: usize) {
+    while !cache.is_empty() && cache.len() >= max {
+        let first_to_expire = cache
+            .iter()
+            .min_by_key(|(_, (expires, ..))| *expires)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = first_to_expire {
+            cache.remove(&key);
+        }
+    }
+}
+
+/// How often waiting for the next watch cycle checks the interrupt flag
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs the query now and then again every interval, yielding each result
+///
+/// The stream only ends on ctrl-c, which is checked while waiting for the
+/// next cycle, or when a run fails, which is yielded as a Value::Error.
+pub fn watch_results<F>(
+    interval: Duration,
+    ctrlc: Option<Arc<AtomicBool>>,
+    mut run: F,
+) -> impl Iterator<Item = Value>
+where
+    F: FnMut() -> Result<Value, ShellError>,
+{
+    let interrupted = move || {
+        ctrlc
+            .as_ref()
+            .map_or(false, |c| c.load(std::sync::atomic::Ordering::SeqCst))
+    };
+    let mut next_run = std::time::Instant::now();
+    let mut done = false;
+
+    std::iter::from_fn(move || {
+        while !done && !interrupted() {
+            let now = std::time::Instant::now();
+            if now < next_run {
+                std::thread::sleep(WATCH_POLL_INTERVAL.min(next_run - now));
+                continue;
+            }
+            next_run = now + interval;
+
+            return match run() {
+                // an interrupted run ends the stream like an interrupted wait
+                Err(_) if interrupted() => None,
+                Err(error) => {
+                    done = true;
+                    Some(Value::Error { error })
+                }
+                Ok(value) => Some(value),
+            };
+        }
+
+        None
+    })
+}
+
+pub fn format_error(error: Error, span: Span) -> ShellError {
+    ShellError::GenericError(
+        "Failed to format Iox results".into(),
+        error.to_string(),
+        Some(span),
+        None,
+        Vec::new(),
+    )
+}
+
+/// How ioxsql runs its queries, the same whichever way the results are read
+#[derive(Clone)]
+pub struct SqlQueryOptions {
+    /// How long the query may run, None waits forever
+    pub timeout: Option<Duration>,
+    /// How long to wait for the next batch of results, None waits forever
+    pub stall_timeout: Option<Duration>,
+    pub retries: usize,
+    /// Worker threads of a runtime of the query's own, None uses the shared one
+    pub num_threads: Option<usize>,
+    pub ctrlc: Option<Arc<AtomicBool>>,
+    pub protocol: QueryProtocol,
+    /// Keeps the text of the query out of the logs, see resolve_iox_quiet
+    pub quiet: bool,
+}
+
+impl Default for SqlQueryOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Some(DEFAULT_QUERY_TIMEOUT),
+            stall_timeout: None,
+            retries: DEFAULT_RETRIES,
+            num_threads: None,
+            ctrlc: None,
+            protocol: QueryProtocol::Legacy,
+            quiet: false,
+        }
+    }
+}
+
+impl SqlQueryOptions {
+    /// Applies the options to a client connected for the query
+    pub fn configure(&self, repl: &mut Nuclient) {
+        repl.set_query_timeout(self.timeout);
+        repl.set_stall_timeout(self.stall_timeout);
+        repl.set_retries(self.retries);
+        repl.set_ctrlc(self.ctrlc.clone());
+        repl.set_query_protocol(self.protocol);
+        repl.set_quiet(self.quiet);
+    }
+}
+
+/// Resolves the flags of ioxsql that say how the query runs
+pub fn sql_query_options(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+) -> Result<SqlQueryOptions, ShellError> {
+    Ok(SqlQueryOptions {
+        timeout: timeout_from_value(
+            call.get_flag(engine_state, stack, "timeout")?,
+            DEFAULT_QUERY_TIMEOUT,
+        )?,
+        stall_timeout: stall_timeout_from_value(call.get_flag(
+            engine_state,
+            stack,
+            "stall-timeout",
+        )?)?,
+        retries: retries_from_flag(call.get_flag(engine_state, stack, "retries")?)?,
+        num_threads: num_threads_from_flag(call.get_flag(engine_state, stack, "num-threads")?)?,
+        ctrlc: engine_state.ctrlc.clone(),
+        protocol: query_protocol_from_flag(call.get_flag(engine_state, stack, "protocol")?)?,
+        quiet: resolve_iox_quiet(stack, engine_state),
+    })
+}
+
+/// The server ioxsql queries and how to connect to it, resolved once for
+/// all the queries of the command
+pub struct SqlConnection {
+    pub addr: String,
+    pub token: Option<String>,
+    pub tls_ca: Option<Spanned<String>>,
+    pub keepalive: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub compression: Compression,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub span: Span,
+}
+
+impl SqlConnection {
+    /// A connector for the next query, a connector is used up by connecting
+    pub fn connector(&self) -> Result<IoxConnector, ShellError> {
+        Ok(iox_connector(
+            &self.addr,
+            self.token.as_deref(),
+            self.tls_ca.as_ref(),
+            self.span,
+        )?
+        .with_keepalive(self.keepalive)
+        .with_connect_timeout(self.connect_timeout)
+        .with_compression(self.compression)
+        .with_headers(self.headers.clone()))
+    }
+}
+
+/// Resolves the connection flags of ioxsql, falling back to the profile,
+/// the env and the config
+///
+/// With --trace the id of the new trace is printed on stderr.
+pub fn sql_connection(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    compression: Compression,
+) -> Result<SqlConnection, ShellError> {
+    let addr_flag: Option<Spanned<String>> = call.get_flag(engine_state, stack, "addr")?;
+    let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+    let connect_timeout = timeout_from_value(
+        call.get_flag(engine_state, stack, "connect-timeout")?,
+        DEFAULT_CONNECT_TIMEOUT,
+    )?;
+
+    let profile = resolve_iox_profile(
+        stack,
+        engine_state,
+        call.get_flag(engine_state, stack, "profile")?,
+    )?;
+    let addr_span = addr_flag.as_ref().map_or(call.head, |a| a.span);
+    let addr = profile.resolve_addr(stack, engine_state, addr_flag.map(|a| a.item));
+    let addr = validate_iox_addr(&addr, addr_span)?;
+
+    let token = profile.resolve_token(stack, engine_state, token);
+    let tls_ca = resolve_iox_tls_ca(
+        stack,
+        engine_state,
+        call.get_flag(engine_state, stack, "tls-ca")?,
+        call.head,
+    );
+    let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+    let mut headers = headers_from_value(call.get_flag(engine_state, stack, "header")?)?;
+    let trace_id: Option<Spanned<String>> = call.get_flag(engine_state, stack, "trace-id")?;
+    let trace_id = match trace_id {
+        Some(trace_id) => Some(trace_id_from_flag(trace_id)?),
+        None if call.has_flag("trace") => {
+            let trace_id = new_trace_id();
+            eprintln!("ioxsql: trace id {}", trace_id);
+            Some(trace_id)
+        }
+        None => None,
+    };
+    if let Some(trace_id) = &trace_id {
+        headers.push(traceparent_header(trace_id, call.head)?);
+    }
+
+    Ok(SqlConnection {
+        addr,
+        token,
+        tls_ca,
+        keepalive,
+        connect_timeout,
+        compression,
+        headers,
+        span: call.head,
+    })
+}
+
+/// Connects a client to the database for the query and runs it, on a
+/// runtime of its own with --num-threads
+///
+/// A failed query forgets the cached connections to addr, so the next
+/// query connects again.
+fn tokio_block_on_nuclient<T, F, Fut>(
+    connector: IoxConnector,
+    addr: &str,
+    database: &RemoteDatabase,
+    options: &SqlQueryOptions,
+    query: F,
+) -> Result<Result<T, Error>, ShellError>
+where
+    F: FnOnce(Nuclient) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let connector = connector.for_num_threads(options.num_threads);
+    block_on_iox_with_threads(options.num_threads, async move {
+        let span = connector.span();
+        let mut repl = Nuclient::connect(addr, connector)
+            .await
+            .map_err(|e| connect_error(addr, e, span))?;
+        repl.use_remote_database(database.clone());
+        options.configure(&mut repl);
+
+        Ok::<_, ShellError>(forget_connection_on_error(addr, query(repl).await))
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?
+}
+
+pub fn tokio_block_sql(
+    connector: IoxConnector,
+    addr: &str,
+    database: &RemoteDatabase,
+    sql: &Spanned<String>,
+    options: &SqlQueryOptions,
+) -> Result<Result<String, Error>, ShellError> {
+    let sql = sql.item.to_string();
+    tokio_block_on_nuclient(connector, addr, database, options, |mut repl| async move {
+        let _output_format = repl.set_output_format("csv");
+
+        repl.run_sql(sql).await
+    })
+}
+
+pub fn tokio_block_sql_batches(
+    connector: IoxConnector,
+    addr: &str,
+    database: &RemoteDatabase,
+    sql: &Spanned<String>,
+    options: &SqlQueryOptions,
+) -> Result<Result<(Vec<RecordBatch>, QueryStats), Error>, ShellError> {
+    let sql = sql.item.to_string();
+    tokio_block_on_nuclient(connector, addr, database, options, |mut repl| async move {
+        repl.run_sql_batches_with_stats(sql).await
+    })
+}
+
+pub fn tokio_block_sql_stream(
+    connector: IoxConnector,
+    addr: &str,
+    database: &RemoteDatabase,
+    sql: &Spanned<String>,
+    options: &SqlQueryOptions,
+) -> Result<Result<PerformQuery, Error>, ShellError> {
+    let sql = sql.item.to_string();
+    tokio_block_on_nuclient(connector, addr, database, options, |mut repl| async move {
+        repl.start_query(sql).await
+    })
+}
+
+pub fn tokio_block_sql_count(
+    connector: IoxConnector,
+    addr: &str,
+    database: &RemoteDatabase,
+    sql: &Spanned<String>,
+    options: &SqlQueryOptions,
+) -> Result<Result<usize, Error>, ShellError> {
+    let sql = sql.item.to_string();
+    tokio_block_on_nuclient(connector, addr, database, options, |mut repl| async move {
+        repl.count_sql_rows(sql).await
+    })
+}
+
+pub fn tokio_block_sql_schema(
+    connector: IoxConnector,
+    addr: &str,
+    database: &RemoteDatabase,
+    sql: &Spanned<String>,
+    options: &SqlQueryOptions,
+) -> Result<Result<Option<SchemaRef>, Error>, ShellError> {
+    let sql = sql.item.to_string();
+    tokio_block_on_nuclient(connector, addr, database, options, |mut repl| async move {
+        repl.query_schema(sql).await
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // No timeout and no retries, so a rejected query fails at once
+    fn test_options() -> SqlQueryOptions {
+        SqlQueryOptions {
+            timeout: None,
+            retries: 0,
+            ..Default::default()
+        }
+    }
+
+    // An address nothing listens on, the port was free a moment ago
+    fn closed_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    #[test]
+    fn stopped_server_is_an_error_not_a_panic() {
+        let addr = closed_addr();
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+        let sql = Spanned {
+            item: "select * from cpu".to_string(),
+            span: Span::test_data(),
+        };
+
+        let result = tokio_block_sql(
+            connector,
+            &addr,
+            &RemoteDatabase::new("bananas".to_string(), None),
+            &sql,
+            &test_options(),
+        );
+
+        match result {
+            Err(ShellError::GenericError(msg, ..)) => assert!(msg.contains(&addr)),
+            other => panic!("expected a connection error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn conflicting_flags_name_the_ones_given() {
+        let span = Span::test_data();
+        let given = [("--watch", span), ("--stream", span), ("--out", span)];
+
+        match check_flag_conflicts(SQL_FLAG_CONFLICTS, &given) {
+            Err(ShellError::IncompatibleParametersSingle(msg, _)) => assert_eq!(
+                msg,
+                "--watch re-runs the query on the shared runtime, it can't be combined with --stream, --out"
+            ),
+            other => panic!("expected conflicting flags, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn flags_that_go_together_pass() {
+        let span = Span::test_data();
+        let given = [
+            ("--count-only", span),
+            ("--stream", span),
+            ("--num-threads", span),
+            ("--protocol legacy", span),
+        ];
+
+        assert!(check_flag_conflicts(SQL_FLAG_CONFLICTS, &given).is_ok());
+    }
+
+    #[test]
+    fn flag_values_conflict_by_name() {
+        let span = Span::test_data();
+        let gzip = [("--compression gzip", span), ("--protocol legacy", span)];
+        let csv = [("--stats", span), ("--format csv", span)];
+
+        assert!(check_flag_conflicts(SQL_FLAG_CONFLICTS, &gzip).is_err());
+        assert!(check_flag_conflicts(SQL_FLAG_CONFLICTS, &csv).is_err());
+        assert!(check_flag_conflicts(SQL_FLAG_CONFLICTS, &[("--stats", span)]).is_ok());
+    }
+
+    // Answers every request with the given gRPC status code, handing the
+    // request headers to the test
+    fn mock_grpc_server(
+        grpc_status: &'static str,
+    ) -> (String, std::sync::mpsc::Receiver<http::HeaderMap>) {
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Request, Response, Server};
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            runtime.block_on(async move {
+                let make_service = make_service_fn(move |_| {
+                    let tx = tx.clone();
+                    async move {
+                        Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                            let _ = tx.send(request.headers().clone());
+                            async move {
+                                Response::builder()
+                                    .header("content-type", "application/grpc")
+                                    .header("grpc-status", grpc_status)
+                                    .body(Body::empty())
+                            }
+                        }))
+                    }
+                });
+
+                Server::from_tcp(listener)
+                    .unwrap()
+                    .http2_only(true)
+                    .serve(make_service)
+                    .await
+            })
+        });
+
+        (addr, rx)
+    }
+
+    #[test]
+    fn custom_headers_are_sent_with_the_query() {
+        // 12 is unimplemented
+        let (addr, requests) = mock_grpc_server("12");
+        let headers = headers_from_value(Some(Value::test_string("x-tenant-id=bananas"))).unwrap();
+        let connector = iox_connector(&addr, None, None, Span::test_data())
+            .unwrap()
+            .with_headers(headers);
+        let sql = Spanned {
+            item: "select * from cpu".to_string(),
+            span: Span::test_data(),
+        };
+
+        // the mock rejects the query, only the request it saw matters
+        let _ = tokio_block_sql_batches(
+            connector,
+            &addr,
+            &RemoteDatabase::new("bananas".to_string(), None),
+            &sql,
+            &test_options(),
+        );
+
+        let headers = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(headers["x-tenant-id"], "bananas");
+    }
+
+    #[test]
+    fn token_is_sent_as_a_bearer_authorization_header() {
+        // 12 is unimplemented
+        let (addr, requests) = mock_grpc_server("12");
+        let connector = iox_connector(&addr, Some("s3cr3t"), None, Span::test_data()).unwrap();
+        let sql = Spanned {
+            item: "select * from cpu".to_string(),
+            span: Span::test_data(),
+        };
+
+        // the mock rejects the query, only the request it saw matters
+        let _ = tokio_block_sql_batches(
+            connector,
+            &addr,
+            &RemoteDatabase::new("bananas".to_string(), None),
+            &sql,
+            &test_options(),
+        );
+
+        let headers = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(headers["authorization"], "Bearer s3cr3t");
+    }
+
+    #[test]
+    fn trace_id_is_sent_as_traceparent() {
+        // 12 is unimplemented
+        let (addr, requests) = mock_grpc_server("12");
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let connector = iox_connector(&addr, None, None, Span::test_data())
+            .unwrap()
+            .with_headers(vec![
+                traceparent_header(trace_id, Span::test_data()).unwrap()
+            ]);
+        let sql = Spanned {
+            item: "select * from cpu".to_string(),
+            span: Span::test_data(),
+        };
+
+        // the mock rejects the query, only the request it saw matters
+        let _ = tokio_block_sql_batches(
+            connector,
+            &addr,
+            &RemoteDatabase::new("bananas".to_string(), None),
+            &sql,
+            &test_options(),
+        );
+
+        let headers = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        let traceparent = headers["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with(&format!("00-{}-", trace_id)));
+        assert!(traceparent.ends_with("-01"));
+    }
+
+    #[cfg(feature = "iox-flightsql")]
+    #[test]
+    fn flight_sql_asks_for_gzipped_results() {
+        let accept_encoding = |compression| {
+            // 12 is unimplemented
+            let (addr, requests) = mock_grpc_server("12");
+            let connector = iox_connector(&addr, None, None, Span::test_data())
+                .unwrap()
+                .with_compression(compression);
+            let sql = Spanned {
+                item: "select * from cpu".to_string(),
+                span: Span::test_data(),
+            };
+
+            // the mock rejects the query, only the request it saw matters
+            let _ = tokio_block_sql_batches(
+                connector,
+                &addr,
+                &RemoteDatabase::new("bananas".to_string(), None),
+                &sql,
+                &SqlQueryOptions {
+                    protocol: QueryProtocol::FlightSql,
+                    ..test_options()
+                },
+            );
+
+            let headers = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+            headers
+                .get("grpc-accept-encoding")
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        assert!(accept_encoding(Compression::Gzip).unwrap().contains("gzip"));
+        assert_eq!(accept_encoding(Compression::None), None);
+    }
+
+    #[test]
+    fn async_connection_is_not_shared_after_its_runtime_ends() {
+        // 12 is unimplemented
+        let (addr, requests) = mock_grpc_server("12");
+        let database = RemoteDatabase::new("bananas".to_string(), None);
+        let sql = Spanned {
+            item: "select * from cpu".to_string(),
+            span: Span::test_data(),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let result = runtime.block_on(crate::iox::run_sql_async(
+            &addr,
+            database.clone(),
+            &sql.item,
+        ));
+        drop(runtime);
+        assert!(!matches!(result, Err(Error::Connecting { .. })));
+        requests.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // a connection cached on the dropped runtime could no longer send this
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+        let _ = tokio_block_sql_batches(
+            connector,
+            &addr,
+            &database,
+            &sql,
+            &SqlQueryOptions {
+                timeout: Some(Duration::from_secs(5)),
+                ..test_options()
+            },
+        );
+        requests.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn unauthenticated_query_keeps_its_status_code() {
+        // 16 is unauthenticated
+        let (addr, _requests) = mock_grpc_server("16");
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+        let sql = Spanned {
+            item: "select * from cpu".to_string(),
+            span: Span::test_data(),
+        };
+
+        let result = tokio_block_sql_batches(
+            connector,
+            &addr,
+            &RemoteDatabase::new("bananas".to_string(), None),
+            &sql,
+            &test_options(),
+        )
+        .unwrap();
+
+        match result {
+            Err(error) => assert_eq!(error.grpc_code(), Some(tonic::Code::Unauthenticated)),
+            Ok(_) => panic!("expected the query to be rejected"),
+        }
+    }
+
+    fn rows_batch(rows: i64) -> RecordBatch {
+        use arrow::array::{ArrayRef, Int64Array};
+
+        let user: Int64Array = (0..rows).collect::<Vec<i64>>().into();
+        RecordBatch::try_from_iter(vec![("user", Arc::new(user) as ArrayRef)]).unwrap()
+    }
+
+    fn num_rows(batches: &[RecordBatch]) -> usize {
+        batches.iter().map(|b| b.num_rows()).sum()
+    }
+
+    #[test]
+    fn explain_prefixes_the_query_once() {
+        assert_eq!(
+            explain_sql("select * from cpu", false),
+            "EXPLAIN select * from cpu"
+        );
+        assert_eq!(
+            explain_sql("select * from cpu", true),
+            "EXPLAIN ANALYZE select * from cpu"
+        );
+        assert_eq!(
+            explain_sql("  explain select * from cpu", true),
+            "  explain select * from cpu"
+        );
+        assert_eq!(
+            explain_sql("EXPLAIN ANALYZE select 1", false),
+            "EXPLAIN ANALYZE select 1"
+        );
+        assert_eq!(
+            explain_sql("explained_view", false),
+            "EXPLAIN explained_view"
+        );
+    }
+
+    fn dbnames() -> Vec<String> {
+        vec!["bananas".to_string(), "apples".to_string()]
+    }
+
+    #[test]
+    fn rows_are_tagged_with_their_namespace() {
+        let span = Span::test_data();
+        let (table, failures) = query_namespaces(&dbnames(), false, span, |dbname| {
+            Ok(vec![rows_batch(if dbname == "bananas" { 2 } else { 1 })])
+        })
+        .unwrap();
+
+        let rows = match table {
+            Value::List { vals, .. } => vals,
+            _ => panic!("expected a list"),
+        };
+        let namespaces: Vec<Value> = rows
+            .iter()
+            .map(|row| row.get_data_by_key("namespace").unwrap())
+            .collect();
+
+        assert!(failures.is_empty());
+        assert_eq!(
+            namespaces,
+            vec![
+                Value::test_string("bananas"),
+                Value::test_string("bananas"),
+                Value::test_string("apples"),
+            ]
+        );
+        assert_eq!(
+            rows[2].get_data_by_key("user"),
+            Some(Value::Int { val: 0, span })
+        );
+    }
+
+    #[test]
+    fn failing_namespace_is_reported_and_skipped() {
+        let failing = |dbname: &str| {
+            if dbname == "bananas" {
+                Err(ShellError::GenericError(
+                    "Query against database 'bananas' failed".into(),
+                    "table cpu not found".into(),
+                    None,
+                    None,
+                    Vec::new(),
+                ))
+            } else {
+                Ok(vec![rows_batch(1)])
+            }
+        };
+
+        let (table, failures) =
+            query_namespaces(&dbnames(), false, Span::test_data(), failing).unwrap();
+        assert_eq!(
+            failures,
+            vec!["database 'bananas' failed: table cpu not found"]
+        );
+        assert!(matches!(table, Value::List { vals, .. } if vals.len() == 1));
+
+        assert!(query_namespaces(&dbnames(), true, Span::test_data(), failing).is_err());
+    }
+
+    #[test]
+    fn script_is_split_into_statements() {
+        assert_eq!(
+            split_sql_statements("select 1; select ';' from cpu;\n select \"a;b\" from mem ;"),
+            vec!["select 1", "select ';' from cpu", "select \"a;b\" from mem"]
+        );
+        assert_eq!(
+            split_sql_statements("select 'it''s; here'"),
+            vec!["select 'it''s; here'"]
+        );
+        assert!(split_sql_statements(" ; ").is_empty());
+    }
+
+    #[test]
+    fn statements_run_in_order_and_are_tagged() {
+        let span = Span::test_data();
+        let statements = split_sql_statements("select * from cpu; select * from mem");
+        let mut ran = vec![];
+        let results = run_statements(&statements, span, |statement| {
+            ran.push(statement.to_string());
+            results_to_value(
+                SqlFormat::Json,
+                &[rows_batch(ran.len() as i64)],
+                None,
+                false,
+                span,
+            )
+        })
+        .unwrap();
+
+        assert_eq!(ran, vec!["select * from cpu", "select * from mem"]);
+        let results = match results {
+            Value::List { vals, .. } => vals,
+            _ => panic!("expected a list"),
+        };
+        assert_eq!(results.len(), 2);
+        for (index, result) in results.iter().enumerate() {
+            assert_eq!(
+                result.get_data_by_key("index"),
+                Some(Value::Int {
+                    val: index as i64,
+                    span
+                })
+            );
+            assert_eq!(
+                result.get_data_by_key("statement"),
+                Some(Value::test_string(&ran[index]))
+            );
+            assert!(
+                matches!(result.get_data_by_key("result"), Some(Value::List { vals, .. }) if vals.len() == index + 1)
+            );
+        }
+
+        let failed = run_statements(&statements, span, |statement| {
+            if statement.contains("mem") {
+                Err(ShellError::GenericError(
+                    "Query failed".into(),
+                    "This feature is not implemented".into(),
+                    None,
+                    None,
+                    Vec::new(),
+                ))
+            } else {
+                Ok(Value::nothing(span))
+            }
+        });
+        match failed {
+            Err(ShellError::GenericError(title, msg, ..)) => {
+                assert_eq!(title, "Statement 1 failed");
+                assert_eq!(msg, "Query failed: This feature is not implemented");
+            }
+            other => panic!("expected the second statement to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn limit_truncates_across_batches() {
+        let batches = vec![rows_batch(3), rows_batch(3), rows_batch(3)];
+
+        let limited = limit_batches(batches, Some(5));
+
+        assert_eq!(limited.len(), 2);
+        assert_eq!(limited[1].num_rows(), 2);
+        assert_eq!(num_rows(&limited), 5);
+    }
+
+    #[test]
+    fn limit_larger_than_the_result_is_a_no_op() {
+        let batches = vec![rows_batch(2), rows_batch(1)];
+
+        assert_eq!(num_rows(&limit_batches(batches.clone(), Some(10))), 3);
+        assert_eq!(num_rows(&limit_batches(batches.clone(), None)), 3);
+        assert!(limit_batches(batches, Some(0)).is_empty());
+    }
+
+    #[test]
+    fn limit_flag_rejects_negative_values() {
+        let limit = |item| Spanned {
+            item,
+            span: Span::test_data(),
+        };
+
+        assert_eq!(limit_from_flag(None).unwrap(), None);
+        assert_eq!(limit_from_flag(Some(limit(7))).unwrap(), Some(7));
+        assert!(limit_from_flag(Some(limit(-1))).is_err());
+    }
+
+    #[test]
+    fn protocol_defaults_to_legacy() {
+        assert_eq!(
+            query_protocol_from_flag(None).unwrap(),
+            QueryProtocol::Legacy
+        );
+        assert_eq!(
+            query_protocol_from_flag(Some(spanned("flightsql"))).unwrap(),
+            QueryProtocol::FlightSql
+        );
+        assert!(query_protocol_from_flag(Some(spanned("grpc"))).is_err());
+    }
+
+    #[test]
+    fn compression_defaults_to_none() {
+        assert_eq!(compression_from_flag(None).unwrap(), Compression::None);
+        assert_eq!(
+            compression_from_flag(Some(spanned("gzip"))).unwrap(),
+            Compression::Gzip
+        );
+        assert!(compression_from_flag(Some(spanned("zstd"))).is_err());
+    }
+
+    #[test]
+    fn header_only_csv_is_an_empty_table() {
+        let span = Span::test_data();
+        let input = PipelineData::Value(Value::test_string("region,user\n"), None);
+
+        let table = from_delimited_data(
+            false,
+            false,
+            ',',
+            csv::Trim::None,
+            input,
+            span,
+            &nu_protocol::Config::default(),
+        )
+        .unwrap()
+        .into_value(span);
+
+        assert_eq!(table, Value::List { vals: vec![], span });
+    }
+
+    #[test]
+    fn csv_parse_errors_point_at_the_call() {
+        let head = Span::new(10, 16);
+        let input = PipelineData::Value(Value::test_string("region,user\nla\n"), None);
+
+        let result = from_delimited_data(
+            false,
+            false,
+            ',',
+            csv::Trim::None,
+            input,
+            head,
+            &nu_protocol::Config::default(),
+        );
+
+        match result {
+            Err(ShellError::DelimiterError(_, span)) => assert_eq!(span, head),
+            other => panic!("expected a delimiter error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn spanned(item: &str) -> Spanned<String> {
+        Spanned {
+            item: item.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn sql_is_read_verbatim_from_file() {
+        let path = std::env::temp_dir().join("nu_iox_sql_from_file.sql");
+        let query = "select *\nfrom cpu\nwhere region = 'la';\n";
+        std::fs::write(&path, query).unwrap();
+
+        let sql = sql_from_args(
+            None,
+            Some(spanned(&path.to_string_lossy())),
+            Span::test_data(),
+        );
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(sql.unwrap().item, query);
+    }
+
+    #[test]
+    fn missing_sql_file_names_the_path() {
+        let sql = sql_from_args(None, Some(spanned("/no/such/query.sql")), Span::test_data());
+
+        match sql {
+            Err(ShellError::ReadingFile(msg, _)) => assert!(msg.contains("/no/such/query.sql")),
+            other => panic!("expected a reading file error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_query_is_an_error() {
+        let path = std::env::temp_dir().join("nu_iox_empty_query.sql");
+        std::fs::write(&path, " \n\t\n").unwrap();
+
+        let from_file = sql_from_args(
+            None,
+            Some(spanned(&path.to_string_lossy())),
+            Span::test_data(),
+        );
+        let _ = std::fs::remove_file(path);
+
+        for sql in [
+            sql_from_args(Some(spanned("")), None, Span::test_data()),
+            sql_from_args(Some(spanned("  \n ")), None, Span::test_data()),
+            from_file,
+        ] {
+            match sql {
+                Err(ShellError::GenericError(title, ..)) => assert_eq!(title, "Empty query"),
+                other => panic!("expected an empty query error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn query_and_file_are_exclusive() {
+        let sql = sql_from_args(
+            Some(spanned("select 1")),
+            Some(spanned("query.sql")),
+            Span::test_data(),
+        );
+
+        assert!(matches!(
+            sql,
+            Err(ShellError::IncompatibleParameters { .. })
+        ));
+        assert!(matches!(
+            sql_from_args(None, None, Span::test_data()),
+            Err(ShellError::MissingParameter(..))
+        ));
+    }
+
+    #[test]
+    fn format_defaults_to_arrow() {
+        assert_eq!(sql_format_from_str(None).unwrap(), SqlFormat::Arrow);
+    }
+
+    #[test]
+    fn format_parses_known_values() {
+        assert_eq!(
+            sql_format_from_str(Some(Value::test_string("json"))).unwrap(),
+            SqlFormat::Json
+        );
+        assert_eq!(
+            sql_format_from_str(Some(Value::test_string("csv"))).unwrap(),
+            SqlFormat::Csv
+        );
+        assert_eq!(
+            sql_format_from_str(Some(Value::test_string("ndjson"))).unwrap(),
+            SqlFormat::Ndjson
+        );
+    }
+
+    #[test]
+    fn stats_record_reports_rows_batches_bytes_and_elapsed_ms() {
+        let span = Span::test_data();
+        let stats = QueryStats {
+            rows: 3,
+            batches: 2,
+            bytes: 1024,
+            elapsed: std::time::Duration::from_millis(42),
+            cached: false,
+        };
+
+        let expected = Value::Record {
+            cols: vec![
+                "rows".to_string(),
+                "batches".to_string(),
+                "bytes".to_string(),
+                "elapsed_ms".to_string(),
+                "cached".to_string(),
+            ],
+            vals: vec![
+                Value::Int { val: 3, span },
+                Value::Int { val: 2, span },
+                Value::Filesize { val: 1024, span },
+                Value::Int { val: 42, span },
+                Value::Bool { val: false, span },
+            ],
+            span,
+        };
+
+        assert_eq!(query_stats_to_value(&stats, span), expected);
+    }
+
+    fn cache_key(sql: &str) -> QueryCacheKey {
+        (
+            "http://cache.example.com:8082".to_string(),
+            None,
+            "bananas".to_string(),
+            sql.to_string(),
+        )
+    }
+
+    #[test]
+    fn cached_query_is_not_run_again_within_the_ttl() {
+        let runs = std::cell::Cell::new(0);
+        let run = || {
+            runs.set(runs.get() + 1);
+            let batches = vec![rows_batch(2)];
+            let stats = QueryStats::new(&batches, Duration::from_millis(42));
+            Ok(Ok((batches, stats)))
+        };
+        let ttl = Duration::from_secs(60);
+        let now = Instant::now();
+        let key = cache_key("select * from cpu -- cached");
+
+        let (_, first) = cached_sql_batches(key.clone(), ttl, now, run)
+            .unwrap()
+            .unwrap();
+        let (batches, second) =
+            cached_sql_batches(key.clone(), ttl, now + Duration::from_secs(1), run)
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(runs.get(), 1);
+        assert!(!first.cached);
+        assert!(second.cached);
+        assert_eq!(num_rows(&batches), 2);
+
+        cached_sql_batches(cache_key("select 1"), ttl, now, run)
+            .unwrap()
+            .unwrap();
+        assert_eq!(runs.get(), 2);
+
+        // another token may not be allowed to read the results
+        let (addr, _, namespace, sql) = key.clone();
+        let with_token = (addr, token_hash(Some("s3cr3t")), namespace, sql);
+        cached_sql_batches(with_token, ttl, now, run)
+            .unwrap()
+            .unwrap();
+        assert_eq!(runs.get(), 3);
+
+        cached_sql_batches(key, ttl, now + ttl, run)
+            .unwrap()
+            .unwrap();
+        assert_eq!(runs.get(), 4);
+    }
+
+    #[test]
+    fn cache_is_not_locked_while_the_query_runs() {
+        let ttl = Duration::from_secs(60);
+        let now = Instant::now();
+        let run = || {
+            let batches = vec![rows_batch(1)];
+            let stats = QueryStats::new(&batches, Duration::from_millis(1));
+            Ok(Ok((batches, stats)))
+        };
+
+        // with the lock held the inner query would wait for the outer forever
+        let result = cached_sql_batches(cache_key("select 1 -- outer"), ttl, now, || {
+            cached_sql_batches(cache_key("select 1 -- inner"), ttl, now, run)
+        });
+
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[test]
+    fn full_cache_drops_the_result_expiring_first() {
+        let now = Instant::now();
+        let stats = QueryStats::new(&[], Duration::ZERO);
+        let mut cache: HashMap<QueryCacheKey, CachedQuery> = HashMap::new();
+        for (sql, expires_in) in [("select 1", 30), ("select 2", 10), ("select 3", 20)] {
+            let expires = now + Duration::from_secs(expires_in);
+            cache.insert(cache_key(sql), (expires, vec![], stats.clone()));
+        }
+
+        make_room(&mut cache, 3);
+
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains_key(&cache_key("select 2")));
+
+        make_room(&mut cache, 3);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn failed_query_is_not_cached() {
+        let runs = std::cell::Cell::new(0);
+        let run = || {
+            runs.set(runs.get() + 1);
+            Ok(Err(Error::NoDatabaseSelected))
+        };
+        let ttl = Duration::from_secs(60);
+        let key = cache_key("select * from cpu -- failing");
+
+        assert!(cached_sql_batches(key.clone(), ttl, Instant::now(), run)
+            .unwrap()
+            .is_err());
+        assert!(cached_sql_batches(key, ttl, Instant::now(), run)
+            .unwrap()
+            .is_err());
+        assert_eq!(runs.get(), 2);
+    }
+
+    #[test]
+    fn watch_runs_the_query_every_interval() {
+        let mut runs = 0;
+        let results: Vec<Value> = watch_results(Duration::from_millis(1), None, || {
+            runs += 1;
+            Ok(Value::test_int(runs))
+        })
+        .take(3)
+        .collect();
+
+        assert_eq!(
+            results,
+            vec![Value::test_int(1), Value::test_int(2), Value::test_int(3)]
+        );
+    }
+
+    #[test]
+    fn watch_stops_on_ctrlc_and_on_errors() {
+        let ctrlc = Arc::new(AtomicBool::new(false));
+        let flag = ctrlc.clone();
+        let mut runs = 0;
+        let results: Vec<Value> = watch_results(Duration::from_millis(1), Some(ctrlc), || {
+            runs += 1;
+            if runs == 2 {
+                flag.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+            Ok(Value::test_int(runs))
+        })
+        .collect();
+        assert_eq!(results, vec![Value::test_int(1), Value::test_int(2)]);
+
+        let mut failing = watch_results(Duration::from_millis(1), None, || {
+            Err(ShellError::IOError("server went away".into()))
+        });
+        assert!(matches!(failing.next(), Some(Value::Error { .. })));
+        assert!(failing.next().is_none());
+    }
+
+    #[test]
+    fn watch_interval_must_be_positive() {
+        let duration = |val| {
+            Some(Value::Duration {
+                val,
+                span: Span::test_data(),
+            })
+        };
+
+        assert_eq!(watch_interval_from_value(None).unwrap(), None);
+        assert_eq!(
+            watch_interval_from_value(duration(2_000_000_000)).unwrap(),
+            Some(Duration::from_secs(2))
+        );
+        assert!(watch_interval_from_value(duration(0)).is_err());
+    }
+
+    #[test]
+    fn csv_output_file_has_the_formatted_rows() {
+        let path = std::env::temp_dir().join("nu_iox_output_file.csv");
+        let output_file = Spanned {
+            item: path.to_string_lossy().to_string(),
+            span: Span::test_data(),
+        };
+        let batches = vec![rows_batch(2), rows_batch(1)];
+
+        let csv = results_to_text(SqlFormat::Csv, &batches, Span::test_data()).unwrap();
+        write_text(&output_file, &csv).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(contents, "user\n0\n1\n0\n");
+        assert_eq!(row_count(&batches), 3);
+    }
+
+    #[test]
+    fn raw_output_is_the_text_the_table_is_parsed_from() {
+        let span = Span::test_data();
+        let text = results_to_text(SqlFormat::Csv, &[rows_batch(2)], span).unwrap();
+
+        let raw = raw_result(Ok(text.clone()), &Call::new(span)).unwrap();
+        assert_eq!(raw, Value::test_string("user\n0\n1\n"));
+
+        let table = from_delimited_data(
+            false,
+            false,
+            ',',
+            csv::Trim::None,
+            PipelineData::Value(raw, None),
+            span,
+            &nu_protocol::Config::default(),
+        )
+        .unwrap()
+        .into_value(span);
+        let formatted = from_delimited_data(
+            false,
+            false,
+            ',',
+            csv::Trim::None,
+            PipelineData::Value(Value::test_string(text), None),
+            span,
+            &nu_protocol::Config::default(),
+        )
+        .unwrap()
+        .into_value(span);
+
+        assert_eq!(table, formatted);
+        match table {
+            Value::List { vals, .. } => assert_eq!(vals.len(), 2),
+            _ => panic!("expected a table"),
+        }
+    }
+
+    #[test]
+    fn raw_output_still_reports_a_failed_query() {
+        // 16 is unauthenticated
+        let (addr, _requests) = mock_grpc_server("16");
+        let connector = iox_connector(&addr, None, None, Span::test_data()).unwrap();
+
+        let sql_result = tokio_block_sql(
+            connector,
+            &addr,
+            &RemoteDatabase::new("bananas".to_string(), None),
+            &spanned("select * from cpu"),
+            &test_options(),
+        )
+        .unwrap();
+
+        assert!(raw_result(sql_result, &Call::new(Span::test_data())).is_err());
+    }
+
+    #[test]
+    fn plain_pretty_output_has_no_ansi_codes() {
+        let span = Span::test_data();
+
+        let pretty = pretty_text(&[rows_batch(2)], false, span).unwrap();
+        assert!(pretty.contains("| user |"));
+        assert!(!pretty.contains('\x1b'));
+
+        assert_eq!(strip_colors("\x1b[1;31mcpu\x1b[0m | 955"), "cpu | 955");
+    }
+
+    #[test]
+    fn output_file_needs_a_text_format() {
+        assert!(results_to_text(SqlFormat::Arrow, &[rows_batch(1)], Span::test_data()).is_err());
+    }
+
+    fn params(vals: &[&str]) -> HashMap<String, String> {
+        sql_params_from_value(Some(Value::List {
+            vals: vals.iter().map(|v| Value::test_string(*v)).collect(),
+            span: Span::test_data(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn numeric_params_are_substituted_as_is() {
+        let sql = spanned("select * from cpu where user > $min and usage < $max limit $min");
+
+        let sql = substitute_sql_params(&sql, &params(&["min=10", "max=0.5"])).unwrap();
+
+        assert_eq!(
+            sql.item,
+            "select * from cpu where user > 10 and usage < 0.5 limit 10"
+        );
+    }
+
+    #[test]
+    fn string_params_are_quoted_and_escaped() {
+        let sql = spanned("select * from cpu where host = $host and region = '$region'");
+
+        let sql = substitute_sql_params(&sql, &params(&["host=a' or '1'='1"])).unwrap();
+
+        assert_eq!(
+            sql.item,
+            "select * from cpu where host = 'a'' or ''1''=''1' and region = '$region'"
+        );
+    }
+
+    #[test]
+    fn record_params_keep_their_type() {
+        let span = Span::test_data();
+        let params = sql_params_from_value(Some(Value::Record {
+            cols: vec!["user".into(), "host".into(), "gone".into()],
+            vals: vec![
+                Value::Int { val: 955, span },
+                Value::test_string("955"),
+                Value::Nothing { span },
+            ],
+            span,
+        }))
+        .unwrap();
+
+        let sql = substitute_sql_params(&spanned("$user $host $gone"), &params).unwrap();
+
+        assert_eq!(sql.item, "955 '955' NULL");
+    }
+
+    #[test]
+    fn missing_params_are_an_error() {
+        let sql = spanned("select * from cpu where host = $host and user > $min or $host is null");
+
+        match substitute_sql_params(&sql, &params(&["min=1"])) {
+            Err(ShellError::GenericError(_, msg, ..)) => assert_eq!(msg, "no param for $host"),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn param_names_must_be_identifiers() {
+        let param = |p| sql_params_from_value(Some(Value::test_string(p)));
+
+        assert!(param("no_equals").is_err());
+        assert!(param("1st=a").is_err());
+        assert!(param("host name=a").is_err());
+    }
+
+    fn hosts_batch(hosts: Vec<&str>) -> RecordBatch {
+        use arrow::array::{ArrayRef, Int64Array, StringArray};
+
+        let user: Int64Array = (0..hosts.len() as i64).collect::<Vec<i64>>().into();
+        RecordBatch::try_from_iter(vec![
+            ("host", Arc::new(StringArray::from(hosts)) as ArrayRef),
+            ("user", Arc::new(user) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    fn index_hosts(hosts: Vec<&str>, allow_dup: bool) -> Result<Value, ShellError> {
+        let batches = vec![hosts_batch(hosts)];
+        let rows = batches_to_value(&batches, Span::test_data()).unwrap();
+
+        index_rows_by(rows, &spanned("host"), allow_dup, Span::test_data())
+    }
+
+    #[test]
+    fn index_by_keys_the_rows_by_the_column() {
+        let span = Span::test_data();
+
+        let indexed = index_hosts(vec!["a", "b"], false).unwrap();
+
+        match &indexed {
+            Value::Record { cols, .. } => assert_eq!(cols, &["a", "b"]),
+            other => panic!("expected a record, got {:?}", other),
+        }
+        assert_eq!(
+            indexed
+                .get_data_by_key("b")
+                .and_then(|row| row.get_data_by_key("user")),
+            Some(Value::Int { val: 1, span })
+        );
+    }
+
+    #[test]
+    fn duplicate_index_keys_fail_unless_allowed() {
+        let span = Span::test_data();
+
+        match index_hosts(vec!["a", "b", "a"], false) {
+            Err(ShellError::GenericError(_, msg, ..)) => {
+                assert_eq!(msg, "'a' appears more than once in column 'host'")
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+
+        let indexed = index_hosts(vec!["a", "b", "a"], true).unwrap();
+        assert_eq!(
+            indexed
+                .get_data_by_key("a")
+                .and_then(|row| row.get_data_by_key("user")),
+            Some(Value::Int { val: 2, span })
+        );
+    }
+
+    #[test]
+    fn index_column_must_be_in_the_schema() {
+        let batches = vec![hosts_batch(vec!["a"])];
+
+        assert!(validate_index_column(&batches, &spanned("host")).is_ok());
+        assert!(validate_index_column(&batches, &spanned("region")).is_err());
+        assert!(validate_index_column(&[], &spanned("region")).is_ok());
+    }
+
+    fn sort_key(column: &str, descending: bool) -> SortKey {
+        SortKey {
+            column: spanned(column),
+            descending,
+        }
+    }
+
+    fn int_column(batch: &RecordBatch, name: &str) -> Vec<Option<i64>> {
+        use arrow::array::Int64Array;
+
+        let column = batch.column(batch.schema().index_of(name).unwrap());
+        column
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .iter()
+            .collect()
+    }
+
+    fn string_column(batch: &RecordBatch, name: &str) -> Vec<String> {
+        use arrow::array::StringArray;
+
+        let column = batch.column(batch.schema().index_of(name).unwrap());
+        column
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .iter()
+            .map(|value| value.unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sort_compares_numbers_as_numbers_across_batches() {
+        use arrow::array::{ArrayRef, Int64Array};
+
+        let batch = |users: Vec<Option<i64>>| {
+            RecordBatch::try_from_iter(vec![(
+                "user",
+                Arc::new(Int64Array::from(users)) as ArrayRef,
+            )])
+            .unwrap()
+        };
+        let batches = vec![batch(vec![Some(10), None]), batch(vec![Some(9), Some(100)])];
+
+        let ascending = sort_batches(batches.clone(), &[sort_key("user", false)]).unwrap();
+        let descending = sort_batches(batches, &[sort_key("user", true)]).unwrap();
+
+        assert_eq!(ascending.len(), 1);
+        assert_eq!(
+            int_column(&ascending[0], "user"),
+            vec![Some(9), Some(10), Some(100), None]
+        );
+        assert_eq!(
+            int_column(&descending[0], "user"),
+            vec![None, Some(100), Some(10), Some(9)]
+        );
+    }
+
+    #[test]
+    fn sort_by_string_then_number() {
+        let batches = vec![hosts_batch(vec!["b", "a"]), hosts_batch(vec!["B", "b"])];
+
+        let sorted =
+            sort_batches(batches, &[sort_key("host", false), sort_key("user", true)]).unwrap();
+
+        assert_eq!(string_column(&sorted[0], "host"), vec!["B", "a", "b", "b"]);
+        assert_eq!(
+            int_column(&sorted[0], "user"),
+            vec![Some(0), Some(1), Some(1), Some(0)]
+        );
+        assert!(sort_batches(vec![hosts_batch(vec!["a"])], &[sort_key("region", false)]).is_err());
+    }
+
+    #[test]
+    fn sort_keys_take_an_optional_order() {
+        let keys = sort_keys_from_value(Some(Value::List {
+            vals: vec![
+                Value::test_string("host"),
+                Value::test_string("user:DESC"),
+                Value::test_string("time:asc"),
+                Value::test_string("a:b"),
+            ],
+            span: Span::test_data(),
+        }))
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            keys,
+            vec![
+                sort_key("host", false),
+                sort_key("user", true),
+                sort_key("time", false),
+                sort_key("a:b", false),
+            ]
+        );
+        assert_eq!(
+            sort_keys_from_value(Some(Value::test_string("user:desc"))).unwrap(),
+            Some(vec![sort_key("user", true)])
+        );
+        assert_eq!(sort_keys_from_value(None).unwrap(), None);
+    }
+
+    #[test]
+    fn columns_project_the_batches_in_the_given_order() {
+        let batches = vec![hosts_batch(vec!["a", "b"]), hosts_batch(vec!["c"])];
+
+        let projected = project_batches(batches, &[spanned("user"), spanned("host")]).unwrap();
+
+        assert_eq!(projected.len(), 2);
+        assert_eq!(num_rows(&projected), 3);
+        let names: Vec<String> = projected[0]
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(names, vec!["user", "host"]);
+
+        let only_host = project_batches(projected, &[spanned("host")]).unwrap();
+        assert_eq!(only_host[1].num_columns(), 1);
+    }
+
+    #[test]
+    fn missing_projected_column_is_an_error_at_its_span() {
+        let batches = vec![hosts_batch(vec!["a"])];
+        let region = Spanned {
+            item: "region".to_string(),
+            span: Span::new(5, 11),
+        };
+
+        match project_batches(batches, &[spanned("host"), region]) {
+            Err(ShellError::UnsupportedInput(msg, span)) => {
+                assert_eq!(
+                    msg,
+                    "column 'region' is not in the results, the columns are host, user"
+                );
+                assert_eq!(span, Span::new(5, 11));
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+        assert!(project_batches(Vec::new(), &[spanned("region")])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn format_rejects_unknown_values() {
+        assert!(sql_format_from_str(Some(Value::test_string("xml"))).is_err());
+    }
 }
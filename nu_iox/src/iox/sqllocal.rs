@@ -0,0 +1,87 @@
+use super::convert::batches_to_value;
+use super::nuclient::{run_local_sql, LocalTable};
+use super::util::block_on_iox;
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Spanned, SyntaxShape,
+};
+use std::path::PathBuf;
+
+#[derive(Clone)]
+pub struct Ioxsqllocal;
+
+impl Command for Ioxsqllocal {
+    fn name(&self) -> &str {
+        "ioxsqllocal"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxsqllocal")
+            .required(
+                "query",
+                SyntaxShape::String,
+                "SQL to execute against the local files",
+            )
+            .rest(
+                "files",
+                SyntaxShape::Filepath,
+                "parquet or csv files to query, each is a table named after its file stem",
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "Sql query against local parquet or csv files without an Iox server."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let sql: Spanned<String> = call.req(engine_state, stack, 0)?;
+        let files: Vec<Spanned<String>> = call.rest(engine_state, stack, 1)?;
+
+        if files.is_empty() {
+            return Err(ShellError::MissingParameter("files".into(), call.head));
+        }
+
+        let tables: Vec<LocalTable> = files
+            .into_iter()
+            .map(|file| LocalTable::from_path(PathBuf::from(file.item)))
+            .collect();
+
+        let batches = block_on_iox(run_local_sql(&tables, &sql.item))
+            .map_err(|e| ShellError::IOError(e.to_string()))?
+            .map_err(|e| {
+                ShellError::GenericError(
+                    "Local query failed".into(),
+                    e.to_string(),
+                    Some(sql.span),
+                    None,
+                    Vec::new(),
+                )
+            })?;
+
+        Ok(batches_to_value(&batches, call.head)?.into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Run an sql query against a local parquet file",
+                example: r#"ioxsqllocal "select * from cpu" ./cpu.parquet"#,
+                result: None,
+            },
+            Example {
+                description: "Join two local files",
+                example: r#"ioxsqllocal "select * from cpu join mem on cpu.host = mem.host" cpu.parquet mem.csv"#,
+                result: None,
+            },
+        ]
+    }
+}
@@ -0,0 +1,285 @@
+use super::convert::array_value_to_nu_value;
+use super::schema::{run_catalog_query, with_catalog_flags};
+use arrow::record_batch::RecordBatch;
+use nu_engine::CallExt;
+use nu_glob::Pattern;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape,
+    Value,
+};
+use regex::Regex;
+
+#[derive(Clone)]
+pub struct Ioxtables;
+
+impl Command for Ioxtables {
+    fn name(&self) -> &str {
+        "ioxtables"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        with_catalog_flags(
+            Signature::build("ioxtables")
+                .named(
+                    "pattern",
+                    SyntaxShape::String,
+                    "only list the tables whose name matches this glob, e.g. cpu*",
+                    Some('p'),
+                )
+                .named(
+                    "filter",
+                    SyntaxShape::String,
+                    "only list the tables whose name matches this glob, or this regex between slashes, e.g. /^cpu_\\d+$/",
+                    None,
+                ),
+        )
+    }
+
+    fn usage(&self) -> &str {
+        "List the table names of an Iox Database."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Reads information_schema.tables of the database and returns a list of names, sorted by name."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let filter = name_filter_from_flags(
+            call.get_flag(engine_state, stack, "pattern")?,
+            call.get_flag(engine_state, stack, "filter")?,
+        )?;
+
+        let batches = run_catalog_query(engine_state, stack, call, tables_sql())?;
+        let names = table_names(&batches, filter.as_ref(), call.head)?;
+
+        Ok(Value::List {
+            vals: names,
+            span: call.head,
+        }
+        .into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "List the tables of the default database",
+                example: r#"ioxtables"#,
+                result: None,
+            },
+            Example {
+                description: "List the cpu tables of the bananas database",
+                example: r#"ioxtables -d bananas --pattern 'cpu*'"#,
+                result: None,
+            },
+            Example {
+                description: "List the tables named cpu_ and a number, with a regex",
+                example: r#"ioxtables --filter '/^cpu_\d+$/'"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+/// The information schema query behind `ioxtables`
+pub fn tables_sql() -> String {
+    "SELECT table_name FROM information_schema.tables \
+    WHERE table_schema = 'iox' ORDER BY table_name"
+        .to_string()
+}
+
+/// Parses the --pattern glob, pointing a bad glob at the flag
+pub fn pattern_from_flag(pattern: Spanned<String>) -> Result<Pattern, ShellError> {
+    Pattern::new(&pattern.item).map_err(|e| {
+        ShellError::GenericError(
+            e.msg.to_string(),
+            "glob error".to_string(),
+            Some(pattern.span),
+            None,
+            Vec::new(),
+        )
+    })
+}
+
+/// The glob or regex that --pattern and --filter narrow the names with
+#[derive(Debug)]
+pub enum NameFilter {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+impl NameFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            NameFilter::Glob(pattern) => pattern.matches(name),
+            NameFilter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Parses the --filter flag, a regex when it is between slashes like in
+/// InfluxQL and a glob otherwise
+pub fn filter_from_flag(filter: Spanned<String>) -> Result<NameFilter, ShellError> {
+    let regex = filter
+        .item
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix('/'));
+
+    match regex {
+        Some(regex) => Regex::new(regex).map(NameFilter::Regex).map_err(|e| {
+            ShellError::GenericError(
+                "Invalid regex".to_string(),
+                e.to_string(),
+                Some(filter.span),
+                Some("Write the regex between slashes, e.g. /^cpu_\\d+$/".to_string()),
+                Vec::new(),
+            )
+        }),
+        None => pattern_from_flag(filter).map(NameFilter::Glob),
+    }
+}
+
+/// Picks the filter from --pattern or --filter, which can't both be given
+pub fn name_filter_from_flags(
+    pattern: Option<Spanned<String>>,
+    filter: Option<Spanned<String>>,
+) -> Result<Option<NameFilter>, ShellError> {
+    match (pattern, filter) {
+        (Some(_), Some(filter)) => Err(ShellError::IncompatibleParametersSingle(
+            "use either --pattern or --filter".into(),
+            filter.span,
+        )),
+        (Some(pattern), None) => pattern_from_flag(pattern).map(|p| Some(NameFilter::Glob(p))),
+        (None, Some(filter)) => filter_from_flag(filter).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Takes the names out of the first column of the batches, keeping the
+/// ones the filter matches
+pub fn table_names(
+    batches: &[RecordBatch],
+    filter: Option<&NameFilter>,
+    span: Span,
+) -> Result<Vec<Value>, ShellError> {
+    let mut names = Vec::new();
+
+    for batch in batches.iter().filter(|b| b.num_columns() > 0) {
+        let column = batch.column(0);
+        for row in 0..batch.num_rows() {
+            if let Value::String { val, span } = array_value_to_nu_value(column, row, span)? {
+                if filter.map_or(true, |f| f.matches(&val)) {
+                    names.push(Value::String { val, span });
+                }
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::{ArrayRef, StringArray};
+    use std::sync::Arc;
+
+    fn names_batch(names: Vec<&str>) -> RecordBatch {
+        RecordBatch::try_from_iter(vec![(
+            "table_name",
+            Arc::new(StringArray::from(names)) as ArrayRef,
+        )])
+        .unwrap()
+    }
+
+    fn spanned(item: &str) -> Spanned<String> {
+        Spanned {
+            item: item.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    fn glob(pattern: &str) -> NameFilter {
+        NameFilter::Glob(pattern_from_flag(spanned(pattern)).unwrap())
+    }
+
+    #[test]
+    fn table_names_are_strings_from_every_batch() {
+        let batches = vec![names_batch(vec!["cpu", "disk"]), names_batch(vec!["mem"])];
+
+        let names = table_names(&batches, None, Span::test_data()).unwrap();
+
+        assert_eq!(
+            names,
+            vec![
+                Value::test_string("cpu"),
+                Value::test_string("disk"),
+                Value::test_string("mem"),
+            ]
+        );
+    }
+
+    #[test]
+    fn pattern_filters_names() {
+        let batches = vec![names_batch(vec!["cpu", "cpu_total", "disk"])];
+
+        let names = table_names(&batches, Some(&glob("cpu*")), Span::test_data()).unwrap();
+
+        assert_eq!(
+            names,
+            vec![Value::test_string("cpu"), Value::test_string("cpu_total")]
+        );
+    }
+
+    #[test]
+    fn bad_pattern_is_an_error() {
+        let pattern = Spanned {
+            item: "cpu[".to_string(),
+            span: Span::test_data(),
+        };
+
+        assert!(matches!(
+            pattern_from_flag(pattern),
+            Err(ShellError::GenericError(..))
+        ));
+    }
+
+    #[test]
+    fn filter_is_a_glob_or_a_regex() {
+        let batches = vec![names_batch(vec!["cpu", "cpu_1", "cpu_total", "disk"])];
+        let names = |filter: &str| {
+            let filter = filter_from_flag(spanned(filter)).unwrap();
+            table_names(&batches, Some(&filter), Span::test_data()).unwrap()
+        };
+
+        assert_eq!(
+            names("cpu_*"),
+            vec![Value::test_string("cpu_1"), Value::test_string("cpu_total")]
+        );
+        assert_eq!(names("/^cpu_\\d+$/"), vec![Value::test_string("cpu_1")]);
+        assert_eq!(
+            names("/isk|^cpu$/"),
+            vec![Value::test_string("cpu"), Value::test_string("disk")]
+        );
+    }
+
+    #[test]
+    fn bad_regex_is_an_error() {
+        match filter_from_flag(spanned("/cpu(/")) {
+            Err(ShellError::GenericError(title, ..)) => assert_eq!(title, "Invalid regex"),
+            other => panic!("expected an invalid regex error, got {:?}", other),
+        }
+
+        assert!(matches!(
+            name_filter_from_flags(Some(spanned("cpu*")), Some(spanned("/cpu/"))),
+            Err(ShellError::IncompatibleParametersSingle(..))
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+use super::schema::{
+    catalog_columns, run_catalog_query, schema_sql, with_catalog_flags, CatalogColumn,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct Ioxtagkeys;
+
+impl Command for Ioxtagkeys {
+    fn name(&self) -> &str {
+        "ioxtagkeys"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        with_catalog_flags(Signature::build("ioxtagkeys").optional(
+            "measurement",
+            SyntaxShape::String,
+            "only list the tag keys of this measurement",
+        ))
+    }
+
+    fn usage(&self) -> &str {
+        "List the tag keys of an Iox Database, like SHOW TAG KEYS."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Reads information_schema.columns of the database, the tags are the dictionary encoded columns. One row per measurement and tag key."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let measurement: Option<String> = call.opt(engine_state, stack, 0)?;
+        let batches = run_catalog_query(
+            engine_state,
+            stack,
+            call,
+            schema_sql(measurement.as_deref()),
+        )?;
+
+        Ok(
+            tag_keys_to_value(&catalog_columns(&batches, call.head)?, call.head)
+                .into_pipeline_data(),
+        )
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "List the tag keys of every measurement",
+                example: r#"ioxtagkeys"#,
+                result: None,
+            },
+            Example {
+                description: "List the tag keys of the cpu measurement in the bananas database",
+                example: r#"ioxtagkeys -d bananas cpu"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+/// The tag columns as a table of measurement and tag_key
+pub fn tag_keys_to_value(columns: &[CatalogColumn], span: Span) -> Value {
+    let vals = columns
+        .iter()
+        .filter(|column| column.is_tag())
+        .map(|column| Value::Record {
+            cols: vec!["measurement".to_string(), "tag_key".to_string()],
+            vals: vec![
+                Value::String {
+                    val: column.table_name.clone(),
+                    span,
+                },
+                Value::String {
+                    val: column.column_name.clone(),
+                    span,
+                },
+            ],
+            span,
+        })
+        .collect();
+
+    Value::List { vals, span }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::iox::schema::TAG_DATA_TYPE;
+
+    fn column(table_name: &str, column_name: &str, data_type: &str) -> CatalogColumn {
+        CatalogColumn {
+            table_name: table_name.to_string(),
+            column_name: column_name.to_string(),
+            data_type: data_type.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_dictionary_columns_are_tag_keys() {
+        let span = Span::test_data();
+        let columns = vec![
+            column("cpu", "host", TAG_DATA_TYPE),
+            column("cpu", "region", TAG_DATA_TYPE),
+            column("cpu", "time", "Timestamp(Nanosecond, None)"),
+            column("cpu", "usage_user", "Float64"),
+            column("mem", "host", TAG_DATA_TYPE),
+        ];
+
+        let tag_keys = match tag_keys_to_value(&columns, span) {
+            Value::List { vals, .. } => vals,
+            _ => panic!("expected a list"),
+        };
+
+        assert_eq!(tag_keys.len(), 3);
+        assert_eq!(
+            tag_keys[1],
+            Value::Record {
+                cols: vec!["measurement".to_string(), "tag_key".to_string()],
+                vals: vec![Value::test_string("cpu"), Value::test_string("region")],
+                span,
+            }
+        );
+        assert_eq!(
+            tag_keys[2].get_data_by_key("measurement"),
+            Some(Value::test_string("mem"))
+        );
+    }
+}
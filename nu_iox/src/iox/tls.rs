@@ -0,0 +1,142 @@
+use hyper::client::HttpConnector;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use nu_protocol::{ShellError, Span, Spanned};
+use rustls::{Certificate, ClientConfig, RootCertStore};
+use std::fs::File;
+use std::io::BufReader;
+
+/// Connector used for https Iox addresses
+pub type IoxTlsConnector = HttpsConnector<HttpConnector>;
+
+/// Builds the TLS configuration for an Iox address
+///
+/// Plain http addresses need no TLS and return None. For https the server
+/// certificate is checked against the PEM file given with --tls-ca, or
+/// against the system roots when no file was given.
+pub fn iox_tls_config(
+    addr: &str,
+    tls_ca: Option<&Spanned<String>>,
+    span: Span,
+) -> Result<Option<ClientConfig>, ShellError> {
+    let https = addr.starts_with("https://");
+
+    let roots = match (https, tls_ca) {
+        (false, None) => return Ok(None),
+        (false, Some(tls_ca)) => {
+            return Err(ShellError::UnsupportedInput(
+                "--tls-ca needs an https:// Iox address".into(),
+                tls_ca.span,
+            ))
+        }
+        (true, Some(tls_ca)) => custom_roots(tls_ca)?,
+        (true, None) => system_roots(span)?,
+    };
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Some(config))
+}
+
+/// Wraps the TCP connector in TLS using the configuration from iox_tls_config
+pub fn iox_tls_connector(config: ClientConfig, mut http: HttpConnector) -> IoxTlsConnector {
+    // the https scheme is checked by the TLS layer instead
+    http.enforce_http(false);
+
+    HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_only()
+        .enable_http2()
+        .wrap_connector(http)
+}
+
+fn custom_roots(tls_ca: &Spanned<String>) -> Result<RootCertStore, ShellError> {
+    let file = File::open(&tls_ca.item)
+        .map_err(|e| ShellError::ReadingFile(e.to_string(), tls_ca.span))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| ShellError::ReadingFile(e.to_string(), tls_ca.span))?;
+
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(&certs);
+
+    if added == 0 {
+        return Err(ShellError::UnsupportedInput(
+            format!("no PEM certificates found in '{}'", tls_ca.item),
+            tls_ca.span,
+        ));
+    }
+
+    Ok(roots)
+}
+
+fn system_roots(span: Span) -> Result<RootCertStore, ShellError> {
+    let mut roots = RootCertStore::empty();
+
+    // unreadable system certificates are skipped, we only fail if none are left
+    if let Ok(certs) = rustls_native_certs::load_native_certs() {
+        for cert in certs {
+            let _ = roots.add(&Certificate(cert.0));
+        }
+    }
+
+    if roots.is_empty() {
+        return Err(ShellError::GenericError(
+            "No TLS root certificates available".into(),
+            "cannot verify the https Iox server".into(),
+            Some(span),
+            Some("Pass the server's certificate authority with --tls-ca".into()),
+            Vec::new(),
+        ));
+    }
+
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spanned(item: &str) -> Spanned<String> {
+        Spanned {
+            item: item.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn plain_http_needs_no_connector() {
+        let connector = iox_tls_config("http://127.0.0.1:8082", None, Span::test_data());
+
+        assert!(matches!(connector, Ok(None)));
+    }
+
+    #[test]
+    fn rejects_tls_ca_for_plain_http() {
+        let tls_ca = spanned("ca.pem");
+        let connector = iox_tls_config("http://127.0.0.1:8082", Some(&tls_ca), Span::test_data());
+
+        assert!(matches!(connector, Err(ShellError::UnsupportedInput(..))));
+    }
+
+    #[test]
+    fn reports_missing_ca_file() {
+        let tls_ca = spanned("/this/ca/does/not/exist.pem");
+        let connector = iox_tls_config("https://127.0.0.1:8082", Some(&tls_ca), Span::test_data());
+
+        assert!(matches!(connector, Err(ShellError::ReadingFile(..))));
+    }
+
+    #[test]
+    fn rejects_ca_file_without_certificates() {
+        let path = std::env::temp_dir().join("nu_iox_empty_ca.pem");
+        std::fs::write(&path, "not a certificate").unwrap();
+
+        let tls_ca = spanned(&path.to_string_lossy());
+        let connector = iox_tls_config("https://127.0.0.1:8082", Some(&tls_ca), Span::test_data());
+
+        assert!(matches!(connector, Err(ShellError::UnsupportedInput(..))));
+    }
+}
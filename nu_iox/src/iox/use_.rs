@@ -0,0 +1,132 @@
+use super::dbnames::dbname_shape;
+use super::namespace::tokio_block_namespace_names;
+use super::util::{
+    check_database_exists, iox_connector, resolve_iox_keepalive, resolve_iox_profile,
+    resolve_iox_tls_ca, validate_iox_addr,
+};
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, PipelineData, ShellError, Signature, Spanned, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct Ioxuse;
+
+impl Command for Ioxuse {
+    fn name(&self) -> &str {
+        "ioxuse"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxuse")
+            .required(
+                "dbname",
+                dbname_shape(),
+                "name of the database to use for subsequent Iox commands",
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "Select the Iox Database used when -d is not given."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "This sets IOX_DBNAME for the rest of the current scope, after checking the database exists."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let dbname: Spanned<String> = call.req(engine_state, stack, 0)?;
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr = profile.resolve_addr(stack, engine_state, None);
+        let addr = validate_iox_addr(&addr, call.head)?;
+
+        let token = profile.resolve_token(stack, engine_state, None);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+        let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+            .with_keepalive(keepalive);
+
+        let names = tokio_block_namespace_names(connector, &addr)?.map_err(|e| {
+            ShellError::GenericError(
+                "Failed to list the Iox databases".into(),
+                e.to_string(),
+                Some(call.head),
+                None,
+                Vec::new(),
+            )
+        })?;
+
+        check_database_exists(&dbname, &names)?;
+
+        stack.add_env_var(
+            "IOX_DBNAME".to_string(),
+            Value::String {
+                val: dbname.item.clone(),
+                span: dbname.span,
+            },
+        );
+
+        Ok(PipelineData::Value(
+            Value::String {
+                val: format!("You are now querying the database {}", dbname.item),
+                span: call.head,
+            },
+            None,
+        ))
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            description: "Use the bananas database for the following queries",
+            example: r#"ioxuse bananas; ioxsql "select * from cpu""#,
+            result: None,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nu_protocol::Span;
+
+    fn dbname(name: &str) -> Spanned<String> {
+        Spanned {
+            item: name.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn existing_database_is_accepted() {
+        let names = vec!["bananas".to_string(), "apples".to_string()];
+
+        assert!(check_database_exists(&dbname("apples"), &names).is_ok());
+    }
+
+    #[test]
+    fn missing_database_is_rejected() {
+        let names = vec!["bananas".to_string()];
+
+        assert!(check_database_exists(&dbname("typo"), &names).is_err());
+    }
+}
@@ -1,7 +1,225 @@
 use csv::ReaderBuilder;
+use http::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use hyper::client::HttpConnector;
+use influxdb_iox_client::connection::{
+    Builder as ConnectionBuilder, Connection, Error as ConnectionError,
+};
+use lazy_static::lazy_static;
 use nu_protocol::engine::{EngineState, Stack};
-use nu_protocol::ShellError;
-use tokio::runtime::{Builder, Runtime};
+use nu_protocol::{did_you_mean, ShellError, Span, Spanned, Value};
+use observability_deps::tracing::info;
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use super::nuclient::{
+    Compression, ConnectTimeoutSnafu, ConnectingSnafu, Error as NuclientError, RemoteDatabase,
+    DEFAULT_RETRIES,
+};
+use super::tls::{iox_tls_config, iox_tls_connector};
+
+/// Address of the Iox querier used when neither a flag nor IOX_ADDR is set
+///
+/// Builds can point it elsewhere by setting NU_IOX_DEFAULT_ADDR at compile time.
+pub const DEFAULT_IOX_ADDR: &str = match option_env!("NU_IOX_DEFAULT_ADDR") {
+    Some(addr) => addr,
+    None => "http://127.0.0.1:8082",
+};
+
+/// Address of the Iox router used by the write commands when neither a flag
+/// nor IOX_WRITE_ADDR is set, overridable with NU_IOX_DEFAULT_WRITE_ADDR at
+/// compile time
+pub const DEFAULT_IOX_WRITE_ADDR: &str = match option_env!("NU_IOX_DEFAULT_WRITE_ADDR") {
+    Some(addr) => addr,
+    None => "http://127.0.0.1:8081",
+};
+
+/// How long connecting to an Iox server may take when --connect-timeout is not given
+///
+/// Kept short, a server that doesn't answer within it is usually unreachable
+/// and the query timeout only starts once the connection is up.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// TCP keepalive of Iox connections when IOX_KEEPALIVE_SECS is not set
+///
+/// Well below the 60 second idle timeout common to load balancers, so an
+/// idle connection in a long shell session is not dropped.
+pub const DEFAULT_IOX_KEEPALIVE: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    // Built on first use and then shared by every Iox command in the session
+    static ref IOX_RUNTIME: Result<Runtime, String> = get_runtime(None).map_err(|e| e.to_string());
+
+    // Config files already warned about, so a broken file is reported once per session
+    static ref WARNED_CONFIGS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+
+    // Connections opened on the shared runtime, reused by later commands to the same server
+    static ref IOX_CONNECTIONS: Mutex<HashMap<ConnectionKey, Connection>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Identifies the server, credentials and settings a cached connection was
+/// opened for
+///
+/// Only a hash of the token is kept, the token itself stays with the builder.
+/// The settings start out as the defaults of iox_connector.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConnectionKey {
+    addr: String,
+    token_hash: Option<u64>,
+    tls_ca: Option<String>,
+    keepalive: Option<Duration>,
+    connect_timeout: Option<Duration>,
+}
+
+impl ConnectionKey {
+    pub fn new(addr: &str, token: Option<&str>) -> Self {
+        Self {
+            addr: addr.to_string(),
+            token_hash: token_hash(token),
+            tls_ca: None,
+            keepalive: Some(DEFAULT_IOX_KEEPALIVE),
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+        }
+    }
+
+    /// Sets the certificate authority file the server was verified against
+    pub fn with_tls_ca(mut self, tls_ca: Option<&str>) -> Self {
+        self.tls_ca = tls_ca.map(str::to_string);
+        self
+    }
+}
+
+/// Hashes the token for cache keys, so the token itself is never kept in one
+pub fn token_hash(token: Option<&str>) -> Option<u64> {
+    token.map(|token| {
+        let mut hasher = DefaultHasher::new();
+        token.hash(&mut hasher);
+        hasher.finish()
+    })
+}
+
+/// Returns the cached connection for the key, connecting and caching it when there is none
+///
+/// A failed connect leaves the cache as it was.
+pub async fn cached_connection<C, E, F, Fut>(
+    cache: &Mutex<HashMap<ConnectionKey, C>>,
+    key: &ConnectionKey,
+    connect: F,
+) -> Result<C, E>
+where
+    C: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<C, E>>,
+{
+    let cached = cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(key)
+        .cloned();
+    if let Some(connection) = cached {
+        return Ok(connection);
+    }
+
+    // the lock isn't held while connecting, a racing command just connects too
+    let connection = connect().await?;
+    cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(key.clone(), connection.clone());
+
+    Ok(connection)
+}
+
+/// Drops every cached connection to addr, so the next command reconnects
+pub fn forget_iox_connection(addr: &str) {
+    forget_cached_connection(&IOX_CONNECTIONS, addr);
+}
+
+fn forget_cached_connection<C>(cache: &Mutex<HashMap<ConnectionKey, C>>, addr: &str) {
+    cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .retain(|key, _| key.addr != addr);
+}
+
+/// Passes the result through, forgetting the cached connections to addr when it failed
+pub fn forget_connection_on_error<T, E>(addr: &str, result: Result<T, E>) -> Result<T, E> {
+    if result.is_err() {
+        forget_iox_connection(addr);
+    }
+    result
+}
+
+/// Runs the future to completion on the shared Iox runtime
+///
+/// The shared runtime uses the default tokio scheduler, one worker thread
+/// per core. It must not be called from within an async context since tokio
+/// panics on a nested block_on, so that case is returned as an error instead.
+pub fn block_on_iox<F: Future>(future: F) -> Result<F::Output, std::io::Error> {
+    block_on_iox_with_threads(None, future)
+}
+
+/// Like block_on_iox, but honours a --num-threads request
+///
+/// The shared runtime is built once with the default worker count, so a
+/// command given --num-threads runs on its own runtime of that size which
+/// is dropped again when the command finishes.
+pub fn block_on_iox_with_threads<F: Future>(
+    num_threads: Option<usize>,
+    future: F,
+) -> Result<F::Output, std::io::Error> {
+    let kind = std::io::ErrorKind::Other;
+
+    if Handle::try_current().is_ok() {
+        let msg = "Cannot block on an Iox request from within an async runtime";
+        return Err(std::io::Error::new(kind, msg));
+    }
+
+    match num_threads {
+        None => {
+            let tokio_runtime = IOX_RUNTIME
+                .as_ref()
+                .map_err(|e| std::io::Error::new(kind, e.clone()))?;
+
+            Ok(tokio_runtime.block_on(future))
+        }
+        Some(num_threads) => Ok(get_runtime(Some(num_threads))?.block_on(future)),
+    }
+}
+
+/// Reads a --retries flag value, 0 turns retrying off
+pub fn retries_from_flag(retries: Option<Spanned<i64>>) -> Result<usize, ShellError> {
+    match retries {
+        None => Ok(DEFAULT_RETRIES),
+        Some(Spanned { item, span }) if item < 0 => Err(ShellError::UnsupportedInput(
+            format!("--retries must not be negative, got {}", item),
+            span,
+        )),
+        Some(Spanned { item, .. }) => Ok(item as usize),
+    }
+}
+
+/// Reads a --num-threads flag value, the worker count must be at least 1
+pub fn num_threads_from_flag(
+    num_threads: Option<Spanned<i64>>,
+) -> Result<Option<usize>, ShellError> {
+    match num_threads {
+        None => Ok(None),
+        Some(Spanned { item, span }) if item < 1 => Err(ShellError::UnsupportedInput(
+            format!("--num-threads must be at least 1, got {}", item),
+            span,
+        )),
+        Some(Spanned { item, .. }) => Ok(Some(item as usize)),
+    }
+}
 
 pub fn tokio_block02() -> Result<(), std::io::Error> {
     use influxdb_iox_client::{
@@ -15,7 +233,7 @@ pub fn tokio_block02() -> Result<(), std::io::Error> {
 
     tokio_runtime.block_on(async move {
         let connection = Builder::default()
-            .build("http://127.0.0.1:8082")
+            .build(DEFAULT_IOX_ADDR)
             .await
             .expect("client should be valid");
 
@@ -48,10 +266,7 @@ pub fn tokio_block01() -> Result<(), std::io::Error> {
 
     let tokio_runtime = get_runtime(num_threads)?;
     tokio_runtime.block_on(async move {
-        let connection = Builder::default()
-            .build("http://127.0.0.1:8082")
-            .await
-            .unwrap();
+        let connection = Builder::default().build(DEFAULT_IOX_ADDR).await.unwrap();
 
         let mut client = Client::new(connection);
 
@@ -76,52 +291,1631 @@ pub fn get_runtime(num_threads: Option<usize>) -> Result<Runtime, std::io::Error
     let kind = std::io::ErrorKind::Other;
     match num_threads {
         None => Runtime::new(),
-        Some(num_threads) => {
-            println!(
-                "Setting number of threads to '{}' per command line request",
-                num_threads
-            );
-
-            match num_threads {
-                0 => {
-                    let msg = format!(
-                        "Invalid num-threads: '{}' must be greater than zero",
-                        num_threads
-                    );
-                    Err(std::io::Error::new(kind, msg))
-                }
-                1 => Builder::new_current_thread().enable_all().build(),
-                _ => Builder::new_multi_thread()
-                    .enable_all()
-                    .worker_threads(num_threads)
-                    .build(),
+        Some(num_threads) => match num_threads {
+            0 => {
+                let msg = format!(
+                    "Invalid num-threads: '{}' must be greater than zero",
+                    num_threads
+                );
+                Err(std::io::Error::new(kind, msg))
             }
-        }
+            1 => Builder::new_current_thread().enable_all().build(),
+            _ => Builder::new_multi_thread()
+                .enable_all()
+                .worker_threads(num_threads)
+                .build(),
+        },
     }
 }
 
+/// Reads an environment variable that must be set, a missing or empty one
+/// is an error naming it at `span`
 pub fn get_env_var_from_engine(
     stack: &mut Stack,
     engine_state: &EngineState,
     env: &str,
+    span: Span,
 ) -> Result<String, ShellError> {
-    let result = stack
+    get_optional_env_var(stack, engine_state, env).ok_or_else(|| {
+        ShellError::GenericError(
+            format!("Environment variable {} is not set", env),
+            format!("{} is missing or empty", env),
+            Some(span),
+            Some(format!("Set it with let-env {} = ...", env)),
+            Vec::new(),
+        )
+    })
+}
+
+/// How long a query may run when --timeout is not given
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Reads a --timeout flag value, a zero duration disables the timeout
+pub fn timeout_from_value(
+    timeout: Option<Value>,
+    default: Duration,
+) -> Result<Option<Duration>, ShellError> {
+    match timeout {
+        None => Ok(Some(default)),
+        Some(Value::Duration { val, span }) => match val {
+            0 => Ok(None),
+            val if val < 0 => Err(ShellError::UnsupportedInput(
+                "the timeout must not be negative".into(),
+                span,
+            )),
+            val => Ok(Some(Duration::from_nanos(val as u64))),
+        },
+        Some(value) => Err(ShellError::CantConvert(
+            "duration".into(),
+            value.get_type().to_string(),
+            value.span()?,
+            None,
+        )),
+    }
+}
+
+/// Settings read from the nu_iox config file
+///
+/// Every key is optional, e.g. in `~/.config/nu_iox/config.toml`:
+///
+/// ```toml
+/// addr = "http://iox.example.com:8082"
+/// dbname = "bananas"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoxConfig {
+    pub addr: Option<String>,
+    pub dbname: Option<String>,
+    pub token: Option<String>,
+    pub format: Option<String>,
+}
+
+/// Where the config file is looked for, IOX_CONFIG wins over the default
+/// `nu_iox/config.toml` in the user's config directory
+pub fn iox_config_path(stack: &Stack, engine_state: &EngineState) -> Option<PathBuf> {
+    get_optional_env_var(stack, engine_state, "IOX_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| nu_path::config_dir().map(|dir| dir.join("nu_iox").join("config.toml")))
+}
+
+/// Loads the config file, a missing file gives empty settings
+///
+/// A file that can't be read or parsed is warned about on stderr and
+/// ignored, so a typo in it never stops a command from running.
+pub fn load_iox_config(stack: &Stack, engine_state: &EngineState) -> IoxConfig {
+    let path = match iox_config_path(stack, engine_state) {
+        Some(path) => path,
+        None => return IoxConfig::default(),
+    };
+
+    let parsed = match std::fs::read_to_string(&path) {
+        Ok(contents) => parse_iox_config(&path, &contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return IoxConfig::default(),
+        Err(e) => Err(e.to_string()),
+    };
+
+    parsed.unwrap_or_else(|e| {
+        let mut warned = WARNED_CONFIGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if warned.insert(path.clone()) {
+            eprintln!("nu_iox: ignoring config file {}: {}", path.display(), e);
+        }
+        IoxConfig::default()
+    })
+}
+
+/// Parses the config as json when the file ends in .json, as toml otherwise
+pub fn parse_iox_config(path: &Path, contents: &str) -> Result<IoxConfig, String> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => nu_json::from_str(contents).map_err(|e| e.to_string()),
+        _ => toml::from_str(contents).map_err(|e| e.to_string()),
+    }
+}
+
+/// Resolves the address of the Iox server to query
+///
+/// Precedence is the explicit flag, then IOX_ADDR, then the addr of the
+/// config file, then DEFAULT_IOX_ADDR
+pub fn resolve_iox_addr(stack: &Stack, engine_state: &EngineState, flag: Option<String>) -> String {
+    pick_iox_addr(
+        flag,
+        get_optional_env_var(stack, engine_state, "IOX_ADDR")
+            .or_else(|| load_iox_config(stack, engine_state).addr),
+        DEFAULT_IOX_ADDR,
+    )
+}
+
+/// Connection settings of a named profile
+///
+/// A profile is a set of environment variables named after it, e.g. for
+/// `--profile prod`: IOX_PROFILE_PROD_ADDR, IOX_PROFILE_PROD_WRITE_ADDR and
+/// IOX_PROFILE_PROD_TOKEN. Dashes in the name become underscores.
+///
+/// The precedence is the explicit flag, then the profile, then IOX_ADDR /
+/// IOX_WRITE_ADDR / IOX_TOKEN, then the defaults, see resolve_addr,
+/// resolve_write_addr and resolve_token.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IoxProfile {
+    pub addr: Option<String>,
+    pub write_addr: Option<String>,
+    pub token: Option<String>,
+}
+
+impl IoxProfile {
+    /// The address to query, --addr wins over the profile, which wins over
+    /// IOX_ADDR and the config
+    pub fn resolve_addr(
+        &self,
+        stack: &Stack,
+        engine_state: &EngineState,
+        flag: Option<String>,
+    ) -> String {
+        resolve_iox_addr(stack, engine_state, Self::pick(flag, &self.addr))
+    }
+
+    /// The address to write to, the flag wins over the profile, which wins
+    /// over IOX_WRITE_ADDR
+    pub fn resolve_write_addr(
+        &self,
+        stack: &Stack,
+        engine_state: &EngineState,
+        flag: Option<String>,
+    ) -> String {
+        resolve_iox_write_addr(stack, engine_state, Self::pick(flag, &self.write_addr))
+    }
+
+    /// The token, --token wins over the profile, which wins over IOX_TOKEN
+    /// and the config
+    pub fn resolve_token(
+        &self,
+        stack: &Stack,
+        engine_state: &EngineState,
+        flag: Option<String>,
+    ) -> Option<String> {
+        resolve_iox_token(stack, engine_state, Self::pick(flag, &self.token))
+    }
+
+    // An empty flag counts as unset, like an empty env var
+    fn pick(flag: Option<String>, profile: &Option<String>) -> Option<String> {
+        flag.filter(|v| !v.is_empty()).or_else(|| profile.clone())
+    }
+}
+
+/// Looks up the profile given with --profile, no profile gives empty settings
+pub fn resolve_iox_profile(
+    stack: &Stack,
+    engine_state: &EngineState,
+    profile: Option<Spanned<String>>,
+) -> Result<IoxProfile, ShellError> {
+    let profile = match profile {
+        None => return Ok(IoxProfile::default()),
+        Some(profile) => profile,
+    };
+
+    let prefix = format!(
+        "IOX_PROFILE_{}_",
+        profile.item.to_uppercase().replace('-', "_")
+    );
+    let lookup = |name: &str| get_optional_env_var(stack, engine_state, &(prefix.clone() + name));
+
+    let iox_profile = IoxProfile {
+        addr: lookup("ADDR"),
+        write_addr: lookup("WRITE_ADDR"),
+        token: lookup("TOKEN"),
+    };
+
+    if iox_profile == IoxProfile::default() {
+        return Err(ShellError::GenericError(
+            format!("Unknown Iox profile '{}'", profile.item),
+            "no settings found for this profile".into(),
+            Some(profile.span),
+            Some(format!("Set {}ADDR and/or {}TOKEN", prefix, prefix)),
+            Vec::new(),
+        ));
+    }
+
+    Ok(iox_profile)
+}
+
+/// Resolves the address of the Iox server to write to
+///
+/// Precedence is the explicit flag, then IOX_WRITE_ADDR, then DEFAULT_IOX_WRITE_ADDR
+pub fn resolve_iox_write_addr(
+    stack: &Stack,
+    engine_state: &EngineState,
+    flag: Option<String>,
+) -> String {
+    pick_iox_addr(
+        flag,
+        get_optional_env_var(stack, engine_state, "IOX_WRITE_ADDR"),
+        DEFAULT_IOX_WRITE_ADDR,
+    )
+}
+
+fn pick_iox_addr(flag: Option<String>, env: Option<String>, default: &str) -> String {
+    flag.filter(|v| !v.is_empty())
+        .or(env)
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Returns the environment variable as a string, treating an empty value as unset
+pub fn get_optional_env_var(
+    stack: &Stack,
+    engine_state: &EngineState,
+    env: &str,
+) -> Option<String> {
+    stack
         .get_env_var(engine_state, env)
-        .map(|v| v.as_string().unwrap_or_default())
-        .filter(|v| !v.is_empty());
+        .and_then(|v| v.as_string().ok())
+        .filter(|v| !v.is_empty())
+}
+
+/// Resolves the authorization token, the explicit flag wins over IOX_TOKEN,
+/// which wins over the token of the config file
+pub fn resolve_iox_token(
+    stack: &Stack,
+    engine_state: &EngineState,
+    flag: Option<String>,
+) -> Option<String> {
+    flag.filter(|v| !v.is_empty())
+        .or_else(|| get_optional_env_var(stack, engine_state, "IOX_TOKEN"))
+        .or_else(|| {
+            load_iox_config(stack, engine_state)
+                .token
+                .filter(|v| !v.is_empty())
+        })
+}
+
+/// Formats IOX_FORMAT may name, those of ioxsql --format
+pub const IOX_FORMATS: &[&str] = &["arrow", "csv", "json", "ndjson", "pretty"];
+
+/// Resolves the --format value, the explicit flag wins over IOX_FORMAT,
+/// which wins over the format of the config file
+///
+/// An IOX_FORMAT naming no format is ignored with a warning, the way an
+/// invalid config file is, so a typo in the shell's env doesn't break
+/// every query.
+pub fn resolve_iox_format(
+    stack: &Stack,
+    engine_state: &EngineState,
+    flag: Option<Value>,
+    span: Span,
+) -> Option<Value> {
+    flag.or_else(|| {
+        get_optional_env_var(stack, engine_state, "IOX_FORMAT")
+            .filter(|format| {
+                let valid = IOX_FORMATS.contains(&format.as_str());
+                if !valid {
+                    eprintln!(
+                        "nu_iox: ignoring IOX_FORMAT '{}', expected one of {}",
+                        format,
+                        IOX_FORMATS.join(", ")
+                    );
+                }
+                valid
+            })
+            .or_else(|| load_iox_config(stack, engine_state).format)
+            .map(|val| Value::String { val, span })
+    })
+}
+
+/// Resolves the database to use, the explicit flag wins over IOX_DBNAME,
+/// which wins over the dbname of the config file
+///
+/// An empty value counts as unset, and having none of them is an error
+/// rather than a query against a database that doesn't exist.
+pub fn resolve_iox_dbname(
+    stack: &Stack,
+    engine_state: &EngineState,
+    flag: Option<String>,
+    span: Span,
+) -> Result<String, ShellError> {
+    flag.filter(|v| !v.is_empty())
+        .or_else(|| get_optional_env_var(stack, engine_state, "IOX_DBNAME"))
+        .or_else(|| {
+            load_iox_config(stack, engine_state)
+                .dbname
+                .filter(|v| !v.is_empty())
+        })
+        .ok_or_else(|| {
+            ShellError::GenericError(
+                "No database specified".into(),
+                "no database specified: pass -d or set IOX_DBNAME".into(),
+                Some(span),
+                Some("Select one for the session with: ioxuse <dbname>".into()),
+                Vec::new(),
+            )
+        })
+}
+
+/// Resolves the database a query runs against from --dbname and --namespace
+///
+/// A namespace given on its own is also the name the database is shown by,
+/// otherwise the name is resolved as by resolve_iox_dbname.
+pub fn resolve_iox_database(
+    stack: &Stack,
+    engine_state: &EngineState,
+    dbname: Option<String>,
+    namespace: Option<String>,
+    span: Span,
+) -> Result<RemoteDatabase, ShellError> {
+    let namespace = namespace.filter(|v| !v.is_empty());
+    let name = match (dbname.filter(|v| !v.is_empty()), &namespace) {
+        (None, Some(namespace)) => namespace.clone(),
+        (dbname, _) => resolve_iox_dbname(stack, engine_state, dbname, span)?,
+    };
+
+    Ok(RemoteDatabase::new(name, namespace))
+}
+
+/// Checks the database is one of the names listed by the server
+///
+/// A missing database is an error suggesting the closest name and listing
+/// the others, instead of the server error a query against it gives.
+pub fn check_database_exists(dbname: &Spanned<String>, names: &[String]) -> Result<(), ShellError> {
+    if names.iter().any(|name| name == &dbname.item) {
+        return Ok(());
+    }
+
+    let help = match did_you_mean(names, &dbname.item) {
+        Some(closest) => format!(
+            "Did you mean '{}'? Available databases: {}",
+            closest,
+            names.join(", ")
+        ),
+        None => "The server has no databases".to_string(),
+    };
+
+    Err(ShellError::GenericError(
+        format!("Database '{}' not found", dbname.item),
+        "not an Iox database".into(),
+        Some(dbname.span),
+        Some(help),
+        Vec::new(),
+    ))
+}
+
+/// Whether IOX_QUIET asks for queries to be kept out of the logs
+///
+/// Any value but an empty one, 0 or false turns it on, see Nuclient::set_quiet.
+pub fn resolve_iox_quiet(stack: &Stack, engine_state: &EngineState) -> bool {
+    get_optional_env_var(stack, engine_state, "IOX_QUIET").map_or(false, |quiet| {
+        !matches!(quiet.trim().to_lowercase().as_str(), "" | "0" | "false")
+    })
+}
+
+/// Whether the pretty format keeps its ANSI colors
+///
+/// Output is plain unless --color asks for colors, --no-color and a set
+/// NO_COLOR both turn them off again.
+pub fn resolve_pretty_color(
+    stack: &Stack,
+    engine_state: &EngineState,
+    color: bool,
+    no_color: bool,
+) -> bool {
+    color && !no_color && get_optional_env_var(stack, engine_state, "NO_COLOR").is_none()
+}
+
+/// Resolves the certificate authority file, the explicit flag wins over IOX_TLS_CA
+pub fn resolve_iox_tls_ca(
+    stack: &Stack,
+    engine_state: &EngineState,
+    flag: Option<Spanned<String>>,
+    span: Span,
+) -> Option<Spanned<String>> {
+    flag.filter(|v| !v.item.is_empty()).or_else(|| {
+        get_optional_env_var(stack, engine_state, "IOX_TLS_CA").map(|item| Spanned { item, span })
+    })
+}
+
+/// Resolves the TCP keepalive from IOX_KEEPALIVE_SECS
+///
+/// The value is in whole seconds and 0 turns keepalive off. This is TCP
+/// keepalive, the HTTP/2 ping settings of the gRPC channel are not exposed
+/// by influxdb_iox_client's connection Builder.
+pub fn resolve_iox_keepalive(
+    stack: &Stack,
+    engine_state: &EngineState,
+    span: Span,
+) -> Result<Option<Duration>, ShellError> {
+    match get_optional_env_var(stack, engine_state, "IOX_KEEPALIVE_SECS") {
+        None => Ok(Some(DEFAULT_IOX_KEEPALIVE)),
+        Some(secs) => match secs.trim().parse::<u64>() {
+            Ok(0) => Ok(None),
+            Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+            Err(_) => Err(ShellError::UnsupportedInput(
+                format!(
+                    "IOX_KEEPALIVE_SECS must be a whole number of seconds, got '{}'",
+                    secs
+                ),
+                span,
+            )),
+        },
+    }
+}
+
+/// Everything needed to open a connection to an Iox server
+pub struct IoxConnector {
+    builder: ConnectionBuilder,
+    tls: Option<rustls::ClientConfig>,
+    keepalive: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    compression: Compression,
+    // only whether there is one, the token itself is never logged
+    has_token: bool,
+    // None when the connection must not be shared with other commands
+    cache_key: Option<ConnectionKey>,
+    span: Span,
+}
+
+impl IoxConnector {
+    /// Adds metadata headers sent with every request on the connection
+    ///
+    /// The headers aren't part of the cache key, so a connection with custom
+    /// headers is never shared.
+    pub fn with_headers(mut self, headers: Vec<(HeaderName, HeaderValue)>) -> Self {
+        if !headers.is_empty() {
+            self.cache_key = None;
+        }
+        for (name, value) in headers {
+            self.builder = self.builder.header(name, value);
+        }
+        self
+    }
+
+    /// Sets the TCP keepalive of the connection, None turns it off
+    pub fn with_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.keepalive = keepalive;
+        if let Some(key) = &mut self.cache_key {
+            key.keepalive = keepalive;
+        }
+        self
+    }
+
+    /// Sets how long connecting may take, None waits forever
+    pub fn with_connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.connect_timeout = connect_timeout;
+        if let Some(key) = &mut self.cache_key {
+            key.connect_timeout = connect_timeout;
+        }
+        self
+    }
+
+    /// Sets the compression asked for the results of Flight SQL queries
+    ///
+    /// It is set on the clients made from the connection, not on the
+    /// connection, so connections are still shared whatever it is.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The compression asked for the results of Flight SQL queries
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Opens a connection of its own when the command runs on a dedicated
+    /// runtime, see num_threads_from_flag, as the connection dies with it
+    pub fn for_num_threads(self, num_threads: Option<usize>) -> Self {
+        match num_threads {
+            Some(_) => self.without_cache(),
+            None => self,
+        }
+    }
+
+    /// Opens a connection of its own rather than sharing the cached one
+    ///
+    /// For connecting on a runtime other than the shared one, a connection
+    /// dies with the runtime it was opened on.
+    pub fn without_cache(mut self) -> Self {
+        self.cache_key = None;
+        self
+    }
+
+    /// The span connection errors are reported against
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Connects to the Iox server
+    ///
+    /// A server that is down or refuses the connection is reported as a
+    /// ShellError naming the address, so it can be caught with try/catch.
+    pub async fn connect(self, addr: &str) -> Result<Connection, ShellError> {
+        let span = self.span;
+
+        self.try_connect(addr).await.map_err(|e| match e {
+            NuclientError::Connecting { source, .. } => connect_error(addr, source, span),
+            e => connect_error(addr, e, span),
+        })
+    }
+
+    /// Connects to the Iox server, returning the connection error as is
+    ///
+    /// Connecting for longer than the connect timeout fails with
+    /// Error::ConnectTimeout rather than hanging on an unroutable host. The
+    /// connection is cached for the session, a later connector for the same
+    /// address and token reuses it until forget_iox_connection drops it.
+    pub async fn try_connect(mut self, addr: &str) -> Result<Connection, NuclientError> {
+        match self.cache_key.take() {
+            None => self.open(addr).await,
+            Some(key) => cached_connection(&IOX_CONNECTIONS, &key, || self.open(addr)).await,
+        }
+    }
+
+    async fn open(self, addr: &str) -> Result<Connection, NuclientError> {
+        info!(
+            "Connecting to Iox at {} (tls: {}, token: {})",
+            addr,
+            self.tls.is_some(),
+            if self.has_token { "<redacted>" } else { "none" }
+        );
+
+        let mut http = HttpConnector::new();
+        http.set_nodelay(true);
+        http.set_keepalive(self.keepalive);
+
+        let connect_timeout = self.connect_timeout;
+        let connecting = async move {
+            match self.tls {
+                None => {
+                    self.builder
+                        .build_with_connector(addr.to_string(), http)
+                        .await
+                }
+                Some(config) => {
+                    self.builder
+                        .build_with_connector(addr.to_string(), iox_tls_connector(config, http))
+                        .await
+                }
+            }
+        };
+
+        connect_within(addr, connect_timeout, connecting).await
+    }
+}
+
+/// Awaits the connection, failing with Error::ConnectTimeout once the timeout expires
+async fn connect_within(
+    addr: &str,
+    timeout: Option<Duration>,
+    connecting: impl Future<Output = Result<Connection, ConnectionError>>,
+) -> Result<Connection, NuclientError> {
+    let connected = match timeout {
+        None => connecting.await,
+        Some(timeout) => match tokio::time::timeout(timeout, connecting).await {
+            Ok(connected) => connected,
+            Err(_) => return ConnectTimeoutSnafu { addr, timeout }.fail(),
+        },
+    };
+
+    connected.context(ConnectingSnafu { addr })
+}
+
+/// Reports a failed connection to the Iox server at addr
+pub fn connect_error(addr: &str, error: impl std::fmt::Display, span: Span) -> ShellError {
+    ShellError::GenericError(
+        format!("Failed to connect to Iox at {}", addr),
+        error.to_string(),
+        Some(span),
+        Some("Check that the Iox server is running, or point IOX_ADDR at it".into()),
+        Vec::new(),
+    )
+}
+
+/// Creates the connector for an already validated Iox address
+///
+/// https addresses get a TLS connector, see iox_tls_config. The keepalive
+/// starts out as DEFAULT_IOX_KEEPALIVE, see with_keepalive, the connect
+/// timeout as DEFAULT_CONNECT_TIMEOUT, see with_connect_timeout, and no
+/// compression, see with_compression.
+pub fn iox_connector(
+    addr: &str,
+    token: Option<&str>,
+    tls_ca: Option<&Spanned<String>>,
+    span: Span,
+) -> Result<IoxConnector, ShellError> {
+    Ok(IoxConnector {
+        builder: iox_connection_builder(token, span)?,
+        tls: iox_tls_config(addr, tls_ca, span)?,
+        keepalive: Some(DEFAULT_IOX_KEEPALIVE),
+        connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+        compression: Compression::None,
+        has_token: token.is_some(),
+        cache_key: Some(
+            ConnectionKey::new(addr, token).with_tls_ca(tls_ca.map(|ca| ca.item.as_str())),
+        ),
+        span,
+    })
+}
+
+/// Creates the connection Builder shared by the Iox commands
+///
+/// When a token is given it is sent as a bearer authorization header, so
+/// every client created from the connection is authenticated.
+///
+/// gRPC compression is not negotiated here: it has to be enabled on the
+/// generated tonic client, so only Flight SQL queries, whose client nu_iox
+/// makes itself, ask for it, see IoxConnector::with_compression.
+pub fn iox_connection_builder(
+    token: Option<&str>,
+    span: Span,
+) -> Result<ConnectionBuilder, ShellError> {
+    let builder = ConnectionBuilder::default();
+
+    match token {
+        None => Ok(builder),
+        Some(token) => Ok(builder.header(AUTHORIZATION, bearer_header_value(token, span)?)),
+    }
+}
+
+// The value is marked sensitive so the token never shows up in Debug output
+pub(crate) fn bearer_header_value(token: &str, span: Span) -> Result<HeaderValue, ShellError> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| {
+        ShellError::UnsupportedInput(
+            "the Iox token contains characters that are not allowed in a header".into(),
+            span,
+        )
+    })?;
+    value.set_sensitive(true);
+
+    Ok(value)
+}
 
-    let result_env = if let Some(env_name) = result {
-        env_name
-    } else {
-        "you need to throw an error if the environment variable does not exist".to_string()
+/// W3C trace context header Iox continues the trace of a request from
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Reads a --trace-id value, 32 hex digits that are not all zero
+pub fn trace_id_from_flag(trace_id: Spanned<String>) -> Result<String, ShellError> {
+    let id = trace_id.item.trim().to_ascii_lowercase();
+
+    if id.len() != 32 || !id.chars().all(|c| c.is_ascii_hexdigit()) || id.chars().all(|c| c == '0')
+    {
+        return Err(ShellError::UnsupportedInput(
+            format!(
+                "--trace-id must be 32 hex digits that are not all zero, got '{}'",
+                trace_id.item
+            ),
+            trace_id.span,
+        ));
+    }
+
+    Ok(id)
+}
+
+/// A random trace id, for --trace when no --trace-id is given
+pub fn new_trace_id() -> String {
+    use rand::prelude::{thread_rng, Rng};
+
+    let id: u128 = thread_rng().gen_range(1..=u128::MAX);
+    format!("{:032x}", id)
+}
+
+/// The traceparent header of a request in the trace, with a span id of its own
+///
+/// The trace is marked sampled, so a server that samples only some traces
+/// still records it.
+pub fn traceparent_header(
+    trace_id: &str,
+    span: Span,
+) -> Result<(HeaderName, HeaderValue), ShellError> {
+    use rand::prelude::{thread_rng, Rng};
+
+    let span_id: u64 = thread_rng().gen_range(1..=u64::MAX);
+    info!(%trace_id, span_id = %format!("{:016x}", span_id), "Tracing Iox request");
+
+    let value =
+        HeaderValue::from_str(&format!("00-{}-{:016x}-01", trace_id, span_id)).map_err(|_| {
+            ShellError::UnsupportedInput(format!("'{}' is not a valid trace id", trace_id), span)
+        })?;
+
+    Ok((HeaderName::from_static(TRACEPARENT), value))
+}
+
+/// Parses the --header flag, a `key=value` string or a list of them
+///
+/// Names and values are checked here so a header the server would never
+/// see is reported against the flag. Authorization is left to --token.
+pub fn headers_from_value(
+    headers: Option<Value>,
+) -> Result<Vec<(HeaderName, HeaderValue)>, ShellError> {
+    let headers = match headers {
+        None => return Ok(Vec::new()),
+        Some(Value::List { vals, .. }) => vals,
+        Some(value) => vec![value],
     };
 
-    Ok(result_env)
+    headers
+        .iter()
+        .map(|header| {
+            let span = header.span()?;
+            let header = header.as_string()?;
+            let (name, value) = header.split_once('=').ok_or_else(|| {
+                ShellError::UnsupportedInput(
+                    format!("header '{}' is not of the form key=value", header),
+                    span,
+                )
+            })?;
+
+            let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| {
+                ShellError::UnsupportedInput(format!("'{}' is not a valid header name", name), span)
+            })?;
+            if name == AUTHORIZATION {
+                return Err(ShellError::UnsupportedInput(
+                    "pass the authorization token with --token or IOX_TOKEN".into(),
+                    span,
+                ));
+            }
+            let value = HeaderValue::from_str(value.trim()).map_err(|_| {
+                ShellError::UnsupportedInput(
+                    format!(
+                        "the value of header '{}' contains characters that are not allowed",
+                        name
+                    ),
+                    span,
+                )
+            })?;
+
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Checks that the Iox server address is a full http(s) URL
+///
+/// This lets us report a bad address as a ShellError before the
+/// connection Builder ever sees it.
+pub fn validate_iox_addr(addr: &str, span: Span) -> Result<String, ShellError> {
+    match url::Url::parse(addr) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(addr.to_string()),
+        _ => Err(ShellError::UnsupportedInput(
+            format!(
+                "Incomplete or incorrect Iox address '{}'. Expected a full URL, e.g., {}",
+                addr, DEFAULT_IOX_ADDR
+            ),
+            span,
+        )),
+    }
 }
 
-pub fn number_of_csv_records(data: &str) -> Result<usize, Box<dyn std::error::Error>> {
+/// Counts the data records of a csv string, the header row is not counted
+///
+/// Quoted cells may hold separators, quotes and newlines, so records are
+/// counted by the csv reader instead of by splitting lines. Data that does
+/// not parse as csv, e.g. rows with differing field counts, is an error.
+pub fn number_of_csv_records(data: &str) -> Result<usize, csv::Error> {
     let mut rdr = ReaderBuilder::new().from_reader(data.as_bytes());
-    let numofrecords = rdr.records().count();
-    //println!("Number of records = {:?}", numofrecords);
+
+    let mut numofrecords = 0;
+    for record in rdr.records() {
+        record?;
+        numofrecords += 1;
+    }
+
     Ok(numofrecords)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn identical_targets_reuse_the_cached_connection() {
+        let cache = Mutex::new(HashMap::new());
+        let opened = &std::cell::Cell::new(0);
+        let connect = move || async move {
+            opened.set(opened.get() + 1);
+            Ok::<_, String>(Arc::new(opened.get()))
+        };
+        let key = ConnectionKey::new("http://127.0.0.1:8082", Some("s3cr3t"));
+
+        let (first, second, other_token, failed) = block_on_iox(async {
+            (
+                cached_connection(&cache, &key, connect).await.unwrap(),
+                cached_connection(&cache, &key, connect).await.unwrap(),
+                cached_connection(
+                    &cache,
+                    &ConnectionKey::new("http://127.0.0.1:8082", Some("other")),
+                    connect,
+                )
+                .await
+                .unwrap(),
+                cached_connection(
+                    &cache,
+                    &ConnectionKey::new("http://127.0.0.1:8083", None),
+                    || async { Err::<Arc<usize>, _>("refused".to_string()) },
+                )
+                .await,
+            )
+        })
+        .unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &other_token));
+        assert_eq!(opened.get(), 2);
+        assert!(failed.is_err());
+        assert_eq!(cache.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn forgetting_an_address_drops_all_its_connections() {
+        let addr = "http://forget.example.com:8082";
+        let cache = Mutex::new(HashMap::from([
+            (ConnectionKey::new(addr, None), 1),
+            (ConnectionKey::new(addr, Some("s3cr3t")), 2),
+            (ConnectionKey::new("http://127.0.0.1:8082", None), 3),
+        ]));
+
+        forget_cached_connection(&cache, addr);
+
+        let left: Vec<i32> = cache.lock().unwrap().values().copied().collect();
+        assert_eq!(left, vec![3]);
+        assert_eq!(forget_connection_on_error(addr, Ok::<_, String>(1)), Ok(1));
+    }
+
+    #[test]
+    fn connection_settings_are_part_of_the_cache_key() {
+        let addr = "http://127.0.0.1:8082";
+        let key = |connector: IoxConnector| connector.cache_key.unwrap();
+        let connector = || iox_connector(addr, None, None, Span::test_data()).unwrap();
+
+        assert_eq!(key(connector()), ConnectionKey::new(addr, None));
+        assert_eq!(
+            key(connector().with_keepalive(Some(DEFAULT_IOX_KEEPALIVE))),
+            key(connector())
+        );
+        assert_ne!(key(connector().with_keepalive(None)), key(connector()));
+        assert_ne!(
+            key(connector().with_connect_timeout(Some(Duration::from_secs(1)))),
+            key(connector())
+        );
+        assert_ne!(
+            ConnectionKey::new(addr, None).with_tls_ca(Some("other.pem")),
+            ConnectionKey::new(addr, None)
+        );
+    }
+
+    #[test]
+    fn custom_headers_or_threads_opt_out_of_the_cache() {
+        let connector = |addr| iox_connector(addr, None, None, Span::test_data()).unwrap();
+        let header = headers_from_value(Some(Value::test_string("x-tenant-id=bananas"))).unwrap();
+
+        assert!(connector("http://127.0.0.1:8082").cache_key.is_some());
+        assert!(connector("http://127.0.0.1:8082")
+            .with_headers(header)
+            .cache_key
+            .is_none());
+        assert!(connector("http://127.0.0.1:8082")
+            .for_num_threads(Some(2))
+            .cache_key
+            .is_none());
+    }
+
+    /// A stack with only the given env vars, and IOX_CONFIG pointing at a
+    /// missing file unless given, so the developer's own config file is
+    /// never read by the tests
+    fn stack_with_env(vars: &[(&str, &str)]) -> Stack {
+        let mut stack = Stack::new();
+        stack.add_env_var(
+            "IOX_CONFIG".to_string(),
+            Value::test_string("/no/such/nu_iox/config.toml"),
+        );
+        for (name, val) in vars {
+            stack.add_env_var(name.to_string(), Value::test_string(*val));
+        }
+        stack
+    }
+
+    #[test]
+    fn iox_quiet_values() {
+        let engine_state = EngineState::new();
+        let quiet =
+            |val: &str| resolve_iox_quiet(&stack_with_env(&[("IOX_QUIET", val)]), &engine_state);
+
+        assert!(!resolve_iox_quiet(&stack_with_env(&[]), &engine_state));
+        assert!(!quiet(""));
+        assert!(!quiet("0"));
+        assert!(!quiet("false"));
+        assert!(quiet("1"));
+        assert!(quiet("true"));
+    }
+
+    #[test]
+    fn pretty_output_is_plain_unless_color_is_asked_for() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[]);
+        let no_color = stack_with_env(&[("NO_COLOR", "1")]);
+
+        assert!(!resolve_pretty_color(&stack, &engine_state, false, false));
+        assert!(resolve_pretty_color(&stack, &engine_state, true, false));
+        assert!(!resolve_pretty_color(&stack, &engine_state, true, true));
+        assert!(!resolve_pretty_color(&no_color, &engine_state, true, false));
+    }
+
+    #[test]
+    fn counts_csv_records_without_the_header() {
+        let data = "region,user\nla,955\npa,9599\n";
+
+        assert_eq!(number_of_csv_records(data).unwrap(), 2);
+    }
+
+    #[test]
+    fn counts_records_with_embedded_delimiters_and_quotes() {
+        let data = concat!(
+            "host,note\n",
+            "a,\"one, two\"\n",
+            "b,\"first line\nsecond line\"\n",
+            "c,\"she said \"\"hi\"\"\"\n",
+        );
+
+        assert_eq!(number_of_csv_records(data).unwrap(), 3);
+    }
+
+    #[test]
+    fn error_message_has_no_csv_records() {
+        let data = "Error running remote query: status: NotFound, message: \"table cpu not found\"";
+
+        assert_eq!(number_of_csv_records(data).unwrap(), 0);
+    }
+
+    #[test]
+    fn ragged_rows_are_not_csv() {
+        let data = "Error running remote query\nstatus: Unavailable, message: \"a, b\"\n";
+
+        assert!(number_of_csv_records(data).is_err());
+    }
+
+    #[test]
+    fn block_on_iox_reuses_the_runtime() {
+        // A task spawned in one call is only still alive in the next call
+        // if both calls ran on the same runtime
+        let ready = Arc::new(AtomicBool::new(false));
+        let flag = ready.clone();
+
+        let handle = block_on_iox(async move {
+            tokio::spawn(async move {
+                while !flag.load(Ordering::SeqCst) {
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .unwrap();
+
+        ready.store(true, Ordering::SeqCst);
+        assert!(block_on_iox(handle).unwrap().is_ok());
+    }
+
+    #[test]
+    fn block_on_iox_refuses_nested_calls() {
+        let nested = block_on_iox(async { block_on_iox(async {}).is_err() }).unwrap();
+
+        assert!(nested);
+    }
+
+    #[test]
+    fn block_on_iox_with_threads_uses_requested_runtime() {
+        let answer = block_on_iox_with_threads(Some(1), async { 42 }).unwrap();
+
+        assert_eq!(answer, 42);
+    }
+
+    #[test]
+    fn retries_default_and_reject_negative_values() {
+        let spanned = |item| Spanned {
+            item,
+            span: Span::test_data(),
+        };
+
+        assert_eq!(retries_from_flag(None).unwrap(), DEFAULT_RETRIES);
+        assert_eq!(retries_from_flag(Some(spanned(0))).unwrap(), 0);
+        assert!(retries_from_flag(Some(spanned(-1))).is_err());
+    }
+
+    #[test]
+    fn num_threads_must_be_positive() {
+        let spanned = |item| Spanned {
+            item,
+            span: Span::test_data(),
+        };
+
+        assert_eq!(num_threads_from_flag(None).unwrap(), None);
+        assert_eq!(num_threads_from_flag(Some(spanned(2))).unwrap(), Some(2));
+        assert!(matches!(
+            num_threads_from_flag(Some(spanned(0))),
+            Err(ShellError::UnsupportedInput(..))
+        ));
+        assert!(num_threads_from_flag(Some(spanned(-1))).is_err());
+    }
+
+    #[test]
+    fn timeout_defaults_when_not_given() {
+        assert_eq!(
+            timeout_from_value(None, DEFAULT_QUERY_TIMEOUT).unwrap(),
+            Some(DEFAULT_QUERY_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn timeout_of_zero_disables_it() {
+        let zero = Value::Duration {
+            val: 0,
+            span: Span::test_data(),
+        };
+
+        assert_eq!(
+            timeout_from_value(Some(zero), DEFAULT_QUERY_TIMEOUT).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn timeout_is_read_from_duration() {
+        let five_secs = Value::Duration {
+            val: 5_000_000_000,
+            span: Span::test_data(),
+        };
+
+        assert_eq!(
+            timeout_from_value(Some(five_secs), DEFAULT_QUERY_TIMEOUT).unwrap(),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn addr_defaults_when_nothing_is_set() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[]);
+
+        assert_eq!(
+            resolve_iox_addr(&stack, &engine_state, None),
+            DEFAULT_IOX_ADDR
+        );
+        assert_eq!(
+            resolve_iox_write_addr(&stack, &engine_state, None),
+            DEFAULT_IOX_WRITE_ADDR
+        );
+    }
+
+    #[test]
+    fn addr_env_var_overrides_default() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[
+            ("IOX_ADDR", "http://query.example.com:8082"),
+            ("IOX_WRITE_ADDR", "http://write.example.com:8081"),
+        ]);
+
+        assert_eq!(
+            resolve_iox_addr(&stack, &engine_state, None),
+            "http://query.example.com:8082"
+        );
+        assert_eq!(
+            resolve_iox_write_addr(&stack, &engine_state, None),
+            "http://write.example.com:8081"
+        );
+    }
+
+    #[test]
+    fn addr_flag_overrides_env_var() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[("IOX_ADDR", "http://query.example.com:8082")]);
+
+        assert_eq!(
+            resolve_iox_addr(
+                &stack,
+                &engine_state,
+                Some("http://flag.example.com:8082".to_string())
+            ),
+            "http://flag.example.com:8082"
+        );
+    }
+
+    #[test]
+    fn token_flag_overrides_env_var() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[("IOX_TOKEN", "from-env")]);
+
+        assert_eq!(
+            resolve_iox_token(&stack, &engine_state, Some("from-flag".to_string())),
+            Some("from-flag".to_string())
+        );
+        assert_eq!(
+            resolve_iox_token(&stack, &engine_state, None),
+            Some("from-env".to_string())
+        );
+        assert_eq!(
+            resolve_iox_token(&stack_with_env(&[]), &engine_state, None),
+            None
+        );
+    }
+
+    #[test]
+    fn keepalive_comes_from_env_var() {
+        let engine_state = EngineState::new();
+        let span = Span::test_data();
+        let keepalive = |secs: &str| {
+            resolve_iox_keepalive(
+                &stack_with_env(&[("IOX_KEEPALIVE_SECS", secs)]),
+                &engine_state,
+                span,
+            )
+        };
+
+        assert_eq!(
+            resolve_iox_keepalive(&stack_with_env(&[]), &engine_state, span).unwrap(),
+            Some(DEFAULT_IOX_KEEPALIVE)
+        );
+        assert_eq!(keepalive("45").unwrap(), Some(Duration::from_secs(45)));
+        assert_eq!(keepalive("0").unwrap(), None);
+        assert!(keepalive("30s").is_err());
+    }
+
+    #[test]
+    fn missing_dbname_is_a_friendly_error() {
+        let engine_state = EngineState::new();
+
+        match resolve_iox_dbname(&stack_with_env(&[]), &engine_state, None, Span::test_data()) {
+            Err(ShellError::GenericError(_, msg, ..)) => {
+                assert_eq!(msg, "no database specified: pass -d or set IOX_DBNAME")
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+
+        let empty = stack_with_env(&[("IOX_DBNAME", "")]);
+        assert!(resolve_iox_dbname(&empty, &engine_state, None, Span::test_data()).is_err());
+    }
+
+    #[test]
+    fn dbname_flag_overrides_env_var() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[("IOX_DBNAME", "from-env")]);
+
+        assert_eq!(
+            resolve_iox_dbname(
+                &stack,
+                &engine_state,
+                Some("from-flag".to_string()),
+                Span::test_data()
+            )
+            .unwrap(),
+            "from-flag"
+        );
+        assert_eq!(
+            resolve_iox_dbname(&stack, &engine_state, None, Span::test_data()).unwrap(),
+            "from-env"
+        );
+    }
+
+    #[test]
+    fn namespace_flag_sets_the_wire_namespace() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[("IOX_DBNAME", "from-env")]);
+        let database = |dbname: Option<&str>, namespace: Option<&str>| {
+            resolve_iox_database(
+                &stack,
+                &engine_state,
+                dbname.map(String::from),
+                namespace.map(String::from),
+                Span::test_data(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            database(Some("bananas"), Some("myorg_bananas")),
+            RemoteDatabase::new("bananas".into(), Some("myorg_bananas".into()))
+        );
+        assert_eq!(
+            database(None, Some("myorg_bananas")),
+            RemoteDatabase::new("myorg_bananas".into(), None)
+        );
+        assert_eq!(
+            database(None, None),
+            RemoteDatabase::new("from-env".into(), None)
+        );
+    }
+
+    #[test]
+    fn token_is_sent_as_sensitive_bearer_header() {
+        let value = bearer_header_value("s3cr3t", Span::test_data()).unwrap();
+
+        assert_eq!(value.to_str().unwrap(), "Bearer s3cr3t");
+        assert!(value.is_sensitive());
+        assert!(!format!("{:?}", value).contains("s3cr3t"));
+    }
+
+    #[test]
+    fn trace_ids_are_32_hex_digits() {
+        let trace_id = |item: &str| {
+            trace_id_from_flag(Spanned {
+                item: item.to_string(),
+                span: Span::test_data(),
+            })
+        };
+
+        assert_eq!(
+            trace_id("4BF92F3577B34DA6A3CE929D0E0E4736").unwrap(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert!(trace_id("4bf92f3577b34da6").is_err());
+        assert!(trace_id("4bf92f3577b34da6a3ce929d0e0e473g").is_err());
+        assert!(trace_id(&"0".repeat(32)).is_err());
+
+        let generated = new_trace_id();
+        assert_eq!(trace_id(&generated).unwrap(), generated);
+
+        let (name, value) = traceparent_header(&generated, Span::test_data()).unwrap();
+        let value = value.to_str().unwrap();
+        assert_eq!(name, TRACEPARENT);
+        assert!(value.starts_with(&format!("00-{}-", generated)));
+        assert!(value.ends_with("-01"));
+        assert_eq!(value.len(), 55);
+    }
+
+    #[test]
+    fn headers_are_parsed_from_a_string_or_a_list() {
+        let headers = headers_from_value(Some(Value::List {
+            vals: vec![
+                Value::test_string("x-tenant-id=bananas"),
+                Value::test_string("X-Trace-Id = 42"),
+            ],
+            span: Span::test_data(),
+        }))
+        .unwrap();
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].0, "x-tenant-id");
+        assert_eq!(headers[0].1, "bananas");
+        assert_eq!(headers[1].0, "x-trace-id");
+        assert_eq!(headers[1].1, "42");
+
+        let single = headers_from_value(Some(Value::test_string("x-tenant-id=apples"))).unwrap();
+        assert_eq!(single[0].1, "apples");
+
+        assert!(headers_from_value(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let header = |h: &str| headers_from_value(Some(Value::test_string(h)));
+
+        assert!(header("x-tenant-id").is_err());
+        assert!(header("bad name=1").is_err());
+        assert!(header("x-tenant-id=bad\nvalue").is_err());
+        assert!(header("authorization=Bearer s3cr3t").is_err());
+    }
+
+    #[test]
+    fn token_with_invalid_characters_is_rejected() {
+        assert!(bearer_header_value("bad\ntoken", Span::test_data()).is_err());
+    }
+
+    fn profile(name: &str) -> Option<Spanned<String>> {
+        Some(Spanned {
+            item: name.to_string(),
+            span: Span::test_data(),
+        })
+    }
+
+    #[test]
+    fn profile_is_read_from_env_vars() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[
+            ("IOX_PROFILE_PROD_ADDR", "https://prod.example.com:443"),
+            ("IOX_PROFILE_PROD_TOKEN", "prod-token"),
+            ("IOX_PROFILE_DEV_BOX_ADDR", "http://dev.example.com:8082"),
+        ]);
+
+        let prod = resolve_iox_profile(&stack, &engine_state, profile("prod")).unwrap();
+        assert_eq!(prod.addr, Some("https://prod.example.com:443".to_string()));
+        assert_eq!(prod.write_addr, None);
+        assert_eq!(prod.token, Some("prod-token".to_string()));
+
+        let dev = resolve_iox_profile(&stack, &engine_state, profile("dev-box")).unwrap();
+        assert_eq!(dev.addr, Some("http://dev.example.com:8082".to_string()));
+
+        assert_eq!(
+            resolve_iox_profile(&stack, &engine_state, None).unwrap(),
+            IoxProfile::default()
+        );
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[]);
+
+        assert!(resolve_iox_profile(&stack, &engine_state, profile("staging")).is_err());
+    }
+
+    #[test]
+    fn flags_override_profile_which_overrides_env_vars() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[
+            ("IOX_ADDR", "http://env.example.com:8082"),
+            ("IOX_TOKEN", "env-token"),
+            ("IOX_PROFILE_PROD_ADDR", "https://prod.example.com:443"),
+            ("IOX_PROFILE_PROD_TOKEN", "prod-token"),
+            ("IOX_WRITE_ADDR", "http://env.example.com:8080"),
+            (
+                "IOX_PROFILE_PROD_WRITE_ADDR",
+                "https://prod-write.example.com:443",
+            ),
+        ]);
+        let prod = resolve_iox_profile(&stack, &engine_state, profile("prod")).unwrap();
+        let flag = |val: &str| Some(val.to_string());
+
+        // flag, profile and env var all set, the flag wins
+        assert_eq!(
+            prod.resolve_addr(&stack, &engine_state, flag("http://flag.example.com:8082")),
+            "http://flag.example.com:8082"
+        );
+        assert_eq!(
+            prod.resolve_write_addr(&stack, &engine_state, flag("http://flag.example.com:8080")),
+            "http://flag.example.com:8080"
+        );
+        assert_eq!(
+            prod.resolve_token(&stack, &engine_state, flag("flag-token")),
+            flag("flag-token")
+        );
+
+        // no flag, or an empty one, the profile wins over the env var
+        for unset in [None, flag("")] {
+            assert_eq!(
+                prod.resolve_addr(&stack, &engine_state, unset.clone()),
+                "https://prod.example.com:443"
+            );
+            assert_eq!(
+                prod.resolve_write_addr(&stack, &engine_state, unset.clone()),
+                "https://prod-write.example.com:443"
+            );
+            assert_eq!(
+                prod.resolve_token(&stack, &engine_state, unset),
+                flag("prod-token")
+            );
+        }
+
+        // no profile, the env var is left
+        let none = IoxProfile::default();
+        assert_eq!(
+            none.resolve_addr(&stack, &engine_state, None),
+            "http://env.example.com:8082"
+        );
+        assert_eq!(
+            none.resolve_write_addr(&stack, &engine_state, None),
+            "http://env.example.com:8080"
+        );
+        assert_eq!(
+            none.resolve_token(&stack, &engine_state, None),
+            flag("env-token")
+        );
+    }
+
+    #[test]
+    fn namespace_and_sql_paths_share_the_default_addr() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[]);
+
+        // both pass their unset --addr flag
+        let namespace_flag: Option<Spanned<String>> = None;
+        let sql_flag: Option<Spanned<String>> = None;
+        let namespace_addr =
+            resolve_iox_addr(&stack, &engine_state, namespace_flag.map(|a| a.item));
+        let sql_addr = resolve_iox_addr(&stack, &engine_state, sql_flag.map(|a| a.item));
+
+        assert_eq!(namespace_addr, sql_addr);
+        assert_eq!(namespace_addr, DEFAULT_IOX_ADDR);
+        assert!(validate_iox_addr(DEFAULT_IOX_ADDR, Span::test_data()).is_ok());
+        assert!(validate_iox_addr(DEFAULT_IOX_WRITE_ADDR, Span::test_data()).is_ok());
+    }
+
+    #[test]
+    fn connect_times_out_with_the_address() {
+        let addr = "http://iox.example.com:8082";
+        let pending = std::future::pending::<Result<Connection, ConnectionError>>();
+
+        let result = block_on_iox(connect_within(
+            addr,
+            Some(Duration::from_millis(10)),
+            pending,
+        ))
+        .unwrap();
+
+        match result {
+            Err(NuclientError::ConnectTimeout { addr: failed, .. }) => assert_eq!(failed, addr),
+            other => panic!("expected a connect timeout, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn blackholed_address_does_not_hang() {
+        // 10.255.255.1 is not routed, the SYN is never answered
+        let addr = "http://10.255.255.1:8082";
+        let connector = iox_connector(addr, None, None, Span::test_data())
+            .unwrap()
+            .with_connect_timeout(Some(Duration::from_millis(200)));
+
+        let start = std::time::Instant::now();
+        let result = block_on_iox(connector.try_connect(addr)).unwrap();
+
+        // a sandbox without a route may refuse it right away instead
+        assert!(matches!(
+            result,
+            Err(NuclientError::ConnectTimeout { .. } | NuclientError::Connecting { .. })
+        ));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    // Writes the config to its own temp file, returning the path
+    fn temp_config(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn config_file_sits_between_env_vars_and_defaults() {
+        let engine_state = EngineState::new();
+        let path = temp_config(
+            "nu_iox_config_precedence.toml",
+            concat!(
+                "addr = \"http://config.example.com:8082\"\n",
+                "dbname = \"from-config\"\n",
+                "token = \"config-token\"\n",
+                "format = \"json\"\n",
+            ),
+        );
+        let config = path.to_string_lossy().to_string();
+        let only_config = stack_with_env(&[("IOX_CONFIG", &config)]);
+        let with_env = stack_with_env(&[
+            ("IOX_CONFIG", &config),
+            ("IOX_ADDR", "http://env.example.com:8082"),
+            ("IOX_DBNAME", "from-env"),
+            ("IOX_TOKEN", "env-token"),
+        ]);
+        let span = Span::test_data();
+
+        let addr = resolve_iox_addr(&only_config, &engine_state, None);
+        let dbname = resolve_iox_dbname(&only_config, &engine_state, None, span);
+        let token = resolve_iox_token(&only_config, &engine_state, None);
+        let format = resolve_iox_format(&only_config, &engine_state, None, span);
+        let env_addr = resolve_iox_addr(&with_env, &engine_state, None);
+        let env_dbname = resolve_iox_dbname(&with_env, &engine_state, None, span);
+        let env_token = resolve_iox_token(&with_env, &engine_state, None);
+        let flag_addr = resolve_iox_addr(
+            &with_env,
+            &engine_state,
+            Some("http://flag.example.com:8082".to_string()),
+        );
+        let flag_format = resolve_iox_format(
+            &only_config,
+            &engine_state,
+            Some(Value::test_string("csv")),
+            span,
+        );
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(addr, "http://config.example.com:8082");
+        assert_eq!(dbname.unwrap(), "from-config");
+        assert_eq!(token, Some("config-token".to_string()));
+        assert_eq!(format, Some(Value::test_string("json")));
+
+        assert_eq!(env_addr, "http://env.example.com:8082");
+        assert_eq!(env_dbname.unwrap(), "from-env");
+        assert_eq!(env_token, Some("env-token".to_string()));
+
+        assert_eq!(flag_addr, "http://flag.example.com:8082");
+        assert_eq!(flag_format, Some(Value::test_string("csv")));
+    }
+
+    #[test]
+    fn json_config_file_is_read() {
+        let path = temp_config(
+            "nu_iox_config_read.json",
+            r#"{"addr": "http://json.example.com:8082"}"#,
+        );
+        let stack = stack_with_env(&[("IOX_CONFIG", &path.to_string_lossy())]);
+
+        let config = load_iox_config(&stack, &EngineState::new());
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(
+            config.addr,
+            Some("http://json.example.com:8082".to_string())
+        );
+        assert_eq!(config.dbname, None);
+    }
+
+    #[test]
+    fn broken_or_missing_config_file_is_ignored() {
+        let engine_state = EngineState::new();
+        let path = temp_config("nu_iox_config_broken.toml", "addr = [not toml");
+        let broken = stack_with_env(&[("IOX_CONFIG", &path.to_string_lossy())]);
+        let missing = stack_with_env(&[("IOX_CONFIG", "/no/such/nu_iox/config.toml")]);
+
+        let addr = resolve_iox_addr(&broken, &engine_state, None);
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(addr, DEFAULT_IOX_ADDR);
+        assert_eq!(
+            load_iox_config(&missing, &engine_state),
+            IoxConfig::default()
+        );
+    }
+
+    #[test]
+    fn missing_env_var_is_an_error_naming_it() {
+        let engine_state = EngineState::new();
+        let mut stack = stack_with_env(&[("IOX_DBNAME", "bananas"), ("IOX_EMPTY", "")]);
+        let span = Span::test_data();
+
+        assert_eq!(
+            get_env_var_from_engine(&mut stack, &engine_state, "IOX_DBNAME", span).unwrap(),
+            "bananas"
+        );
+        for name in ["IOX_MISSING", "IOX_EMPTY"] {
+            match get_env_var_from_engine(&mut stack, &engine_state, name, span) {
+                Err(ShellError::GenericError(title, ..)) => assert!(title.contains(name)),
+                other => panic!("expected an error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn addr_empty_env_var_is_ignored() {
+        let engine_state = EngineState::new();
+        let stack = stack_with_env(&[("IOX_ADDR", "")]);
+
+        assert_eq!(
+            resolve_iox_addr(&stack, &engine_state, None),
+            DEFAULT_IOX_ADDR
+        );
+    }
+
+    #[test]
+    fn nonexistent_database_suggests_the_closest_name() {
+        let names = vec!["bananas".to_string(), "apples".to_string()];
+        let dbname = |name: &str| Spanned {
+            item: name.to_string(),
+            span: Span::test_data(),
+        };
+
+        assert!(check_database_exists(&dbname("apples"), &names).is_ok());
+        match check_database_exists(&dbname("banana"), &names) {
+            Err(ShellError::GenericError(title, _, _, Some(help), _)) => {
+                assert_eq!(title, "Database 'banana' not found");
+                assert_eq!(
+                    help,
+                    "Did you mean 'bananas'? Available databases: bananas, apples"
+                );
+            }
+            other => panic!("expected a not found error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn format_env_var_is_the_default_format() {
+        let engine_state = EngineState::new();
+        let span = Span::test_data();
+        let format = |vars: &[(&str, &str)], flag: Option<&str>| {
+            let stack = stack_with_env(vars);
+            resolve_iox_format(&stack, &engine_state, flag.map(Value::test_string), span)
+        };
+
+        assert_eq!(
+            format(&[("IOX_FORMAT", "json")], None),
+            Some(Value::test_string("json"))
+        );
+        assert_eq!(
+            format(&[("IOX_FORMAT", "json")], Some("csv")),
+            Some(Value::test_string("csv"))
+        );
+        assert_eq!(format(&[("IOX_FORMAT", "bogus")], None), None);
+        assert_eq!(format(&[("IOX_FORMAT", "")], None), None);
+    }
+}
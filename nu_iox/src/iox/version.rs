@@ -0,0 +1,345 @@
+use super::ping::DEFAULT_PING_TIMEOUT;
+use super::util::{
+    block_on_iox, connect_error, headers_from_value, iox_connector, resolve_iox_keepalive,
+    resolve_iox_profile, resolve_iox_tls_ca, timeout_from_value, validate_iox_addr, IoxConnector,
+    DEFAULT_IOX_ADDR,
+};
+use http::uri::PathAndQuery;
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, Spanned,
+    SyntaxShape, Value,
+};
+use std::time::Duration;
+use tonic::client::Grpc;
+use tonic::codec::ProstCodec;
+
+/// Version of the influxdb_iox_client crate this was built against
+pub const IOX_CLIENT_VERSION: &str = env!("IOX_CLIENT_VERSION");
+
+/// Reply header servers of the InfluxDB family report their version in
+const VERSION_HEADER: &str = "x-influxdb-version";
+
+/// The gRPC health check the version is read from, it answers on every Iox server
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// The grpc.health.v1 request, no service asks about the whole server
+///
+/// influxdb_iox_client's health client only returns whether the server is
+/// serving, the reply headers the version is in are dropped, so the check
+/// is sent with tonic on the connection from iox_connector instead.
+#[derive(Clone, PartialEq, prost::Message)]
+struct HealthCheckRequest {
+    #[prost(string, tag = "1")]
+    service: String,
+}
+
+/// The grpc.health.v1 reply, only its headers are read
+#[derive(Clone, PartialEq, prost::Message)]
+struct HealthCheckResponse {
+    #[prost(int32, tag = "1")]
+    status: i32,
+}
+
+#[derive(Clone)]
+pub struct Ioxversion;
+
+impl Command for Ioxversion {
+    fn name(&self) -> &str {
+        "ioxversion"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxversion")
+            .named(
+                "addr",
+                SyntaxShape::String,
+                format!(
+                    "address of the Iox server, defaults to IOX_ADDR or {}",
+                    DEFAULT_IOX_ADDR
+                ),
+                Some('a'),
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "tls-ca",
+                SyntaxShape::Filepath,
+                "PEM certificate authority for an https Iox server, defaults to IOX_TLS_CA",
+                None,
+            )
+            .named(
+                "header",
+                SyntaxShape::Any,
+                "metadata header sent with every request as key=value, or a list of them, e.g. x-tenant-id=bananas",
+                None,
+            )
+            .named(
+                "timeout",
+                SyntaxShape::Duration,
+                "how long to wait for an answer, defaults to 5sec",
+                None,
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "Show the versions of the Iox server and of the Iox client."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Iox has no version endpoint, the server version is taken from the x-influxdb-version header of its reply to a health check. A server that doesn't send the header is reported as unknown."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let addr_flag: Option<Spanned<String>> = call.get_flag(engine_state, stack, "addr")?;
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+        let timeout = timeout_from_value(
+            call.get_flag(engine_state, stack, "timeout")?,
+            DEFAULT_PING_TIMEOUT,
+        )?;
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr_span = addr_flag.as_ref().map_or(call.head, |a| a.span);
+        let addr = profile.resolve_addr(stack, engine_state, addr_flag.map(|a| a.item));
+        let addr = validate_iox_addr(&addr, addr_span)?;
+
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "tls-ca")?,
+            call.head,
+        );
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+        let headers = headers_from_value(call.get_flag(engine_state, stack, "header")?)?;
+        let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+            .with_keepalive(keepalive)
+            .with_headers(headers);
+
+        let server_version = tokio_block_server_version(connector, &addr, timeout, call.head)?;
+
+        Ok(version_to_value(&addr, server_version, call.head).into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Show the versions for the default Iox server",
+                example: r#"ioxversion"#,
+                result: None,
+            },
+            Example {
+                description: "Show the versions for another Iox server",
+                example: r#"ioxversion -a http://iox.example.com:8082"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+pub fn version_to_value(addr: &str, server_version: Option<String>, span: Span) -> Value {
+    Value::Record {
+        cols: vec![
+            "server_version".to_string(),
+            "client_version".to_string(),
+            "addr".to_string(),
+        ],
+        vals: vec![
+            Value::String {
+                val: server_version.unwrap_or_else(|| "unknown".to_string()),
+                span,
+            },
+            Value::String {
+                val: IOX_CLIENT_VERSION.to_string(),
+                span,
+            },
+            Value::String {
+                val: addr.to_string(),
+                span,
+            },
+        ],
+        span,
+    }
+}
+
+/// Asks the server for its health, returning the version it reported, if any
+///
+/// Whatever the health check answers, only the reply headers matter. A
+/// server that can't be reached is an error naming the address.
+pub fn tokio_block_server_version(
+    connector: IoxConnector,
+    addr: &str,
+    timeout: Option<Duration>,
+    span: Span,
+) -> Result<Option<String>, ShellError> {
+    let version = async move {
+        let connection = connector.connect(addr).await?;
+        let mut client = Grpc::new(connection.into_grpc_connection());
+        client
+            .ready()
+            .await
+            .map_err(|e| connect_error(addr, e, span))?;
+
+        // streamed so the headers are read before, and without, a reply message
+        let reply = client
+            .server_streaming(
+                tonic::Request::new(HealthCheckRequest::default()),
+                PathAndQuery::from_static(HEALTH_CHECK_PATH),
+                ProstCodec::<HealthCheckRequest, HealthCheckResponse>::default(),
+            )
+            .await;
+        let metadata = match &reply {
+            Ok(response) => response.metadata(),
+            // a failed check still comes with the headers of the server
+            Err(status) if !status.metadata().is_empty() => status.metadata(),
+            Err(status) => return Err(connect_error(addr, status, span)),
+        };
+
+        Ok::<_, ShellError>(
+            metadata
+                .get(VERSION_HEADER)
+                .and_then(|version| version.to_str().ok())
+                .map(str::to_string),
+        )
+    };
+
+    block_on_iox(async move {
+        match timeout {
+            None => version.await,
+            Some(timeout) => tokio::time::timeout(timeout, version)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(connect_error(
+                        addr,
+                        format!("no answer within {:?}", timeout),
+                        span,
+                    ))
+                }),
+        }
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Answers the health check, with the version header when one is given
+    fn mock_version_server(version: Option<&'static str>) -> String {
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Request, Response, Server};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            runtime.block_on(async move {
+                let make_service = make_service_fn(move |_| async move {
+                    Ok::<_, hyper::Error>(service_fn(move |_request: Request<Body>| async move {
+                        let mut response = Response::builder()
+                            .header("content-type", "application/grpc")
+                            .header("grpc-status", "0");
+                        if let Some(version) = version {
+                            response = response.header(VERSION_HEADER, version);
+                        }
+                        response.body(Body::empty())
+                    }))
+                });
+
+                Server::from_tcp(listener)
+                    .unwrap()
+                    .http2_only(true)
+                    .serve(make_service)
+                    .await
+            })
+        });
+
+        addr
+    }
+
+    fn server_version_of(addr: &str) -> Result<Option<String>, ShellError> {
+        let connector = iox_connector(addr, None, None, Span::test_data()).unwrap();
+
+        tokio_block_server_version(
+            connector,
+            addr,
+            Some(DEFAULT_PING_TIMEOUT),
+            Span::test_data(),
+        )
+    }
+
+    #[test]
+    fn reports_the_version_the_server_sends() {
+        let addr = mock_version_server(Some("2.0.0-iox"));
+
+        let version = server_version_of(&addr).unwrap();
+        let value = version_to_value(&addr, version, Span::test_data());
+
+        assert_eq!(
+            value.get_data_by_key("server_version"),
+            Some(Value::test_string("2.0.0-iox"))
+        );
+        assert_eq!(
+            value.get_data_by_key("client_version"),
+            Some(Value::test_string(IOX_CLIENT_VERSION))
+        );
+        assert_eq!(
+            value.get_data_by_key("addr"),
+            Some(Value::test_string(addr))
+        );
+    }
+
+    #[test]
+    fn server_without_a_version_is_unknown() {
+        let addr = mock_version_server(None);
+
+        let version = server_version_of(&addr).unwrap();
+        let value = version_to_value(&addr, version, Span::test_data());
+
+        assert_eq!(
+            value.get_data_by_key("server_version"),
+            Some(Value::test_string("unknown"))
+        );
+    }
+
+    #[test]
+    fn stopped_server_is_an_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        match server_version_of(&addr) {
+            Err(ShellError::GenericError(msg, ..)) => assert!(msg.contains(&addr)),
+            other => panic!("expected a connection error, got {:?}", other),
+        }
+    }
+}
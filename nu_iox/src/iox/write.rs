@@ -1,11 +1,22 @@
-use super::util::{get_env_var_from_engine, get_runtime};
+use super::dbnames::dbname_shape;
+use super::lineproto::{
+    count_points_lines, invalid_lines, precision_from_flag, timestamps_to_nanos,
+    validate_line_protocol, write_unless_dry_run, Precision,
+};
+use super::util::{
+    block_on_iox, iox_connector, resolve_iox_dbname, resolve_iox_keepalive, resolve_iox_profile,
+    resolve_iox_tls_ca, validate_iox_addr, IoxConnector,
+};
 use nu_engine::CallExt;
 use nu_protocol::ast::Call;
 use nu_protocol::engine::{Command, EngineState, Stack};
 
 use nu_protocol::{
-    Category, Example, PipelineData, ShellError, Signature, Spanned, SyntaxShape, Value,
+    Category, Config, Example, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape,
+    Value,
 };
+use std::future::Future;
+use std::pin::Pin;
 
 #[derive(Clone)]
 pub struct Ioxwrite;
@@ -17,17 +28,45 @@ impl Command for Ioxwrite {
 
     fn signature(&self) -> nu_protocol::Signature {
         Signature::build("ioxwrite")
-            .required(
+            .optional(
                 "data",
                 SyntaxShape::String,
-                "Line protocol string to write to Iox",
+                "Line protocol string to write to Iox, read from the pipeline when omitted",
             )
             .named(
                 "dbname",
-                SyntaxShape::String,
+                dbname_shape(),
                 "name of the database to write to",
                 Some('d'),
             )
+            .named(
+                "precision",
+                SyntaxShape::String,
+                "unit of the timestamps: ns (default), us, ms or s",
+                Some('p'),
+            )
+            .switch(
+                "dry-run",
+                "check the line protocol and summarize it without writing anything",
+                None,
+            )
+            .switch(
+                "skip-validation",
+                "send the line protocol without checking it first",
+                None,
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
             .category(Category::Filters)
     }
 
@@ -40,27 +79,45 @@ impl Command for Ioxwrite {
         engine_state: &EngineState,
         stack: &mut Stack,
         call: &Call,
-        _input: PipelineData,
+        input: PipelineData,
     ) -> Result<PipelineData, ShellError> {
-        let lp_data: Spanned<String> = call.req(engine_state, stack, 0)?;
+        let data: Option<Spanned<String>> = call.opt(engine_state, stack, 0)?;
+        let config = engine_state.get_config();
+        let lp_data = lp_data_from_input(data, input, config, call.head)?;
         let db: Option<String> = call.get_flag(engine_state, stack, "dbname")?;
+        let precision = precision_from_flag(call.get_flag(engine_state, stack, "precision")?)?;
 
-        let dbname = if let Some(name) = db {
-            name
-        } else {
-            get_env_var_from_engine(stack, engine_state, "IOX_DBNAME").unwrap()
-        };
+        let dry_run = call.has_flag("dry-run");
+
+        if !dry_run && !call.has_flag("skip-validation") {
+            validate_line_protocol(&lp_data)?;
+        }
+
+        let dbname = resolve_iox_dbname(stack, engine_state, db, call.head)?;
 
         println!("dbname = {:?}", dbname);
 
-        let nol_result = tokio_block_write(&dbname, &lp_data);
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr = profile.resolve_write_addr(stack, engine_state, None);
+        let addr = validate_iox_addr(&addr, call.head)?;
 
-        println!("{:?}", nol_result);
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
 
-        Ok(PipelineData::Value(
-            Value::Nothing { span: call.head },
-            None,
-        ))
+        let summary = write_unless_dry_run(&lp_data, &dbname, dry_run, call.head, || {
+            let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+                .with_keepalive(keepalive);
+
+            tokio_block_write(connector, &addr, &dbname, &lp_data, precision, call.head)
+        })?;
+
+        Ok(PipelineData::Value(summary, None))
     }
 
     fn examples(&self) -> Vec<Example> {
@@ -85,36 +142,360 @@ impl Command for Ioxwrite {
                 example: r#"ioxwrite "cpu,region=pa user=9599 222522""#,
                 result: None,
             },
+            Example {
+                description: "Write line protocol whose timestamps are in seconds",
+                example: r#"ioxwrite --precision s "cpu,region=pa user=9599 1660000000""#,
+                result: None,
+            },
+            Example {
+                description: "Check a file of line protocol and see what it would write",
+                example: r#"open data.lp | ioxwrite --dry-run"#,
+                result: None,
+            },
+            Example {
+                description: "Write line protocol piped in from a file",
+                example: r#"open data.lp | ioxwrite -d bananas"#,
+                result: None,
+            },
         ]
     }
 }
 
+// The data argument wins, otherwise the whole pipeline is read as line protocol
+fn lp_data_from_input(
+    data: Option<Spanned<String>>,
+    input: PipelineData,
+    config: &Config,
+    span: Span,
+) -> Result<Spanned<String>, ShellError> {
+    let lp_data = match data {
+        Some(data) => data,
+        None => Spanned {
+            item: input.collect_string("\n", config)?,
+            span,
+        },
+    };
+
+    if lp_data.item.trim().is_empty() {
+        return Err(ShellError::UnsupportedInput(
+            "No line protocol to write: pass it as an argument or pipe it in".into(),
+            lp_data.span,
+        ));
+    }
+
+    Ok(lp_data)
+}
+
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<usize, String>> + 'a>>;
+
+/// Something line protocol can be written to, returning the lines accepted
+pub trait LpWriter {
+    fn send_lp<'a>(
+        &'a mut self,
+        dbname: &'a str,
+        lp_data: &'a str,
+        precision: Precision,
+    ) -> SendFuture<'a>;
+}
+
+// The write request has no precision of its own, the timestamps are scaled
+// to nanoseconds on the way out instead
+impl LpWriter for influxdb_iox_client::write::Client {
+    fn send_lp<'a>(
+        &'a mut self,
+        dbname: &'a str,
+        lp_data: &'a str,
+        precision: Precision,
+    ) -> SendFuture<'a> {
+        Box::pin(async move {
+            let lp_data = timestamps_to_nanos(lp_data, precision)?;
+
+            self.write_lp(dbname.to_string(), lp_data, 0)
+                .await
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// Writes the line protocol and reports what the server accepted
+///
+/// The record has the lines sent and accepted and the bytes sent. When the
+/// server accepted fewer lines than were sent the status is "partial" and
+/// rejected lists the lines that are malformed, with the reason. The server
+/// only reports a count, so a rejected line that looks valid can't be
+/// pointed at. A write that fails outright is an error.
+pub async fn write_and_report<W: LpWriter>(
+    writer: &mut W,
+    dbname: &str,
+    lp_data: &str,
+    precision: Precision,
+    span: Span,
+) -> Result<Value, ShellError> {
+    let lines_sent = count_points_lines(lp_data);
+    let rejected = invalid_lines(lp_data);
+
+    let lines_accepted = match writer.send_lp(dbname, lp_data, precision).await {
+        Ok(lines_accepted) => lines_accepted,
+        Err(error) => {
+            return Err(ShellError::GenericError(
+                format!("Write to Iox database '{}' failed", dbname),
+                error,
+                Some(span),
+                None,
+                rejected
+                    .into_iter()
+                    .map(|(n, reason)| {
+                        ShellError::GenericError(
+                            format!("line {}: {}", n, reason),
+                            reason,
+                            None,
+                            None,
+                            Vec::new(),
+                        )
+                    })
+                    .collect(),
+            ))
+        }
+    };
+
+    let (status, rejected) = if lines_accepted < lines_sent {
+        ("partial", rejected)
+    } else {
+        ("ok", Vec::new())
+    };
+
+    let rejected = rejected
+        .into_iter()
+        .map(|(n, reason)| Value::Record {
+            cols: vec!["line".to_string(), "reason".to_string()],
+            vals: vec![
+                Value::Int {
+                    val: n as i64,
+                    span,
+                },
+                Value::String { val: reason, span },
+            ],
+            span,
+        })
+        .collect();
+
+    Ok(Value::Record {
+        cols: vec![
+            "dbname".to_string(),
+            "status".to_string(),
+            "lines_sent".to_string(),
+            "lines_accepted".to_string(),
+            "bytes".to_string(),
+            "rejected".to_string(),
+        ],
+        vals: vec![
+            Value::String {
+                val: dbname.to_string(),
+                span,
+            },
+            Value::String {
+                val: status.to_string(),
+                span,
+            },
+            Value::Int {
+                val: lines_sent as i64,
+                span,
+            },
+            Value::Int {
+                val: lines_accepted as i64,
+                span,
+            },
+            Value::Filesize {
+                val: lp_data.len() as i64,
+                span,
+            },
+            Value::List {
+                vals: rejected,
+                span,
+            },
+        ],
+        span,
+    })
+}
+
 pub fn tokio_block_write(
-    dbname: &String,
+    connector: IoxConnector,
+    addr: &str,
+    dbname: &str,
     lp_data: &Spanned<String>,
-) -> Result<String, std::io::Error> {
-    use influxdb_iox_client::{connection::Builder, write::Client};
+    precision: Precision,
+    span: Span,
+) -> Result<Value, ShellError> {
+    use influxdb_iox_client::write::Client;
 
-    let num_threads: Option<usize> = None;
-    let tokio_runtime = get_runtime(num_threads)?;
-
-    let nol_result = tokio_runtime.block_on(async move {
-        let connection = Builder::default()
-            .build("http://127.0.0.1:8081")
-            .await
-            .expect("client should be valid");
+    let report = block_on_iox(async move {
+        let connection = connector.connect(addr).await?;
 
         let mut client = Client::new(connection);
+        write_and_report(&mut client, dbname, &lp_data.item, precision, span).await
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?;
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_multi_line_pipeline_input() {
+        let lp = "cpu,region=la user=955 222522\ncpu,region=pa user=9599 222522\n";
+        let input = PipelineData::Value(Value::test_string(lp), None);
+
+        let lp_data =
+            lp_data_from_input(None, input, &Config::default(), Span::test_data()).unwrap();
+
+        assert_eq!(lp_data.item, lp);
+        assert_eq!(lp_data.item.lines().count(), 2);
+    }
+
+    #[test]
+    fn argument_wins_over_pipeline_input() {
+        let input = PipelineData::Value(Value::test_string("from pipeline"), None);
+        let data = Spanned {
+            item: "from argument".to_string(),
+            span: Span::test_data(),
+        };
+
+        let lp_data =
+            lp_data_from_input(Some(data), input, &Config::default(), Span::test_data()).unwrap();
+
+        assert_eq!(lp_data.item, "from argument");
+    }
+
+    // Accepts a fixed number of lines, or fails with the given message
+    struct MockWriter {
+        accepted: Result<usize, String>,
+        calls: usize,
+        precision: Option<Precision>,
+    }
+
+    impl MockWriter {
+        fn new(accepted: Result<usize, String>) -> Self {
+            MockWriter {
+                accepted,
+                calls: 0,
+                precision: None,
+            }
+        }
+    }
 
-        let numoflines = client
-            .write_lp(dbname.to_string(), lp_data.item.to_string(), 0)
-            .await;
+    impl LpWriter for MockWriter {
+        fn send_lp<'a>(
+            &'a mut self,
+            _dbname: &'a str,
+            _lp_data: &'a str,
+            precision: Precision,
+        ) -> SendFuture<'a> {
+            self.calls += 1;
+            self.precision = Some(precision);
+            let accepted = self.accepted.clone();
+            Box::pin(async move { accepted })
+        }
+    }
+
+    fn write_with(accepted: Result<usize, String>, lp: &str) -> Result<Value, ShellError> {
+        let mut writer = MockWriter::new(accepted);
+        let report = block_on_iox(write_and_report(
+            &mut writer,
+            "bananas",
+            lp,
+            Precision::default(),
+            Span::test_data(),
+        ))
+        .unwrap();
+
+        assert_eq!(writer.calls, 1);
+        report
+    }
+
+    #[test]
+    fn precision_is_forwarded_to_the_writer() {
+        let mut writer = MockWriter::new(Ok(1));
+
+        block_on_iox(write_and_report(
+            &mut writer,
+            "bananas",
+            "cpu user=1 1660000000\n",
+            Precision::Seconds,
+            Span::test_data(),
+        ))
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(writer.precision, Some(Precision::Seconds));
+    }
+
+    #[test]
+    fn full_write_is_reported_ok() {
+        let span = Span::test_data();
+        let lp = "cpu user=1 1\n\ncpu user=2 2\n";
 
-        match numoflines {
-            Ok(res) => res.to_string(),
-            Err(error) => error.to_string(),
+        let report = write_with(Ok(2), lp).unwrap();
+
+        assert_eq!(
+            report.get_data_by_key("status"),
+            Some(Value::test_string("ok"))
+        );
+        assert_eq!(
+            report.get_data_by_key("lines_accepted"),
+            Some(Value::Int { val: 2, span })
+        );
+        assert_eq!(
+            report.get_data_by_key("bytes"),
+            Some(Value::Filesize {
+                val: lp.len() as i64,
+                span
+            })
+        );
+    }
+
+    #[test]
+    fn partial_write_lists_the_rejected_lines() {
+        let span = Span::test_data();
+
+        let report = write_with(Ok(1), "cpu user=1 1\ncpu user=oops later\n").unwrap();
+
+        assert_eq!(
+            report.get_data_by_key("status"),
+            Some(Value::test_string("partial"))
+        );
+        assert_eq!(
+            report.get_data_by_key("lines_sent"),
+            Some(Value::Int { val: 2, span })
+        );
+        match report.get_data_by_key("rejected") {
+            Some(Value::List { vals, .. }) => {
+                assert_eq!(vals.len(), 1);
+                assert_eq!(
+                    vals[0].get_data_by_key("line"),
+                    Some(Value::Int { val: 2, span })
+                );
+            }
+            other => panic!("expected a list, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failed_write_is_an_error() {
+        let result = write_with(Err("database not found".to_string()), "cpu user=1 1\n");
+
+        match result {
+            Err(ShellError::GenericError(_, msg, ..)) => assert_eq!(msg, "database not found"),
+            other => panic!("expected an error, got {:?}", other),
         }
-    });
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        let input = PipelineData::new(Span::test_data());
 
-    Ok(nol_result)
+        assert!(lp_data_from_input(None, input, &Config::default(), Span::test_data()).is_err());
+    }
 }
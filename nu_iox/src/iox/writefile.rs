@@ -1,11 +1,20 @@
-use super::util::{get_env_var_from_engine, get_runtime};
+use super::dbnames::dbname_shape;
+use super::lineproto::{precision_from_flag, timestamps_to_nanos, write_unless_dry_run};
+use super::util::{
+    block_on_iox, iox_connector, resolve_iox_dbname, resolve_iox_keepalive, resolve_iox_profile,
+    resolve_iox_tls_ca, validate_iox_addr, IoxConnector,
+};
+use nu_engine::env::current_dir;
 use nu_engine::CallExt;
 use nu_protocol::ast::Call;
 use nu_protocol::engine::{Command, EngineState, Stack};
-use std::fs::File;
-use std::io::Read;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape, Value};
+use nu_protocol::{
+    Category, Example, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape, Value,
+};
 
 #[derive(Clone)]
 pub struct Ioxwritefile;
@@ -19,20 +28,73 @@ impl Command for Ioxwritefile {
         Signature::build("ioxwritefile")
             .required(
                 "filename",
-                SyntaxShape::String,
-                "File name that contains influxdb line protocol data",
+                SyntaxShape::GlobPattern,
+                "File name or glob of the files that contain influxdb line protocol data",
+            )
+            .rest(
+                "rest",
+                SyntaxShape::GlobPattern,
+                "more file names or globs to write",
             )
             .named(
                 "dbname",
-                SyntaxShape::String,
+                dbname_shape(),
                 "name of the database to write to",
                 Some('d'),
             )
+            .named(
+                "batch-size",
+                SyntaxShape::Int,
+                "write the file in chunks of this many lines, defaults to the whole file at once",
+                Some('b'),
+            )
+            .named(
+                "precision",
+                SyntaxShape::String,
+                "unit of the timestamps: ns (default), us, ms or s",
+                Some('p'),
+            )
+            .switch(
+                "dry-run",
+                "check the line protocol and summarize it without writing anything",
+                None,
+            )
+            .switch(
+                "quiet",
+                "don't report the progress of a --batch-size write on stderr",
+                Some('q'),
+            )
+            .switch(
+                "fail-fast",
+                "stop at the first file that fails instead of skipping it",
+                None,
+            )
+            .switch(
+                "keep-crlf",
+                "send windows line endings as they are instead of stripping the \\r before each \\n",
+                None,
+            )
+            .named(
+                "token",
+                SyntaxShape::String,
+                "authorization token for the Iox server, defaults to IOX_TOKEN",
+                Some('t'),
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
             .category(Category::Filters)
     }
 
     fn usage(&self) -> &str {
-        "Write a line protocol file to the Iox Database."
+        "Write line protocol files to the Iox Database."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Returns the lines written per file and the total. A file that fails is reported on stderr and skipped, unless --fail-fast is given. Files with windows line endings are written with plain \\n line endings, unless --keep-crlf is given."
     }
 
     fn run(
@@ -42,32 +104,80 @@ impl Command for Ioxwritefile {
         call: &Call,
         _input: PipelineData,
     ) -> Result<PipelineData, ShellError> {
-        let filename: String = call.req(engine_state, stack, 0)?;
+        let patterns: Vec<Spanned<String>> = call.rest(engine_state, stack, 0)?;
         let db: Option<String> = call.get_flag(engine_state, stack, "dbname")?;
+        let batch_size = batch_size_from_flag(call.get_flag(engine_state, stack, "batch-size")?)?;
+        let precision = precision_from_flag(call.get_flag(engine_state, stack, "precision")?)?;
 
-        let dbname = if let Some(name) = db {
-            name
-        } else {
-            get_env_var_from_engine(stack, engine_state, "IOX_DBNAME").unwrap()
-        };
+        let files = expand_lp_files(&patterns, &current_dir(engine_state, stack)?)?;
+
+        let dbname = resolve_iox_dbname(stack, engine_state, db, call.head)?;
 
         println!("dbname = {:?}", dbname);
 
-        //let mut file = File::open(filename).unwrap();
-        let mut file = File::open(filename)
-            .map_err(|e| ShellError::ReadingFile(e.to_string(), call.span()))?;
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr = profile.resolve_write_addr(stack, engine_state, None);
+        let addr = validate_iox_addr(&addr, call.head)?;
 
-        let mut lp_data = String::new();
-        let _ = file.read_to_string(&mut lp_data);
+        let token: Option<String> = call.get_flag(engine_state, stack, "token")?;
+        let token = profile.resolve_token(stack, engine_state, token);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
 
-        let nol_result = tokio_block_writefile(&dbname, &lp_data);
+        let (summary, failures) = write_files(
+            &files,
+            call.has_flag("fail-fast"),
+            call.has_flag("keep-crlf"),
+            call.head,
+            |lp_data| {
+                write_unless_dry_run(
+                    lp_data,
+                    &dbname,
+                    call.has_flag("dry-run"),
+                    call.head,
+                    || {
+                        let connector =
+                            iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+                                .with_keepalive(keepalive);
+                        let lp_nanos =
+                            timestamps_to_nanos(&lp_data.item, precision).map_err(|e| {
+                                ShellError::UnsupportedInput(
+                                    format!("--precision: {}", e),
+                                    call.head,
+                                )
+                            })?;
+                        let batches = lp_batches(&lp_nanos, batch_size);
+                        let lines = tokio_block_writefile(
+                            connector,
+                            &addr,
+                            &dbname,
+                            &batches,
+                            call.has_flag("quiet"),
+                            call.head,
+                        )?;
 
-        println!("{:?}", nol_result);
+                        Ok(Value::Record {
+                            cols: vec!["lines".to_string()],
+                            vals: vec![Value::Int {
+                                val: lines as i64,
+                                span: call.head,
+                            }],
+                            span: call.head,
+                        })
+                    },
+                )
+            },
+        )?;
+
+        for failure in failures {
+            eprintln!("ioxwritefile: {}", failure);
+        }
 
-        Ok(PipelineData::Value(
-            Value::Nothing { span: call.head },
-            None,
-        ))
+        Ok(PipelineData::Value(summary, None))
     }
 
     fn examples(&self) -> Vec<Example> {
@@ -82,33 +192,524 @@ impl Command for Ioxwritefile {
                 example: r#"ioxwritefile ./ioxnotes/lineproto/popnm.lp"#,
                 result: None,
             },
+            Example {
+                description: "Lint a line protocol file in CI without writing it",
+                example: r#"ioxwritefile --dry-run ./ioxnotes/lineproto/popnm.lp"#,
+                result: None,
+            },
+            Example {
+                description: "Write every line protocol file of a directory",
+                example: r#"ioxwritefile ./ioxnotes/lineproto/*.lp"#,
+                result: None,
+            },
+            Example {
+                description: "Write a large line protocol file 10000 lines at a time",
+                example: r#"ioxwritefile --batch-size 10000 ./ioxnotes/lineproto/popnm.lp"#,
+                result: None,
+            },
         ]
     }
 }
 
-pub fn tokio_block_writefile(dbname: &String, lp_data: &String) -> Result<String, std::io::Error> {
-    use influxdb_iox_client::{connection::Builder, write::Client};
+/// Expands the file names and globs into the files to write, in order
+///
+/// Relative paths are taken from the current directory. Every argument has
+/// to match at least one file.
+pub fn expand_lp_files(
+    patterns: &[Spanned<String>],
+    cwd: &Path,
+) -> Result<Vec<Spanned<PathBuf>>, ShellError> {
+    let mut files = vec![];
+
+    for pattern in patterns {
+        let path = cwd.join(&pattern.item);
+        let matches = nu_glob::glob(&path.to_string_lossy()).map_err(|e| {
+            ShellError::GenericError(
+                e.to_string(),
+                "invalid pattern".to_string(),
+                Some(pattern.span),
+                None,
+                Vec::new(),
+            )
+        })?;
+
+        let matched: Vec<PathBuf> = matches
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect();
+
+        if matched.is_empty() {
+            return Err(ShellError::GenericError(
+                format!("No line protocol files match {}", pattern.item),
+                "no matches found".into(),
+                Some(pattern.span),
+                None,
+                Vec::new(),
+            ));
+        }
+
+        files.extend(matched.into_iter().map(|item| Spanned {
+            item,
+            span: pattern.span,
+        }));
+    }
+
+    Ok(files)
+}
+
+/// Reads and writes each file, returning the lines per file and the total
+///
+/// The write returns a record with a lines column, it is prefixed with the
+/// file it came from. A file that can't be read or written is skipped and
+/// its error returned in the list of failures, unless fail_fast is set and
+/// it aborts the rest. Windows line endings are stripped to \n unless
+/// keep_crlf is set.
+pub fn write_files<F>(
+    files: &[Spanned<PathBuf>],
+    fail_fast: bool,
+    keep_crlf: bool,
+    span: Span,
+    mut write: F,
+) -> Result<(Value, Vec<String>), ShellError>
+where
+    F: FnMut(&Spanned<String>) -> Result<Value, ShellError>,
+{
+    let mut rows = vec![];
+    let mut failures = vec![];
+    let mut total_lines = 0;
+
+    for file in files {
+        let written = std::fs::read_to_string(&file.item)
+            .map_err(|e| ShellError::ReadingFile(e.to_string(), file.span))
+            .and_then(|lp_data| {
+                write(&Spanned {
+                    item: if keep_crlf {
+                        lp_data
+                    } else {
+                        strip_crlf(lp_data)
+                    },
+                    span: file.span,
+                })
+            });
+
+        let (mut cols, mut vals) = match written {
+            Ok(Value::Record { cols, vals, .. }) => (cols, vals),
+            Ok(_) => (vec![], vec![]),
+            Err(error) if fail_fast => return Err(error),
+            Err(ShellError::GenericError(_, msg, ..)) => {
+                failures.push(format!("file '{}' failed: {}", file.item.display(), msg));
+                continue;
+            }
+            Err(error) => {
+                failures.push(format!("file '{}' failed: {}", file.item.display(), error));
+                continue;
+            }
+        };
+
+        if let Some(Value::Int { val, .. }) = cols
+            .iter()
+            .position(|col| col == "lines")
+            .map(|index| &vals[index])
+        {
+            total_lines += val;
+        }
+
+        cols.insert(0, "file".to_string());
+        vals.insert(
+            0,
+            Value::String {
+                val: file.item.display().to_string(),
+                span,
+            },
+        );
+        rows.push(Value::Record { cols, vals, span });
+    }
+
+    let summary = Value::Record {
+        cols: vec!["files".to_string(), "total_lines".to_string()],
+        vals: vec![
+            Value::List { vals: rows, span },
+            Value::Int {
+                val: total_lines,
+                span,
+            },
+        ],
+        span,
+    };
+
+    Ok((summary, failures))
+}
+
+/// Turns the \r\n line endings of a file saved on windows into \n
+///
+/// A \r left at the end of a line would end up in the last field value or
+/// the timestamp, which servers reject.
+pub fn strip_crlf(lp_data: String) -> String {
+    if !lp_data.contains('\r') {
+        return lp_data;
+    }
+
+    lp_data
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reads a --batch-size flag value, a batch needs at least one line
+pub fn batch_size_from_flag(batch_size: Option<Spanned<i64>>) -> Result<Option<usize>, ShellError> {
+    match batch_size {
+        None => Ok(None),
+        Some(Spanned { item, span }) if item < 1 => Err(ShellError::UnsupportedInput(
+            format!("--batch-size must be at least 1, got {}", item),
+            span,
+        )),
+        Some(Spanned { item, .. }) => Ok(Some(item as usize)),
+    }
+}
+
+/// Splits line protocol into chunks of at most batch_size lines
+///
+/// Without a batch size the data is sent as is in a single chunk. Blank
+/// lines carry no points, so they don't count towards a chunk.
+pub fn lp_batches(lp_data: &str, batch_size: Option<usize>) -> Vec<String> {
+    let batch_size = match batch_size {
+        None => return vec![lp_data.to_string()],
+        Some(batch_size) => batch_size,
+    };
+
+    let lines: Vec<&str> = lp_data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+
+    lines
+        .chunks(batch_size)
+        .map(|chunk| chunk.join("\n"))
+        .collect()
+}
+
+/// How often the progress of a batched write is reported at most
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
-    let num_threads: Option<usize> = None;
-    let tokio_runtime = get_runtime(num_threads)?;
+/// Reports the lines committed so far, at most once per interval
+///
+/// The last batch is always reported, so the final count is never left out.
+/// Without an output nothing is reported.
+pub struct WriteProgress<W: Write> {
+    out: Option<W>,
+    total_lines: usize,
+    interval: Duration,
+    last_report: Option<Instant>,
+}
 
-    let nol_result = tokio_runtime.block_on(async move {
-        let connection = Builder::default()
-            .build("http://127.0.0.1:8081")
-            .await
-            .expect("client should be valid");
+impl<W: Write> WriteProgress<W> {
+    pub fn new(out: Option<W>, total_lines: usize, interval: Duration) -> Self {
+        Self {
+            out,
+            total_lines,
+            interval,
+            last_report: None,
+        }
+    }
+
+    pub fn report(&mut self, committed: usize, now: Instant) {
+        let out = match &mut self.out {
+            Some(out) => out,
+            None => return,
+        };
+
+        let due = self.last_report.map_or(true, |last| {
+            now.saturating_duration_since(last) >= self.interval
+        });
+        if !due && committed < self.total_lines {
+            return;
+        }
+
+        let _ = writeln!(out, "wrote {}/{} lines", committed, self.total_lines);
+        self.last_report = Some(now);
+    }
+}
+
+/// Writes the batches one after the other, returning the lines written
+///
+/// When there is more than one batch the progress is reported on stderr,
+/// unless quiet is set. A failing batch stops the write and the error says
+/// how many lines made it.
+pub fn tokio_block_writefile(
+    connector: IoxConnector,
+    addr: &str,
+    dbname: &String,
+    batches: &[String],
+    quiet: bool,
+    span: Span,
+) -> Result<usize, ShellError> {
+    use influxdb_iox_client::write::Client;
+
+    let nol_result = block_on_iox(async move {
+        let connection = connector.connect(addr).await?;
 
         let mut client = Client::new(connection);
+        let mut committed = 0;
+        let mut progress = WriteProgress::new(
+            (!quiet && batches.len() > 1).then(std::io::stderr),
+            batches.iter().map(|batch| batch.lines().count()).sum(),
+            PROGRESS_INTERVAL,
+        );
+
+        for (number, batch) in batches.iter().enumerate() {
+            let numoflines = client
+                .write_lp(dbname.to_string(), batch.to_string(), 0)
+                .await
+                .map_err(|e| {
+                    ShellError::GenericError(
+                        format!(
+                            "Write to Iox failed after {} lines were committed",
+                            committed
+                        ),
+                        e.to_string(),
+                        Some(span),
+                        Some(format!(
+                            "Batch {} of {} was rejected",
+                            number + 1,
+                            batches.len()
+                        )),
+                        Vec::new(),
+                    )
+                })?;
+
+            committed += numoflines;
+            progress.report(committed, Instant::now());
+        }
+
+        Ok::<_, ShellError>(committed)
+    })
+    .map_err(|e| ShellError::IOError(e.to_string()))?;
 
-        let numoflines = client
-            .write_lp(dbname.to_string(), lp_data.to_string(), 0)
-            .await;
+    nol_result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        match numoflines {
-            Ok(res) => res.to_string(),
-            Err(error) => error.to_string(),
+    fn spanned(item: i64) -> Spanned<i64> {
+        Spanned {
+            item,
+            span: Span::test_data(),
         }
-    });
+    }
+
+    #[test]
+    fn writes_everything_at_once_by_default() {
+        let lp = "cpu,region=la user=955 222522\ncpu,region=pa user=9599 222522\n";
+
+        assert_eq!(lp_batches(lp, None), vec![lp.to_string()]);
+    }
+
+    #[test]
+    fn splits_a_file_larger_than_the_batch_size() {
+        let path = std::env::temp_dir().join("nu_iox_writefile_batches.lp");
+        let lp: String = (0..5)
+            .map(|i| format!("cpu,region=la user={} {}\n", i, 222522 + i))
+            .collect();
+        std::fs::write(&path, &lp).unwrap();
+
+        let lp_data = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let batches = lp_batches(&lp_data, Some(2));
 
-    Ok(nol_result)
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].lines().count(), 2);
+        assert_eq!(batches[2], "cpu,region=la user=4 222526");
+    }
+
+    #[test]
+    fn blank_lines_do_not_count_towards_a_batch() {
+        let lp = "cpu user=1 1\n\ncpu user=2 2\n\n";
+
+        assert_eq!(lp_batches(lp, Some(2)), vec!["cpu user=1 1\ncpu user=2 2"]);
+    }
+
+    fn lines_record(lp_data: &Spanned<String>) -> Result<Value, ShellError> {
+        Ok(Value::Record {
+            cols: vec!["lines".to_string()],
+            vals: vec![Value::Int {
+                val: crate::iox::count_points_lines(&lp_data.item) as i64,
+                span: Span::test_data(),
+            }],
+            span: Span::test_data(),
+        })
+    }
+
+    fn temp_lp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cpu.lp"), "cpu user=1 1\ncpu user=2 2\n").unwrap();
+        std::fs::write(dir.join("mem.lp"), "mem free=1 1\n").unwrap();
+        dir
+    }
+
+    fn glob_arg(item: &str) -> Spanned<String> {
+        Spanned {
+            item: item.to_string(),
+            span: Span::test_data(),
+        }
+    }
+
+    #[test]
+    fn writes_every_file_of_a_glob_with_a_total() {
+        let dir = temp_lp_dir("nu_iox_writefile_glob");
+
+        let files = expand_lp_files(&[glob_arg("*.lp")], &dir).unwrap();
+        let mut written = vec![];
+        let (summary, failures) = write_files(&files, false, false, Span::test_data(), |lp_data| {
+            written.push(lp_data.item.clone());
+            lines_record(lp_data)
+        })
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(failures.is_empty());
+        assert_eq!(written.len(), 2);
+        assert_eq!(
+            summary.get_data_by_key("total_lines"),
+            Some(Value::Int {
+                val: 3,
+                span: Span::test_data()
+            })
+        );
+
+        let rows = match summary.get_data_by_key("files") {
+            Some(Value::List { vals, .. }) => vals,
+            other => panic!("expected the files, got {:?}", other),
+        };
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0].get_data_by_key("file"),
+            Some(Value::test_string(dir.join("cpu.lp").display().to_string()))
+        );
+        assert_eq!(
+            rows[1].get_data_by_key("lines"),
+            Some(Value::Int {
+                val: 1,
+                span: Span::test_data()
+            })
+        );
+    }
+
+    #[test]
+    fn failing_file_is_skipped_unless_fail_fast() {
+        let dir = temp_lp_dir("nu_iox_writefile_failure");
+        let files = expand_lp_files(&[glob_arg("cpu.lp"), glob_arg("mem.lp")], &dir).unwrap();
+
+        let write = |lp_data: &Spanned<String>| {
+            if lp_data.item.starts_with("cpu") {
+                Err(ShellError::GenericError(
+                    "Write to Iox failed".into(),
+                    "rejected".into(),
+                    None,
+                    None,
+                    Vec::new(),
+                ))
+            } else {
+                lines_record(lp_data)
+            }
+        };
+
+        let (summary, failures) =
+            write_files(&files, false, false, Span::test_data(), write).unwrap();
+        let fail_fast = write_files(&files, true, false, Span::test_data(), write);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains("cpu.lp"));
+        assert_eq!(
+            summary.get_data_by_key("total_lines"),
+            Some(Value::Int {
+                val: 1,
+                span: Span::test_data()
+            })
+        );
+        assert!(fail_fast.is_err());
+    }
+
+    #[test]
+    fn crlf_line_endings_are_stripped_unless_kept() {
+        let dir = std::env::temp_dir().join("nu_iox_writefile_crlf");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cpu.lp"), "cpu user=1 1\r\ncpu user=2 2\r\n").unwrap();
+        let files = expand_lp_files(&[glob_arg("cpu.lp")], &dir).unwrap();
+
+        let written = |keep_crlf| {
+            let mut written = vec![];
+            write_files(&files, false, keep_crlf, Span::test_data(), |lp_data| {
+                written.push(lp_data.item.clone());
+                lines_record(lp_data)
+            })
+            .unwrap();
+            written
+        };
+        let stripped = written(false);
+        let kept = written(true);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(stripped, vec!["cpu user=1 1\ncpu user=2 2\n"]);
+        assert_eq!(kept, vec!["cpu user=1 1\r\ncpu user=2 2\r\n"]);
+        assert_eq!(strip_crlf("cpu user=1 1".into()), "cpu user=1 1");
+    }
+
+    #[test]
+    fn glob_without_matches_is_an_error() {
+        let dir = temp_lp_dir("nu_iox_writefile_no_match");
+
+        let result = expand_lp_files(&[glob_arg("*.csv")], &dir);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(matches!(result, Err(ShellError::GenericError(..))));
+    }
+
+    fn progress_lines(report: impl FnOnce(&mut WriteProgress<&mut Vec<u8>>)) -> Vec<String> {
+        let mut stderr = vec![];
+        let mut progress = WriteProgress::new(Some(&mut stderr), 200000, PROGRESS_INTERVAL);
+        report(&mut progress);
+        drop(progress);
+
+        String::from_utf8(stderr)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn progress_is_throttled_but_ends_with_the_total() {
+        let start = Instant::now();
+
+        let lines = progress_lines(|progress| {
+            progress.report(50000, start);
+            progress.report(100000, start + Duration::from_millis(10));
+            progress.report(150000, start + PROGRESS_INTERVAL);
+            progress.report(200000, start + PROGRESS_INTERVAL);
+        });
+
+        assert_eq!(
+            lines,
+            vec![
+                "wrote 50000/200000 lines",
+                "wrote 150000/200000 lines",
+                "wrote 200000/200000 lines",
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_size_must_be_positive() {
+        assert_eq!(batch_size_from_flag(None).unwrap(), None);
+        assert_eq!(batch_size_from_flag(Some(spanned(100))).unwrap(), Some(100));
+        assert!(batch_size_from_flag(Some(spanned(0))).is_err());
+    }
 }
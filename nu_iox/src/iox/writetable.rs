@@ -0,0 +1,545 @@
+use super::dbnames::dbname_shape;
+use super::lineproto::{precision_from_flag, write_unless_dry_run, Precision};
+use super::util::{
+    iox_connector, resolve_iox_dbname, resolve_iox_keepalive, resolve_iox_profile,
+    resolve_iox_tls_ca, validate_iox_addr,
+};
+use super::write::tokio_block_write;
+use nu_engine::CallExt;
+use nu_protocol::ast::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, PipelineData, ShellError, Signature, Span, Spanned, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct Ioxwritetable;
+
+impl Command for Ioxwritetable {
+    fn name(&self) -> &str {
+        "ioxwritetable"
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build("ioxwritetable")
+            .required_named(
+                "measurement",
+                SyntaxShape::String,
+                "measurement the rows are written to",
+                Some('m'),
+            )
+            .named(
+                "tags",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "columns written as tags",
+                None,
+            )
+            .named(
+                "fields",
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                "columns written as fields, defaults to every column that is not a tag or the time",
+                None,
+            )
+            .named(
+                "time-col",
+                SyntaxShape::String,
+                "column holding the timestamp of each row, a date or an int in --precision units",
+                None,
+            )
+            .named(
+                "dbname",
+                dbname_shape(),
+                "name of the database to write to",
+                Some('d'),
+            )
+            .named(
+                "precision",
+                SyntaxShape::String,
+                "unit of the timestamps: ns (default), us, ms or s",
+                Some('p'),
+            )
+            .switch(
+                "dry-run",
+                "build the line protocol and summarize it without writing anything",
+                None,
+            )
+            .named(
+                "profile",
+                SyntaxShape::String,
+                "connection profile to take the address and token from, see IOX_PROFILE_<NAME>_ADDR",
+                None,
+            )
+            .category(Category::Filters)
+    }
+
+    fn usage(&self) -> &str {
+        "Write a table of records to the Iox Database."
+    }
+
+    fn extra_usage(&self) -> &str {
+        "Every row becomes a line of line protocol. Ints, floats, strings and booleans keep their type as fields, filesizes and durations are written as ints. A null or missing value leaves its tag or field out of the line, and a row left without any field is an error."
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let measurement: Option<String> = call.get_flag(engine_state, stack, "measurement")?;
+        let layout = TableLayout {
+            measurement: measurement
+                .ok_or_else(|| ShellError::MissingParameter("measurement".into(), call.head))?,
+            tags: call
+                .get_flag(engine_state, stack, "tags")?
+                .unwrap_or_default(),
+            fields: call.get_flag(engine_state, stack, "fields")?,
+            time_col: call.get_flag(engine_state, stack, "time-col")?,
+            precision: precision_from_flag(call.get_flag(engine_state, stack, "precision")?)?,
+        };
+        let db: Option<String> = call.get_flag(engine_state, stack, "dbname")?;
+        let dry_run = call.has_flag("dry-run");
+
+        let rows: Vec<Value> = input.into_iter().collect();
+        if rows.is_empty() {
+            return Err(ShellError::UnsupportedInput(
+                "No rows to write: pipe in a table".into(),
+                call.head,
+            ));
+        }
+
+        let lp_data = Spanned {
+            item: table_to_line_protocol(&rows, &layout, call.head)?,
+            span: call.head,
+        };
+
+        let dbname = resolve_iox_dbname(stack, engine_state, db, call.head)?;
+
+        let profile = resolve_iox_profile(
+            stack,
+            engine_state,
+            call.get_flag(engine_state, stack, "profile")?,
+        )?;
+        let addr = profile.resolve_write_addr(stack, engine_state, None);
+        let addr = validate_iox_addr(&addr, call.head)?;
+
+        let token = profile.resolve_token(stack, engine_state, None);
+        let tls_ca = resolve_iox_tls_ca(stack, engine_state, None, call.head);
+        let keepalive = resolve_iox_keepalive(stack, engine_state, call.head)?;
+
+        let summary = write_unless_dry_run(&lp_data, &dbname, dry_run, call.head, || {
+            let connector = iox_connector(&addr, token.as_deref(), tls_ca.as_ref(), call.head)?
+                .with_keepalive(keepalive);
+
+            tokio_block_write(
+                connector,
+                &addr,
+                &dbname,
+                &lp_data,
+                layout.precision,
+                call.head,
+            )
+        })?;
+
+        Ok(PipelineData::Value(summary, None))
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Write a table to the cpu measurement of the bananas db",
+                example: r#"[[region user]; [la 955] [pa 9599]] | ioxwritetable -d bananas -m cpu --tags [region]"#,
+                result: None,
+            },
+            Example {
+                description: "Copy the results of a query into another database",
+                example: r#"ioxsql -d bananas "select region, user, time from cpu" | ioxwritetable -d apples -m cpu --tags [region] --time-col time"#,
+                result: None,
+            },
+            Example {
+                description: "See the line protocol a table would be written as",
+                example: r#"open cpu.csv | ioxwritetable -m cpu --tags [region] --dry-run"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+/// How the columns of a table map onto line protocol
+#[derive(Debug, Clone, Default)]
+pub struct TableLayout {
+    pub measurement: String,
+    pub tags: Vec<String>,
+    /// None makes every column that is not a tag or the time a field
+    pub fields: Option<Vec<String>>,
+    pub time_col: Option<String>,
+    pub precision: Precision,
+}
+
+/// Builds the line protocol for the rows, one line each
+pub fn table_to_line_protocol(
+    rows: &[Value],
+    layout: &TableLayout,
+    span: Span,
+) -> Result<String, ShellError> {
+    let mut lp_data = String::new();
+
+    for (index, row) in rows.iter().enumerate() {
+        match row {
+            Value::Record { cols, vals, .. } => {
+                lp_data.push_str(&row_to_line(cols, vals, layout, index + 1, span)?);
+                lp_data.push('\n');
+            }
+            other => {
+                return Err(ShellError::UnsupportedInput(
+                    format!(
+                        "row {} is a {}, ioxwritetable takes a table of records",
+                        index + 1,
+                        other.get_type()
+                    ),
+                    other.span().unwrap_or(span),
+                ))
+            }
+        }
+    }
+
+    Ok(lp_data)
+}
+
+fn row_to_line(
+    cols: &[String],
+    vals: &[Value],
+    layout: &TableLayout,
+    row: usize,
+    span: Span,
+) -> Result<String, ShellError> {
+    let column = |name: &str| {
+        cols.iter()
+            .position(|col| col == name)
+            .map(|index| &vals[index])
+            .filter(|value| !matches!(value, Value::Nothing { .. }))
+    };
+
+    let mut line = escape_lp(&layout.measurement, &[',', ' '], span)?;
+
+    for tag in &layout.tags {
+        if let Some(value) = column(tag) {
+            let value_span = value.span().ok();
+            let value = tag_value(value, span)?;
+            // line protocol has no empty tag values, an empty tag is no tag
+            if !value.is_empty() {
+                line.push(',');
+                line.push_str(&escape_lp(tag, &[',', '=', ' '], span)?);
+                line.push('=');
+                line.push_str(&escape_lp(
+                    &value,
+                    &[',', '=', ' '],
+                    value_span.unwrap_or(span),
+                )?);
+            }
+        }
+    }
+
+    let field_names: Vec<&String> = match &layout.fields {
+        Some(fields) => fields.iter().collect(),
+        None => cols
+            .iter()
+            .filter(|col| {
+                !layout.tags.contains(col) && layout.time_col.as_deref() != Some(col.as_str())
+            })
+            .collect(),
+    };
+
+    let mut fields = vec![];
+    for name in field_names {
+        if let Some(value) = column(name) {
+            fields.push(format!(
+                "{}={}",
+                escape_lp(name, &[',', '=', ' '], span)?,
+                field_value(value, span)?
+            ));
+        }
+    }
+
+    if fields.is_empty() {
+        return Err(ShellError::UnsupportedInput(
+            format!(
+                "row {} has no field values, line protocol needs at least one",
+                row
+            ),
+            span,
+        ));
+    }
+
+    line.push(' ');
+    line.push_str(&fields.join(","));
+
+    if let Some(value) = layout.time_col.as_deref().and_then(column) {
+        line.push(' ');
+        line.push_str(&timestamp(value, layout.precision, span)?.to_string());
+    }
+
+    Ok(line)
+}
+
+/// Escapes the characters that end a part of a line with a backslash
+///
+/// Line protocol has no escape for a line break, text with one is an error
+/// rather than a point split across two lines.
+fn escape_lp(text: &str, special: &[char], span: Span) -> Result<String, ShellError> {
+    if text.contains(|c: char| c == '\n' || c == '\r') {
+        return Err(ShellError::UnsupportedInput(
+            format!("{:?} has a line break, line protocol can't write one", text),
+            span,
+        ));
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    Ok(escaped)
+}
+
+fn tag_value(value: &Value, span: Span) -> Result<String, ShellError> {
+    match value {
+        Value::String { val, .. } => Ok(val.clone()),
+        Value::Int { val, .. } => Ok(val.to_string()),
+        Value::Float { val, .. } => Ok(val.to_string()),
+        Value::Bool { val, .. } => Ok(val.to_string()),
+        other => Err(ShellError::UnsupportedInput(
+            format!("a {} can't be written as a tag", other.get_type()),
+            other.span().unwrap_or(span),
+        )),
+    }
+}
+
+fn field_value(value: &Value, span: Span) -> Result<String, ShellError> {
+    match value {
+        Value::Int { val, .. } => Ok(format!("{}i", val)),
+        Value::Filesize { val, .. } => Ok(format!("{}i", val)),
+        Value::Duration { val, .. } => Ok(format!("{}i", val)),
+        Value::Float { val, span } if !val.is_finite() => Err(ShellError::UnsupportedInput(
+            format!(
+                "{} can't be written as a field, line protocol floats are finite",
+                val
+            ),
+            *span,
+        )),
+        Value::Float { val, .. } => Ok(val.to_string()),
+        Value::Bool { val, .. } => Ok(val.to_string()),
+        Value::String { val, span } => Ok(format!("\"{}\"", escape_lp(val, &['"', '\\'], *span)?)),
+        other => Err(ShellError::UnsupportedInput(
+            format!("a {} can't be written as a field", other.get_type()),
+            other.span().unwrap_or(span),
+        )),
+    }
+}
+
+/// The timestamp in precision units, a date is converted, an int taken as is
+///
+/// Only dates that fit in i64 nanoseconds, about 1677 to 2262, are written.
+fn timestamp(value: &Value, precision: Precision, span: Span) -> Result<i64, ShellError> {
+    match value {
+        Value::Date { val, span } => val
+            .timestamp()
+            .checked_mul(1_000_000_000)
+            .and_then(|nanos| nanos.checked_add(val.timestamp_subsec_nanos() as i64))
+            .map(|nanos| nanos / precision.nanos_per_unit())
+            .ok_or_else(|| {
+                ShellError::UnsupportedInput(
+                    format!(
+                        "{} is out of range, line protocol times are nanoseconds between 1677 and 2262",
+                        val.to_rfc3339()
+                    ),
+                    *span,
+                )
+            }),
+        Value::Int { val, .. } => Ok(*val),
+        other => Err(ShellError::UnsupportedInput(
+            format!(
+                "the time column holds a {}, expected a date or an int",
+                other.get_type()
+            ),
+            other.span().unwrap_or(span),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{DateTime, FixedOffset};
+
+    fn row(cols: &[&str], vals: Vec<Value>) -> Value {
+        Value::Record {
+            cols: cols.iter().map(|col| col.to_string()).collect(),
+            vals,
+            span: Span::test_data(),
+        }
+    }
+
+    fn layout(tags: &[&str], time_col: Option<&str>) -> TableLayout {
+        TableLayout {
+            measurement: "cpu".to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            fields: None,
+            time_col: time_col.map(String::from),
+            precision: Precision::default(),
+        }
+    }
+
+    #[test]
+    fn table_is_written_as_line_protocol() {
+        let span = Span::test_data();
+        let time: DateTime<FixedOffset> = "2022-08-01T00:00:00+00:00".parse().unwrap();
+        let cols = ["region", "user", "load", "idle", "host", "time"];
+        let rows = vec![
+            row(
+                &cols,
+                vec![
+                    Value::test_string("la"),
+                    Value::test_int(955),
+                    Value::Float { val: 0.5, span },
+                    Value::Bool { val: true, span },
+                    Value::test_string("a \"quoted\" name"),
+                    Value::Date { val: time, span },
+                ],
+            ),
+            row(
+                &cols,
+                vec![
+                    Value::test_string("pa"),
+                    Value::test_int(9599),
+                    Value::Nothing { span },
+                    Value::Bool { val: false, span },
+                    Value::test_string("b"),
+                    Value::Nothing { span },
+                ],
+            ),
+        ];
+
+        let lp = table_to_line_protocol(&rows, &layout(&["region"], Some("time")), span).unwrap();
+
+        assert_eq!(
+            lp,
+            "cpu,region=la user=955i,load=0.5,idle=true,host=\"a \\\"quoted\\\" name\" 1659312000000000000\n\
+             cpu,region=pa user=9599i,idle=false,host=\"b\"\n"
+        );
+    }
+
+    #[test]
+    fn names_and_tag_values_are_escaped() {
+        let span = Span::test_data();
+        let rows = vec![row(
+            &["data center", "cpu load"],
+            vec![Value::test_string("us,west"), Value::test_int(1)],
+        )];
+        let layout = TableLayout {
+            measurement: "host stats".to_string(),
+            ..layout(&["data center"], None)
+        };
+
+        let lp = table_to_line_protocol(&rows, &layout, span).unwrap();
+
+        assert_eq!(lp, "host\\ stats,data\\ center=us\\,west cpu\\ load=1i\n");
+    }
+
+    #[test]
+    fn dates_follow_the_precision() {
+        let span = Span::test_data();
+        let time: DateTime<FixedOffset> = "2022-08-01T00:00:00+00:00".parse().unwrap();
+        let rows = vec![row(
+            &["user", "time"],
+            vec![Value::test_int(1), Value::Date { val: time, span }],
+        )];
+        let layout = TableLayout {
+            precision: Precision::Seconds,
+            ..layout(&[], Some("time"))
+        };
+
+        let lp = table_to_line_protocol(&rows, &layout, span).unwrap();
+
+        assert_eq!(lp, "cpu user=1i 1659312000\n");
+    }
+
+    #[test]
+    fn dates_out_of_the_nanosecond_range_are_an_error() {
+        let span = Span::test_data();
+        let time: DateTime<FixedOffset> = "1500-01-01T00:00:00+00:00".parse().unwrap();
+        let rows = vec![row(
+            &["user", "time"],
+            vec![Value::test_int(1), Value::Date { val: time, span }],
+        )];
+
+        match table_to_line_protocol(&rows, &layout(&[], Some("time")), span) {
+            Err(ShellError::UnsupportedInput(msg, _)) => assert!(msg.contains("out of range")),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn line_breaks_are_an_error_not_a_new_line() {
+        let span = Span::test_data();
+        let write = |region: &str, note: &str| {
+            let rows = vec![row(
+                &["region", "note"],
+                vec![Value::test_string(region), Value::test_string(note)],
+            )];
+            table_to_line_protocol(&rows, &layout(&["region"], None), span)
+        };
+
+        assert!(write("la", "ok").is_ok());
+        for (region, note) in [
+            ("la\ncpu,host=evil user=1i", "ok"),
+            ("la", "first\nsecond"),
+            ("la\r", "ok"),
+        ] {
+            match write(region, note) {
+                Err(ShellError::UnsupportedInput(msg, _)) => assert!(msg.contains("line break")),
+                other => panic!("expected an error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_listed_fields_are_written() {
+        let rows = vec![row(
+            &["user", "system"],
+            vec![Value::test_int(1), Value::test_int(2)],
+        )];
+        let layout = TableLayout {
+            fields: Some(vec!["system".to_string()]),
+            ..layout(&[], None)
+        };
+
+        let lp = table_to_line_protocol(&rows, &layout, Span::test_data()).unwrap();
+
+        assert_eq!(lp, "cpu system=2i\n");
+    }
+
+    #[test]
+    fn row_without_fields_is_an_error() {
+        let span = Span::test_data();
+        let rows = vec![row(
+            &["region", "user"],
+            vec![Value::test_string("la"), Value::Nothing { span }],
+        )];
+
+        match table_to_line_protocol(&rows, &layout(&["region"], None), span) {
+            Err(ShellError::UnsupportedInput(msg, _)) => assert!(msg.contains("row 1")),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn non_record_rows_are_an_error() {
+        let rows = vec![Value::test_int(1)];
+
+        assert!(table_to_line_protocol(&rows, &layout(&[], None), Span::test_data()).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use arrow::record_batch::RecordBatch;
+use nu_command::{
+    block_on_iox, iox_connector, Compression, Nuclient, QueryProtocol, RemoteDatabase,
+};
+use nu_protocol::Span;
+use std::time::{Duration, Instant};
+
+fn run_with(protocol: QueryProtocol, sql: &str) -> Vec<RecordBatch> {
+    run_compressed(protocol, Compression::Gzip, sql).0
+}
+
+fn run_compressed(
+    protocol: QueryProtocol,
+    compression: Compression,
+    sql: &str,
+) -> (Vec<RecordBatch>, Duration) {
+    let addr =
+        std::env::var("IOX_FLIGHTSQL_TEST_ADDR").expect("IOX_FLIGHTSQL_TEST_ADDR should be set");
+    let dbname = std::env::var("IOX_FLIGHTSQL_TEST_DBNAME")
+        .expect("IOX_FLIGHTSQL_TEST_DBNAME should be set");
+    let connector = iox_connector(&addr, None, None, Span::test_data())
+        .unwrap()
+        .with_compression(compression);
+    let sql = sql.to_string();
+
+    block_on_iox(async move {
+        let mut client = Nuclient::connect(&addr, connector).await.unwrap();
+        client.use_remote_database(RemoteDatabase::new(dbname, None));
+        client.set_query_protocol(protocol);
+        let started = Instant::now();
+        let batches = client.run_sql_batches(sql).await;
+        batches.map(|batches| (batches, started.elapsed()))
+    })
+    .unwrap()
+    .expect("query should succeed")
+}
+
+// Run with `cargo test --features iox-flightsql-tests` against an Iox server
+// with Flight SQL enabled, e.g. IOX_FLIGHTSQL_TEST_ADDR=http://localhost:8082
+// IOX_FLIGHTSQL_TEST_DBNAME=bananas
+#[test]
+fn flight_sql_returns_the_same_rows_as_the_legacy_protocol() {
+    let sql = "select 1 as one, 'a' as letter";
+
+    let legacy = run_with(QueryProtocol::Legacy, sql);
+    let flight_sql = run_with(QueryProtocol::FlightSql, sql);
+
+    assert_eq!(
+        arrow::util::pretty::pretty_format_batches(&flight_sql)
+            .unwrap()
+            .to_string(),
+        arrow::util::pretty::pretty_format_batches(&legacy)
+            .unwrap()
+            .to_string()
+    );
+}
+
+// Compares reading a large result gzipped and uncompressed, set
+// IOX_FLIGHTSQL_TEST_LARGE_SQL to a query returning a wide table, e.g.
+// "select * from cpu", and run with --nocapture to see the timings
+#[test]
+fn gzipped_results_match_the_uncompressed_ones() {
+    let sql = std::env::var("IOX_FLIGHTSQL_TEST_LARGE_SQL")
+        .expect("IOX_FLIGHTSQL_TEST_LARGE_SQL should be set");
+
+    let (plain, plain_elapsed) = run_compressed(QueryProtocol::FlightSql, Compression::None, &sql);
+    let (gzipped, gzipped_elapsed) =
+        run_compressed(QueryProtocol::FlightSql, Compression::Gzip, &sql);
+
+    let rows: usize = plain.iter().map(|b| b.num_rows()).sum();
+    eprintln!(
+        "{} rows: none {:?}, gzip {:?}",
+        rows, plain_elapsed, gzipped_elapsed
+    );
+    assert_eq!(gzipped, plain);
+}
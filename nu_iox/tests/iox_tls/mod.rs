@@ -0,0 +1,28 @@
+use influxdb_iox_client::namespace::Client;
+use nu_command::{block_on_iox, iox_connector};
+use nu_protocol::{Span, Spanned};
+
+// Run with `cargo test --features iox-tls-tests` against an Iox server
+// terminated by TLS, e.g. IOX_TLS_TEST_ADDR=https://localhost:8443
+// IOX_TLS_TEST_CA=./certs/ca.pem
+#[test]
+fn lists_namespaces_over_tls() {
+    let addr = std::env::var("IOX_TLS_TEST_ADDR").expect("IOX_TLS_TEST_ADDR should be set");
+    let tls_ca = Spanned {
+        item: std::env::var("IOX_TLS_TEST_CA").expect("IOX_TLS_TEST_CA should be set"),
+        span: Span::test_data(),
+    };
+
+    let connector = iox_connector(&addr, None, Some(&tls_ca), Span::test_data()).unwrap();
+
+    let namespaces = block_on_iox(async move {
+        let connection = connector
+            .connect(&addr)
+            .await
+            .expect("TLS handshake should succeed");
+        Client::new(connection).get_namespaces().await
+    })
+    .unwrap();
+
+    assert!(namespaces.is_ok());
+}
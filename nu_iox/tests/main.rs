@@ -4,6 +4,10 @@ use quickcheck_macros::quickcheck;
 
 mod commands;
 mod format_conversions;
+#[cfg(feature = "iox-flightsql-tests")]
+mod iox_flightsql;
+#[cfg(feature = "iox-tls-tests")]
+mod iox_tls;
 
 // use nu_engine::EvaluationContext;
 